@@ -17,6 +17,16 @@ if ! command -v cargo-tauri &> /dev/null; then
     cargo install tauri-cli
 fi
 
+# Opus playback links libopus: the system library when pkg-config finds it, otherwise a
+# bundled copy built with CMake. Cross builds (e.g. Windows) always take the CMake route.
+if ! command -v cmake &> /dev/null; then
+    if [ "$1" = "windows" ] || ! pkg-config --exists opus 2> /dev/null; then
+        echo "❌ libopus is required. Install CMake to build the bundled copy, or the"
+        echo "   libopus development package (libopus-dev, opus-devel, brew install opus)."
+        exit 1
+    fi
+fi
+
 # Create dist directory and copy frontend
 echo "📁 Preparing frontend assets..."
 mkdir -p dist
@@ -97,6 +97,8 @@ const requesterName = document.getElementById('requester-name');
 
 const volumeSlider = document.getElementById('volume-slider');
 const volumeIndicator = document.getElementById('volume-indicator');
+const crossfeedToggle = document.getElementById('crossfeed-toggle');
+const crossfeedStrength = document.getElementById('crossfeed-strength');
 
 const DEFAULT_ALBUM_ART = "data:image/svg+xml;utf8,<svg xmlns='http://www.w3.org/2000/svg' width='200' height='200'><rect fill='%23222222' width='200' height='200'/><text x='50%' y='50%' dominant-baseline='middle' text-anchor='middle' fill='%23555555' font-size='48'>♪</text></svg>";
 const DEFAULT_SERVER_URL = 'https://miu.gacha.boo';
@@ -108,6 +110,7 @@ let isPlaying = false;
 let isConnected = false;
 let playerStateInterval = null;
 let reconnectTimeout = null;
+let isBufferStalled = false;
 
 function setCssVariable(name, value) {
     if (!name || typeof value === 'undefined' || value === null) {
@@ -344,6 +347,7 @@ document.addEventListener('DOMContentLoaded', async () => {
         initializeTauriListeners();
         await hydrateThemeOverrides();
         await hydrateHyprlandTheme();
+        await hydrateCrossfeed();
         connectToServer();
     } catch (error) {
         console.error('Failed to acquire Tauri APIs', error);
@@ -355,6 +359,8 @@ function initializeEventListeners() {
     albumArtContainer.addEventListener('click', handlePlayPause);
     volumeSlider.addEventListener('input', handleVolumeChange);
     volumeSlider.addEventListener('mousemove', updateVolumeIndicator);
+    crossfeedToggle.addEventListener('change', handleCrossfeedChange);
+    crossfeedStrength.addEventListener('change', handleCrossfeedChange);
 }
 
 async function resolveAppWindowHandle(apis) {
@@ -480,6 +486,69 @@ function initializeTauriListeners() {
     }).catch((error) => {
         console.error('Failed to register theme_overrides listener', error);
     });
+
+    listen('audio_output_changed', (event) => {
+        const device = event && event.payload ? event.payload.device : null;
+        setConnectionStatus(`Audio output switched to ${device || 'default device'}`, 'info');
+        setTimeout(() => {
+            if (isConnected) {
+                setConnectionStatus('');
+            }
+        }, 4000);
+    }).catch((error) => {
+        console.error('Failed to register audio_output_changed listener', error);
+    });
+
+    listen('buffer_metrics', (event) => {
+        const metrics = event && event.payload ? event.payload : null;
+        const stalled = Boolean(metrics && metrics.stalled);
+        if (stalled === isBufferStalled) {
+            return;
+        }
+
+        isBufferStalled = stalled;
+        if (stalled) {
+            setConnectionStatus('Buffering…', 'info');
+        } else if (isConnected) {
+            setConnectionStatus('');
+        }
+    }).catch((error) => {
+        console.error('Failed to register buffer_metrics listener', error);
+    });
+
+    listen('playback_error', (event) => {
+        const failure = event && event.payload ? event.payload : null;
+        if (!failure) {
+            return;
+        }
+
+        console.warn('Playback error:', failure);
+        if (failure.retryingInMs != null) {
+            const seconds = Math.ceil(failure.retryingInMs / 1000);
+            setConnectionStatus(`Playback failed, retrying in ${seconds}s…`, 'info');
+        } else {
+            setConnectionStatus(`Playback failed: ${failure.message}`, 'error');
+        }
+    }).catch((error) => {
+        console.error('Failed to register playback_error listener', error);
+    });
+
+    listen('audio_spectrum', (event) => {
+        const bands = event && event.payload ? event.payload.bands : null;
+        if (!Array.isArray(bands) || bands.length === 0) {
+            return;
+        }
+
+        // Bass drives the glow, the overall average its spread
+        const bassCount = Math.max(1, Math.floor(bands.length / 8));
+        const bass = bands.slice(0, bassCount).reduce((sum, level) => sum + level, 0) / bassCount;
+        const level = bands.reduce((sum, level) => sum + level, 0) / bands.length;
+        const root = document.documentElement.style;
+        root.setProperty('--miu-spectrum-bass', bass.toFixed(3));
+        root.setProperty('--miu-spectrum-level', level.toFixed(3));
+    }).catch((error) => {
+        console.error('Failed to register audio_spectrum listener', error);
+    });
 }
 
 async function connectToServer(serverUrl = DEFAULT_SERVER_URL) {
@@ -576,6 +645,46 @@ async function handleVolumeChange() {
     }
 }
 
+async function hydrateCrossfeed() {
+    if (!invoke) {
+        return;
+    }
+
+    try {
+        const level = await invoke('get_crossfeed');
+        updateCrossfeedControls(level);
+    } catch (error) {
+        console.warn('Failed to load crossfeed setting', error);
+    }
+}
+
+function updateCrossfeedControls(level) {
+    const enabled = level && level !== 'off';
+    crossfeedToggle.checked = enabled;
+    crossfeedStrength.disabled = !enabled;
+    if (enabled) {
+        crossfeedStrength.value = level;
+    }
+}
+
+async function handleCrossfeedChange() {
+    // The strength is kept while crossfeed is off so turning it back on restores it
+    const level = crossfeedToggle.checked ? crossfeedStrength.value : 'off';
+    crossfeedStrength.disabled = !crossfeedToggle.checked;
+
+    if (!invoke) {
+        console.warn('Cannot set crossfeed without Tauri invoke API');
+        return;
+    }
+
+    try {
+        await invoke('set_crossfeed', { level });
+    } catch (error) {
+        console.error('Crossfeed change failed:', error);
+        await hydrateCrossfeed();
+    }
+}
+
 function updateVolumeIndicator() {
     const volume = getSliderVolume();
     const percentage = Math.round(volume * 100);
@@ -1,511 +1,623 @@
-use anyhow::{anyhow, Result};
-use bytes::Bytes;
-use reqwest::header::{HeaderValue, CONTENT_RANGE, RANGE};
-use reqwest::{Client, Response, StatusCode};
-use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
-use std::io::{Error as IoError, ErrorKind};
-use std::io::{Read, Seek, SeekFrom};
+use crate::cache::{AudioCache, CacheFill};
+use crate::clock::{ClockedSource, PlaybackClock, SMOOTH_LIMIT_MS};
+use crate::config::StreamTransport;
+use crate::crossfeed::{Crossfeed, CrossfeedLevel};
+use crate::decoder::{DecoderHandle, StreamDecoder};
+use crate::equalizer::Equalizer;
+use crate::fade::{FadeSettings, Fader};
+use crate::hls::{HlsPlaylist, HlsStreamReader};
+use crate::http_stream::HttpStreamReader;
+use crate::loudness::LoudnessNormalizer;
+use crate::media_format::MediaFormat;
+use crate::mixer::{Mixer, MixerConfig};
+use crate::read_ahead::ReadAhead;
+use crate::spectrum::SpectrumAnalyzer;
+use crate::stream_stats::StreamStats;
+use crate::stream_token::StreamToken;
+use anyhow::{anyhow, Context, Result};
+use bytes::{Bytes, BytesMut};
+use reqwest::Client;
+use rodio::cpal::traits::HostTrait;
+use rodio::{DeviceTrait, OutputStream, OutputStreamHandle, Sink, Source, StreamError};
+use serde::Serialize;
+use std::fs::File;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::runtime::Handle;
-use tokio::sync::mpsc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use symphonia::core::io::MediaSource;
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
 use tokio::task::JoinHandle;
 
-const PREFETCH_CHANNEL_SIZE: usize = 5;
-const BUFFER_AHEAD_CHUNKS: usize = 3;
-const MIN_BUFFER_CHUNKS: usize = 2;
-const CHUNK_SIZE_ESTIMATE: u64 = 32768; // Estimated bytes per chunk for position calculation
+const GAPLESS_WINDOW_SECS: f64 = 2.0; // Append the next track only if the current one is about to end
+const PREFETCH_SEEK_TOLERANCE_SECS: f64 = 0.5;
+const OUTPUT_STALL_SECS: f64 = 3.0; // A playing sink nobody pulls from for this long is dead
+const MONITOR_TOUCH_INTERVAL: usize = 1024; // Samples between activity timestamps
+const DRIFT_THRESHOLD_SECS: f64 = 0.08; // Audible as a flam against other listeners
+const MAX_CORRECTABLE_DRIFT_SECS: f64 = 10.0; // Beyond this it is a resync, not drift
+const DRIFT_STRIKES: u32 = 2; // Consecutive out-of-range readings before correcting
+const FADE_STOP_MARGIN: Duration = Duration::from_millis(50); // Faded tail still in the device buffer
+const RESUME_BUFFER_MARGIN_SECS: f64 = 1.0; // Keep a warm resume clear of the network
+const MAX_PREFETCH_BYTES: usize = 64 * 1024 * 1024; // Larger tracks are streamed when they start
 
-enum FetchMessage {
-    Chunk(Bytes),
-    Eof,
-    Error(IoError),
+struct AudioComponents {
+    _stream: OutputStream,
+    stream_handle: OutputStreamHandle,
+    device_name: Option<String>,
 }
 
-/// Wrapper to make reqwest::Response work with rodio::Decoder
-struct HttpStreamReader {
-    client: Client,
-    url: String,
-    buffer: Bytes,
-    buffer_pos: usize,
-    position: u64,
-    eof: bool,
-    runtime: Handle,
-    total_length: Option<u64>,
-    chunk_rx: Option<mpsc::Receiver<FetchMessage>>,
-    fetch_task: Option<JoinHandle<()>>,
-    chunk_buffer: Vec<Bytes>,
-    buffer_target_position: u64,
-    last_sse_position: Option<f64>,
-    network_latency_ms: f64,
-}
-
-impl HttpStreamReader {
-    fn new(client: Client, url: String, start_offset: u64, runtime: Handle) -> Result<Self> {
-        let mut reader = Self {
-            client,
-            url,
-            buffer: Bytes::new(),
-            buffer_pos: 0,
-            position: start_offset,
-            eof: false,
-            runtime,
-            total_length: None,
-            chunk_rx: None,
-            fetch_task: None,
-            chunk_buffer: Vec::new(),
-            buffer_target_position: start_offset,
-            last_sse_position: None,
-            network_latency_ms: 50.0, // Default estimate
-        };
+unsafe impl Send for AudioComponents {}
+unsafe impl Sync for AudioComponents {}
 
-        reader
-            .start_stream(start_offset)
-            .map_err(|e| anyhow!("Failed to start audio stream: {}", e))?;
+impl AudioComponents {
+    /// Open the named output device, falling back to the system default when it is unset
+    /// or no longer present.
+    fn open(device_name: Option<&str>) -> Result<Self> {
+        let host = rodio::cpal::default_host();
+
+        let device = device_name.and_then(|name| {
+            let found = host
+                .output_devices()
+                .ok()?
+                .find(|device| device.name().map(|n| n == name).unwrap_or(false));
+            if found.is_none() {
+                println!("Audio: Output device '{}' not found, using default", name);
+            }
+            found
+        });
 
-        Ok(reader)
-    }
+        let device = match device {
+            Some(device) => device,
+            None => host
+                .default_output_device()
+                .ok_or(StreamError::NoDevice)
+                .context("No audio output device available")?,
+        };
 
-    fn buffer_start(&self) -> u64 {
-        self.position.saturating_sub(self.buffer_pos as u64)
-    }
+        let (_stream, stream_handle) = OutputStream::try_from_device(&device)
+            .context("Failed to create audio output stream")?;
 
-    fn start_stream(&mut self, offset: u64) -> std::io::Result<()> {
-        if let Some(total) = self.total_length {
-            if offset > total {
-                return Err(IoError::new(
-                    ErrorKind::InvalidInput,
-                    format!(
-                        "Cannot open stream at offset {} beyond file size {}",
-                        offset, total
-                    ),
-                ));
-            }
+        Ok(Self {
+            _stream,
+            stream_handle,
+            device_name: device.name().ok(),
+        })
+    }
+}
 
-            if offset == total {
-                self.abort_fetcher();
-                self.position = offset;
-                self.eof = true;
-                self.buffer = Bytes::new();
-                self.buffer_pos = 0;
-                self.chunk_rx = None;
-                return Ok(());
-            }
-        }
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputDeviceInfo {
+    pub name: String,
+    pub is_default: bool,
+}
 
-        let request = build_stream_request(&self.client, &self.url, offset);
-        let response = self
-            .runtime
-            .block_on(async { request.send().await })
-            .map_err(|e| IoError::new(ErrorKind::Other, format!("HTTP stream error: {}", e)))?;
+/// Enumerate the output devices of the default cpal host
+pub fn list_output_devices() -> Result<Vec<OutputDeviceInfo>> {
+    let host = rodio::cpal::default_host();
+    let default_name = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+
+    let devices = host
+        .output_devices()
+        .map_err(|e| anyhow!("Failed to enumerate output devices: {}", e))?;
+
+    Ok(devices
+        .filter_map(|device| device.name().ok())
+        .map(|name| OutputDeviceInfo {
+            is_default: default_name.as_deref() == Some(name.as_str()),
+            name,
+        })
+        .collect())
+}
 
-        if !(response.status() == StatusCode::PARTIAL_CONTENT || response.status().is_success()) {
-            return Err(IoError::new(
-                ErrorKind::Other,
-                format!("HTTP stream responded with status {}", response.status()),
-            ));
-        }
+/// Records when the output callback last pulled samples, so an output stream that silently
+/// stopped consuming audio can be told apart from a paused or drained sink.
+struct OutputMonitor {
+    epoch: Instant,
+    last_pull_ms: AtomicU64,
+    // Set while the callback is inside our source, e.g. blocked on a network read
+    in_source: AtomicBool,
+}
 
-        if let Some(total) = total_length_from_response(&response) {
-            if self.total_length.is_none() || self.total_length == Some(0) {
-                self.total_length = Some(total);
-            }
-        } else if self.total_length.is_none() {
-            self.total_length = response.content_length();
+impl OutputMonitor {
+    fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            last_pull_ms: AtomicU64::new(0),
+            in_source: AtomicBool::new(false),
         }
+    }
 
-        self.position = offset;
-        self.buffer = Bytes::new();
-        self.buffer_pos = 0;
-        self.eof = false;
-        self.chunk_buffer.clear();
-        self.buffer_target_position = offset;
+    fn touch(&self) {
+        self.last_pull_ms
+            .store(self.epoch.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
 
-        self.abort_fetcher();
+    fn idle_secs(&self) -> f64 {
+        if self.in_source.load(Ordering::Relaxed) {
+            return 0.0;
+        }
+        let now_ms = self.epoch.elapsed().as_millis() as u64;
+        now_ms.saturating_sub(self.last_pull_ms.load(Ordering::Relaxed)) as f64 / 1000.0
+    }
+}
 
-        let (tx, rx) = mpsc::channel::<FetchMessage>(PREFETCH_CHANNEL_SIZE);
-        self.chunk_rx = Some(rx);
+/// Pass-through source that reports pulls to an [`OutputMonitor`]
+struct MonitoredSource<S> {
+    inner: S,
+    monitor: Arc<OutputMonitor>,
+    pulled: usize,
+}
 
-        let client = self.client.clone();
-        let url = self.url.clone();
+impl<S> MonitoredSource<S> {
+    fn new(inner: S, monitor: Arc<OutputMonitor>) -> Self {
+        monitor.touch();
+        Self {
+            inner,
+            monitor,
+            pulled: 0,
+        }
+    }
+}
 
-        let fetch_task = self.runtime.spawn(async move {
-            let sender = tx;
-            let mut current_response = response;
-            let mut current_offset = offset;
+impl<S: Source<Item = i16>> Iterator for MonitoredSource<S> {
+    type Item = i16;
 
-            loop {
-                match current_response.chunk().await {
-                    Ok(Some(chunk)) => {
-                        if chunk.is_empty() {
-                            continue;
-                        }
+    fn next(&mut self) -> Option<i16> {
+        self.monitor.in_source.store(true, Ordering::Relaxed);
+        let sample = self.inner.next();
+        self.monitor.in_source.store(false, Ordering::Relaxed);
 
-                        current_offset = current_offset.saturating_add(chunk.len() as u64);
+        self.pulled += 1;
+        if self.pulled.is_multiple_of(MONITOR_TOUCH_INTERVAL) {
+            self.monitor.touch();
+        }
+        sample
+    }
+}
 
-                        if sender.send(FetchMessage::Chunk(chunk)).await.is_err() {
-                            break;
-                        }
-                    }
-                    Ok(None) => {
-                        let _ = sender.send(FetchMessage::Eof).await;
-                        break;
-                    }
-                    Err(err) => {
-                        if err.is_timeout() {
-                            match request_stream_async(client.clone(), url.clone(), current_offset)
-                                .await
-                            {
-                                Ok(new_response) => {
-                                    current_response = new_response;
-                                    continue;
-                                }
-                                Err(io_err) => {
-                                    let _ = sender.send(FetchMessage::Error(io_err)).await;
-                                    break;
-                                }
-                            }
-                        } else {
-                            let _ = sender
-                                .send(FetchMessage::Error(IoError::new(
-                                    ErrorKind::Other,
-                                    format!("HTTP chunk error: {}", err),
-                                )))
-                                .await;
-                            break;
-                        }
-                    }
-                }
-            }
-        });
+impl<S: Source<Item = i16>> Source for MonitoredSource<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
 
-        self.fetch_task = Some(fetch_task);
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
 
-        Ok(())
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
     }
 
-    fn reopen_stream(&mut self, offset: u64) -> std::io::Result<()> {
-        self.start_stream(offset)
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
     }
+}
 
-    fn ensure_total_length(&mut self) -> std::io::Result<()> {
-        if self.total_length.is_some() {
-            return Ok(());
-        }
+/// Where a track's audio is read from when playback starts
+enum StreamSource {
+    Cached(PathBuf),
+    Hls(Arc<HlsPlaylist>),
+    Http(HttpStreamReader),
+}
 
-        let client = self.client.clone();
-        let url = self.url.clone();
-        let runtime = self.runtime.clone();
+/// Complete copy of a track, either in the on-disk cache or downloaded into memory
+enum TrackData {
+    File(PathBuf),
+    Memory(Bytes),
+}
 
-        let head_result = runtime.block_on(async { client.head(&url).send().await });
-        if let Ok(resp) = head_result {
-            if resp.status().is_success() {
-                if let Some(len) = resp.content_length() {
-                    self.total_length = Some(len);
-                    return Ok(());
-                }
+impl TrackData {
+    fn open(&self) -> Result<Box<dyn MediaSource>> {
+        match self {
+            TrackData::File(path) => {
+                let file = File::open(path).map_err(|e| {
+                    anyhow!("Failed to open cached audio {}: {}", path.display(), e)
+                })?;
+                Ok(Box::new(file))
             }
+            TrackData::Memory(data) => Ok(Box::new(std::io::Cursor::new(data.clone()))),
         }
+    }
+}
 
-        let request = client.get(&url).header(RANGE, "bytes=0-0");
+/// Track that is currently playing, kept so playback can be rebuilt locally
+#[derive(Clone)]
+struct CurrentStream {
+    track_id: String,
+    stream_url: String,
+    clock_id: u64,
+    decoder: DecoderHandle,
+    // Set for HLS playback, which seeks by reopening at a segment rather than in the decoder
+    hls: Option<Arc<HlsPlaylist>>,
+}
 
-        let response = runtime
-            .block_on(async { request.send().await })
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+/// Sink feeding the output, with the fader that every source queued on it goes through
+struct PlayingSink {
+    sink: Sink,
+    fader: Arc<Fader>,
+}
 
-        if let Some(total) = parse_content_range(response.headers().get(CONTENT_RANGE)) {
-            self.total_length = Some(total);
-        } else if let Some(len) = response.content_length() {
-            self.total_length = Some(len);
+impl PlayingSink {
+    /// Fade out over `fade` and stop once silent. The tail no longer advances the playback
+    /// clock, so fading never moves the synced position.
+    fn retire(self, fade: Duration) {
+        if fade.is_zero() || self.sink.is_paused() || self.sink.empty() {
+            self.sink.stop();
+            return;
         }
 
-        Ok(())
+        self.fader.fade_to(0.0, fade);
+        tokio::spawn(async move {
+            tokio::time::sleep(fade + FADE_STOP_MARGIN).await;
+            self.sink.stop();
+        });
     }
+}
 
-    fn load_next_chunk(&mut self) -> std::io::Result<()> {
-        if self.eof {
-            return Ok(());
-        }
-
-        // Try to get chunk from buffer first
-        if !self.chunk_buffer.is_empty() {
-            self.buffer = self.chunk_buffer.remove(0);
-            self.buffer_pos = 0;
-            return Ok(());
-        }
-
-        let rx = self
-            .chunk_rx
-            .as_mut()
-            .ok_or_else(|| IoError::new(ErrorKind::UnexpectedEof, "Chunk receiver missing"))?;
-
-        // Buffer multiple chunks ahead for smoother playback
-        while self.chunk_buffer.len() < MIN_BUFFER_CHUNKS {
-            match rx.try_recv() {
-                Ok(FetchMessage::Chunk(chunk)) => {
-                    if !chunk.is_empty() {
-                        self.chunk_buffer.push(chunk);
-                    }
-                }
-                Ok(FetchMessage::Eof) => {
-                    self.eof = true;
-                    break;
-                }
-                Ok(FetchMessage::Error(err)) => {
-                    self.eof = true;
-                    return Err(err);
-                }
-                Err(mpsc::error::TryRecvError::Empty) => {
-                    // No more chunks available immediately, get one blocking
-                    match rx.blocking_recv() {
-                        Some(FetchMessage::Chunk(chunk)) => {
-                            if !chunk.is_empty() {
-                                self.chunk_buffer.push(chunk);
-                                break;
-                            }
-                        }
-                        Some(FetchMessage::Eof) | None => {
-                            self.eof = true;
-                            break;
-                        }
-                        Some(FetchMessage::Error(err)) => {
-                            self.eof = true;
-                            return Err(err);
-                        }
-                    }
-                }
-                Err(mpsc::error::TryRecvError::Disconnected) => {
-                    self.eof = true;
-                    break;
-                }
-            }
-        }
+/// A paused stream kept open so that resuming does not reload it
+struct SoftPause {
+    /// Output position once the fade-out finished and the sink was paused; `None` while
+    /// still fading out
+    position: Option<f64>,
+    /// Audio left in the track when the sink was paused
+    remaining: Option<Duration>,
+    faded_at: tokio::time::Instant,
+    expires_at: tokio::time::Instant,
+}
 
-        // Get next chunk from buffer
-        if !self.chunk_buffer.is_empty() {
-            self.buffer = self.chunk_buffer.remove(0);
-            self.buffer_pos = 0;
-            Ok(())
-        } else if self.eof {
-            self.buffer = Bytes::new();
-            self.buffer_pos = 0;
-            Ok(())
-        } else {
-            Err(IoError::new(ErrorKind::UnexpectedEof, "No chunks available"))
+impl SoftPause {
+    fn deadline(&self) -> tokio::time::Instant {
+        match self.position {
+            None => self.faded_at,
+            Some(_) => self.expires_at,
         }
     }
+}
 
+/// Next queued track, downloaded in full and decoded up to its first packet so it can be
+/// appended to the running sink the moment the backend switches over.
+struct PrefetchedTrack {
+    track_id: String,
+    stream_url: String,
+    source: StreamDecoder,
+}
 
-    fn abort_fetcher(&mut self) {
-        if let Some(handle) = self.fetch_task.take() {
-            handle.abort();
-        }
-        self.chunk_buffer.clear();
-    }
+/// State the audio actor publishes for everyone holding an `AudioHandle`
+#[derive(Debug, Clone, Default)]
+pub struct AudioStatus {
+    pub output_device: Option<String>,
+    /// Last server position received over SSE, and when
+    pub last_sync_position: Option<f64>,
+    pub last_sync_at: Option<Instant>,
+}
 
-    /// Update buffer strategy based on SSE position information
-    pub fn update_sse_position(&mut self, position_seconds: f64) {
-        self.last_sse_position = Some(position_seconds);
+enum AudioCommand {
+    Play {
+        track_id: String,
+        stream_url: String,
+        position: f64,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Stop,
+    SoftPause {
+        keep: Duration,
+    },
+    Resume {
+        track_id: String,
+        position: f64,
+        reply: oneshot::Sender<bool>,
+    },
+    SetVolume(f32),
+    SyncPosition {
+        position: f64,
+        latency_ms: Option<f64>,
+    },
+    Prefetch {
+        track_id: String,
+        stream_url: String,
+    },
+    SetOutputDevice {
+        device: Option<String>,
+        resume_position: Option<f64>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    CheckOutput {
+        reply: oneshot::Sender<bool>,
+    },
+}
 
-        // Calculate target buffer position based on SSE position
-        let position_bytes = (position_seconds * 44100.0 * 2.0 * 2.0) as u64; // Estimate for stereo 16-bit
-        let buffer_ahead_bytes = BUFFER_AHEAD_CHUNKS as u64 * CHUNK_SIZE_ESTIMATE;
-        self.buffer_target_position = position_bytes + buffer_ahead_bytes;
-    }
+/// Cheap, cloneable front end of the audio actor.
+///
+/// The actor task owns the `AudioManager` and with it the sink, and works through commands
+/// in order. Opening a stream runs in a task of its own, so volume changes and position
+/// updates are applied right away even while a slow stream is being opened.
+#[derive(Clone)]
+pub struct AudioHandle {
+    commands: mpsc::UnboundedSender<AudioCommand>,
+    status: watch::Receiver<AudioStatus>,
+    clock: Arc<PlaybackClock>,
+    stream_stats: Arc<StreamStats>,
+    read_ahead: Arc<ReadAhead>,
+    spectrum: Arc<SpectrumAnalyzer>,
+    mixer: Arc<Mixer>,
+    crossfeed: Arc<Crossfeed>,
+}
 
-    /// Check if buffer needs repositioning based on SSE sync
-    pub fn needs_buffer_sync(&self) -> bool {
-        if let Some(sse_pos) = self.last_sse_position {
-            let sse_bytes = (sse_pos * 44100.0 * 2.0 * 2.0) as u64;
-            let current_buffer_start = self.buffer_start();
-            let drift = if sse_bytes > current_buffer_start {
-                sse_bytes - current_buffer_start
-            } else {
-                current_buffer_start - sse_bytes
-            };
+impl AudioHandle {
+    /// Open the output device and start the actor task
+    pub fn spawn(
+        cache: Arc<AudioCache>,
+        equalizer: Arc<Equalizer>,
+        loudness: Arc<LoudnessNormalizer>,
+        fades: Arc<FadeSettings>,
+        output_device: Option<&str>,
+        transport: StreamTransport,
+    ) -> Result<Self> {
+        let (commands, command_rx) = mpsc::unbounded_channel();
+        let (opened, opened_rx) = mpsc::unbounded_channel();
+        let manager = AudioManager::new(
+            cache,
+            equalizer,
+            loudness,
+            fades,
+            output_device,
+            transport,
+            opened,
+        )?;
+
+        let handle = Self {
+            commands,
+            status: manager.status.subscribe(),
+            clock: manager.clock.clone(),
+            stream_stats: manager.stream_stats.clone(),
+            read_ahead: manager.read_ahead.clone(),
+            spectrum: manager.spectrum.clone(),
+            mixer: manager.mixer.clone(),
+            crossfeed: manager.crossfeed.clone(),
+        };
+        tauri::async_runtime::spawn(manager.run(command_rx, opened_rx));
 
-            // Sync if drift is more than 2 chunks worth of data
-            drift > (2 * CHUNK_SIZE_ESTIMATE)
-        } else {
-            false
-        }
+        Ok(handle)
     }
 
-    /// Update network latency estimate for better buffering
-    pub fn update_network_latency(&mut self, latency_ms: f64) {
-        self.network_latency_ms = latency_ms.clamp(10.0, 500.0);
+    fn send(&self, command: AudioCommand) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| anyhow!("Audio task is not running"))
     }
 
-    /// Get recommended buffer size based on network conditions
-    fn get_adaptive_buffer_size(&self) -> usize {
-        if self.network_latency_ms > 200.0 {
-            PREFETCH_CHANNEL_SIZE * 2 // Double buffer for high latency
-        } else if self.network_latency_ms > 100.0 {
-            PREFETCH_CHANNEL_SIZE + 2 // Extra buffering for medium latency
-        } else {
-            PREFETCH_CHANNEL_SIZE // Normal buffering for low latency
-        }
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> AudioCommand,
+    ) -> Result<T> {
+        let (reply, response) = oneshot::channel();
+        self.send(command(reply))?;
+        response
+            .await
+            .map_err(|_| anyhow!("Audio task dropped the request"))
     }
-}
 
-impl Read for HttpStreamReader {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.buffer_pos >= self.buffer.len() {
-            self.load_next_chunk()?;
-        }
-
-        if self.buffer_pos >= self.buffer.len() {
-            return Ok(0);
-        }
+    /// Start `track_id` at `position`, continuing into the prefetched copy when there is one.
+    /// Resolves once audio is playing, or with an error if the start failed or was superseded.
+    pub async fn play(&self, track_id: &str, stream_url: &str, position: f64) -> Result<()> {
+        self.request(|reply| AudioCommand::Play {
+            track_id: track_id.to_string(),
+            stream_url: stream_url.to_string(),
+            position,
+            reply,
+        })
+        .await?
+    }
 
-        let available = self.buffer.len() - self.buffer_pos;
-        let to_copy = buf.len().min(available);
-        buf[..to_copy].copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + to_copy]);
-        self.buffer_pos += to_copy;
-        let old_position = self.position;
-        self.position = self.position.checked_add(to_copy as u64).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::Other, "Stream position overflow")
-        })?;
+    pub fn stop(&self) -> Result<()> {
+        self.send(AudioCommand::Stop)
+    }
 
-        // Update buffer metrics for SSE integration
-        if old_position % 100000 == 0 || to_copy == 0 {
-            // Periodically check buffer health
-        }
+    /// Fade out and pause, but keep the stream and decoder open for `keep` so a resume
+    /// within that time continues without reloading the track
+    pub fn soft_pause(&self, keep: Duration) -> Result<()> {
+        self.send(AudioCommand::SoftPause { keep })
+    }
 
-        Ok(to_copy)
+    /// Continue a soft-paused `track_id` at `position`. Resolves to false when nothing warm
+    /// is left to resume or `position` lies outside the buffered data.
+    pub async fn resume(&self, track_id: &str, position: f64) -> bool {
+        self.request(|reply| AudioCommand::Resume {
+            track_id: track_id.to_string(),
+            position,
+            reply,
+        })
+        .await
+        .unwrap_or(false)
     }
-}
 
-impl Drop for HttpStreamReader {
-    fn drop(&mut self) {
-        self.abort_fetcher();
+    pub fn set_volume(&self, volume: f32) -> Result<()> {
+        self.send(AudioCommand::SetVolume(volume))
     }
-}
 
-fn build_stream_request(client: &Client, url: &str, offset: u64) -> reqwest::RequestBuilder {
-    client
-        .get(url)
-        .header(RANGE, format!("bytes={}-", offset))
-        .timeout(Duration::from_secs(30))
-}
+    /// Track the server position reported over SSE and correct drift against it
+    pub fn sync_position(&self, position: f64, latency_ms: Option<f64>) -> Result<()> {
+        self.send(AudioCommand::SyncPosition {
+            position,
+            latency_ms,
+        })
+    }
 
-async fn request_stream_async(
-    client: Client,
-    url: String,
-    offset: u64,
-) -> std::io::Result<Response> {
-    let response = client
-        .get(&url)
-        .header(RANGE, format!("bytes={}-", offset))
-        .timeout(Duration::from_secs(30))
-        .send()
-        .await
-        .map_err(|e| IoError::new(ErrorKind::Other, format!("HTTP stream error: {}", e)))?;
+    /// Download and pre-decode the next queued track so the transition to it can be gapless
+    pub fn prefetch(&self, track_id: &str, stream_url: &str) -> Result<()> {
+        self.send(AudioCommand::Prefetch {
+            track_id: track_id.to_string(),
+            stream_url: stream_url.to_string(),
+        })
+    }
 
-    if !(response.status() == StatusCode::PARTIAL_CONTENT || response.status().is_success()) {
-        return Err(IoError::new(
-            ErrorKind::Other,
-            format!("HTTP stream responded with status {}", response.status()),
-        ));
+    /// Move output to another device. If something is playing it is rebuilt on the new
+    /// device at `resume_position` (the synced position) without asking the server again.
+    pub async fn set_output_device(
+        &self,
+        device: Option<&str>,
+        resume_position: Option<f64>,
+    ) -> Result<()> {
+        self.request(|reply| AudioCommand::SetOutputDevice {
+            device: device.map(str::to_string),
+            resume_position,
+            reply,
+        })
+        .await?
     }
 
-    Ok(response)
-}
+    /// True when the sink has audio queued and is not paused, yet the output stream has not
+    /// pulled any samples for a while; this is what a lost or failed device looks like.
+    pub async fn output_stalled(&self) -> bool {
+        self.request(|reply| AudioCommand::CheckOutput { reply })
+            .await
+            .unwrap_or(false)
+    }
 
-impl Seek for HttpStreamReader {
-    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
-        let target: i128 = match pos {
-            SeekFrom::Start(offset) => offset as i128,
-            SeekFrom::Current(delta) => self.position as i128 + delta as i128,
-            SeekFrom::End(delta) => {
-                self.ensure_total_length()?;
-                let total = self.total_length.ok_or_else(|| {
-                    std::io::Error::new(
-                        std::io::ErrorKind::Unsupported,
-                        "Total length unknown for SeekFrom::End",
-                    )
-                })?;
-                total as i128 + delta as i128
-            }
-        };
+    pub fn output_device_name(&self) -> Option<String> {
+        self.status.borrow().output_device.clone()
+    }
 
-        if target < 0 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Seek before start of stream",
-            ));
-        }
+    pub fn playback_clock(&self) -> Arc<PlaybackClock> {
+        self.clock.clone()
+    }
 
-        let target = target as u64;
+    /// Samples of what is playing, for the visualizer
+    pub fn spectrum(&self) -> Arc<SpectrumAnalyzer> {
+        self.spectrum.clone()
+    }
 
-        if let Some(total) = self.total_length {
-            if target > total {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    format!(
-                        "Seek beyond end of stream: target={}, total={}",
-                        target, total
-                    ),
-                ));
-            }
-            // Allow seeking to EOF (target == total)
-            if target == total {
-                self.position = target;
-                self.eof = true;
-                self.buffer = Bytes::new();
-                self.buffer_pos = 0;
-                return Ok(self.position);
-            }
-        }
+    /// Volume curve, balance and mono settings of the output
+    pub fn mixer(&self) -> Arc<Mixer> {
+        self.mixer.clone()
+    }
 
-        if !self.buffer.is_empty() {
-            let buffer_start = self.buffer_start();
-            let buffer_end = buffer_start + self.buffer.len() as u64;
+    /// Headphone crossfeed applied after the equalizer
+    pub fn crossfeed(&self) -> Arc<Crossfeed> {
+        self.crossfeed.clone()
+    }
 
-            if target >= buffer_start && target < buffer_end {
-                self.buffer_pos = (target - buffer_start) as usize;
-                self.position = target;
-                return Ok(self.position);
-            }
+    /// Buffer state of the stream feeding playback, for the UI and for debugging stutter
+    pub fn buffer_metrics(&self) -> BufferMetrics {
+        let status = self.status.borrow();
+        let stats = &self.stream_stats;
+
+        BufferMetrics {
+            streaming: stats.is_streaming(),
+            stalled: stats.is_stalled(),
+            buffered_bytes: stats.buffered_bytes(),
+            buffered_seconds: stats.buffered_seconds(),
+            download_complete: stats.is_complete(),
+            underruns: stats.underruns(),
+            reconnects: stats.reconnects(),
+            throughput_bytes_per_sec: stats.throughput(),
+            buffer_health: stats.health(),
+            read_ahead_secs: self.read_ahead.target_secs(),
+            sse_age_ms: status
+                .last_sync_at
+                .map(|inst| inst.elapsed().as_millis() as f64),
+            last_position: status.last_sync_position,
         }
-
-        self.reopen_stream(target)?;
-        Ok(self.position)
     }
 }
 
-fn total_length_from_response(response: &Response) -> Option<u64> {
-    parse_content_range(response.headers().get(CONTENT_RANGE)).or(response.content_length())
+/// A stream opened off the actor, ready to be played
+struct PreparedStream {
+    sink: Sink,
+    fader: Arc<Fader>,
+    remaining: Option<Duration>,
+    clock_id: u64,
+    decoder: DecoderHandle,
+    hls: Option<Arc<HlsPlaylist>>,
+    cached: bool,
+    // HLS playback does not see the file the cache keeps, so that has to be downloaded
+    // on the side; progressive streams are copied into the cache as they play
+    cache_separately: bool,
+    measure_path: Option<PathBuf>,
 }
 
-struct AudioComponents {
-    _stream: OutputStream,
-    stream_handle: OutputStreamHandle,
+/// Outcome of an open started by `AudioManager::open_stream`
+struct OpenedStream {
+    generation: u64,
+    track_id: String,
+    stream_url: String,
+    position: f64,
+    fade_in: Duration,
+    result: Result<PreparedStream>,
 }
 
-unsafe impl Send for AudioComponents {}
-unsafe impl Sync for AudioComponents {}
+/// Everything needed to open a stream and build its sink, detached from the actor
+struct StreamOpener {
+    audio: Arc<AudioComponents>,
+    http: Client,
+    stream_http: Client,
+    cache: Arc<AudioCache>,
+    stream_stats: Arc<StreamStats>,
+    read_ahead: Arc<ReadAhead>,
+    output_monitor: Arc<OutputMonitor>,
+    equalizer: Arc<Equalizer>,
+    loudness: Arc<LoudnessNormalizer>,
+    crossfeed: Arc<Crossfeed>,
+    spectrum: Arc<SpectrumAnalyzer>,
+    mixer: Arc<Mixer>,
+    clock: Arc<PlaybackClock>,
+    transport: StreamTransport,
+}
 
-pub struct AudioManager {
+struct AudioManager {
     audio: Arc<AudioComponents>,
-    sink: Arc<Mutex<Option<Sink>>>,
+    sink: Option<PlayingSink>,
     volume: f32,
     http: Client,
-    last_sse_position: Arc<Mutex<Option<f64>>>,
-    last_sse_update: Arc<Mutex<Option<std::time::Instant>>>,
-    buffer_health: Arc<Mutex<f64>>,
+    stream_http: Client,
+    status: watch::Sender<AudioStatus>,
+    stream_stats: Arc<StreamStats>,
+    read_ahead: Arc<ReadAhead>,
+    // Estimated moment the sink runs out of queued audio
+    playback_end: Option<Instant>,
+    prefetched: Arc<Mutex<Option<PrefetchedTrack>>>,
+    // Track the running prefetch task is fetching; the task leaves it in `prefetched` once
+    // the data is ready, so a finished task that left nothing there has failed
+    prefetch_track_id: Option<String>,
+    prefetch_task: Option<JoinHandle<()>>,
+    cache: Arc<AudioCache>,
+    current_stream: Option<CurrentStream>,
+    output_monitor: Arc<OutputMonitor>,
+    equalizer: Arc<Equalizer>,
+    loudness: Arc<LoudnessNormalizer>,
+    fades: Arc<FadeSettings>,
+    crossfeed: Arc<Crossfeed>,
+    spectrum: Arc<SpectrumAnalyzer>,
+    mixer: Arc<Mixer>,
+    clock: Arc<PlaybackClock>,
+    drift_strikes: AtomicU32,
+    transport: StreamTransport,
+    opened: mpsc::UnboundedSender<OpenedStream>,
+    // Bumped whenever a stream open is started or abandoned, so late results are discarded
+    open_generation: u64,
+    pending_open: Option<oneshot::Sender<Result<()>>>,
+    soft_pause: Option<SoftPause>,
 }
 
 impl AudioManager {
-    pub fn new() -> Result<Self> {
-        let (_stream, stream_handle) = OutputStream::try_default()
-            .map_err(|e| anyhow!("Failed to create audio output stream: {}", e))?;
-
-        let audio = Arc::new(AudioComponents {
-            _stream,
-            stream_handle,
-        });
+    fn new(
+        cache: Arc<AudioCache>,
+        equalizer: Arc<Equalizer>,
+        loudness: Arc<LoudnessNormalizer>,
+        fades: Arc<FadeSettings>,
+        output_device: Option<&str>,
+        transport: StreamTransport,
+        opened: mpsc::UnboundedSender<OpenedStream>,
+    ) -> Result<Self> {
+        let audio = Arc::new(AudioComponents::open(output_device)?);
+        println!(
+            "Audio: Using output device {}",
+            audio.device_name.as_deref().unwrap_or("<unknown>")
+        );
 
         let http = Client::builder()
             .user_agent("MIU Player Tauri")
@@ -517,289 +629,1229 @@ impl AudioManager {
             .build()
             .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
 
+        // A progressive stream's body is read for as long as the track plays, so it gets no
+        // overall timeout; the fetcher times each request and chunk itself
+        let stream_http = Client::builder()
+            .user_agent("MIU Player Tauri")
+            .connect_timeout(std::time::Duration::from_secs(10))
+            .pool_idle_timeout(std::time::Duration::from_secs(90))
+            .pool_max_idle_per_host(2)
+            .tcp_keepalive(std::time::Duration::from_secs(30))
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
+
+        let (status, _) = watch::channel(AudioStatus {
+            output_device: audio.device_name.clone(),
+            ..AudioStatus::default()
+        });
+
         Ok(Self {
             audio,
-            sink: Arc::new(Mutex::new(None)),
+            sink: None,
             volume: 0.8,
             http,
-            last_sse_position: Arc::new(Mutex::new(None)),
-            last_sse_update: Arc::new(Mutex::new(None)),
-            buffer_health: Arc::new(Mutex::new(1.0)),
+            stream_http,
+            status,
+            stream_stats: Arc::new(StreamStats::new()),
+            read_ahead: Arc::new(ReadAhead::new()),
+            playback_end: None,
+            prefetched: Arc::new(Mutex::new(None)),
+            prefetch_track_id: None,
+            prefetch_task: None,
+            cache,
+            current_stream: None,
+            output_monitor: Arc::new(OutputMonitor::new()),
+            equalizer,
+            loudness,
+            fades,
+            crossfeed: Arc::new(Crossfeed::new(CrossfeedLevel::Off)),
+            spectrum: Arc::new(SpectrumAnalyzer::new(false)),
+            mixer: Arc::new(Mixer::new(&MixerConfig::default())),
+            clock: Arc::new(PlaybackClock::new()),
+            drift_strikes: AtomicU32::new(0),
+            transport,
+            opened,
+            open_generation: 0,
+            pending_open: None,
+            soft_pause: None,
         })
     }
 
-    // Add method to fetch current position from server
-    async fn fetch_server_position(&self, stream_url: &str) -> Result<f64> {
-        let position_url = if stream_url.contains("/api/music/stream") {
-            stream_url.replace("/api/music/stream", "/api/music/position")
-        } else {
-            // Fallback: assume we can find the base URL
-            let base = stream_url.split("/api/").next().unwrap_or(stream_url);
-            format!("{}/api/music/position", base.trim_end_matches('/'))
+    /// Actor loop: apply commands in order and install streams as their opens complete
+    async fn run(
+        mut self,
+        mut commands: mpsc::UnboundedReceiver<AudioCommand>,
+        mut opened: mpsc::UnboundedReceiver<OpenedStream>,
+    ) {
+        loop {
+            let soft_pause_deadline = self.soft_pause.as_ref().map(SoftPause::deadline);
+
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(command) => self.handle(command).await,
+                    // Every handle is gone
+                    None => break,
+                },
+                Some(stream) = opened.recv() => self.install_stream(stream).await,
+                () = sleep_until(soft_pause_deadline) => self.advance_soft_pause(),
+            }
+        }
+
+        self.stop();
+    }
+
+    async fn handle(&mut self, command: AudioCommand) {
+        match command {
+            AudioCommand::Play {
+                track_id,
+                stream_url,
+                position,
+                reply,
+            } => self.play(track_id, stream_url, position, reply).await,
+            AudioCommand::Stop => self.stop(),
+            AudioCommand::SoftPause { keep } => self.pause_softly(keep),
+            AudioCommand::Resume {
+                track_id,
+                position,
+                reply,
+            } => {
+                let _ = reply.send(self.resume(&track_id, position).await);
+            }
+            AudioCommand::SetVolume(volume) => self.set_volume(volume),
+            AudioCommand::SyncPosition {
+                position,
+                latency_ms,
+            } => self.update_from_sse(position, latency_ms),
+            AudioCommand::Prefetch {
+                track_id,
+                stream_url,
+            } => self.prepare_track_transition(&track_id, &stream_url),
+            AudioCommand::SetOutputDevice {
+                device,
+                resume_position,
+                reply,
+            } => self.set_output_device(device.as_deref(), resume_position, reply),
+            AudioCommand::CheckOutput { reply } => {
+                let _ = reply.send(self.output_stalled());
+            }
+        }
+    }
+
+    fn set_output_device(
+        &mut self,
+        device_name: Option<&str>,
+        resume_position: Option<f64>,
+        reply: oneshot::Sender<Result<()>>,
+    ) {
+        let audio = match AudioComponents::open(device_name) {
+            Ok(audio) => Arc::new(audio),
+            Err(e) => {
+                let _ = reply.send(Err(e));
+                return;
+            }
         };
 
-        // Remove any query parameters for position request
-        let position_url = position_url.split('?').next().unwrap_or(&position_url);
+        let was_playing = self.is_playing();
+        self.stop();
+        self.audio = audio;
+        self.status
+            .send_modify(|status| status.output_device = self.audio.device_name.clone());
+
+        println!(
+            "Audio: Switched output to {}",
+            self.audio.device_name.as_deref().unwrap_or("<unknown>")
+        );
+
+        match (was_playing, self.current_stream.clone(), resume_position) {
+            (true, Some(current), Some(position)) => self.open_stream(
+                current.track_id,
+                current.stream_url,
+                position,
+                false,
+                self.fades.fade_in(),
+                Some(reply),
+            ),
+            _ => {
+                let _ = reply.send(Ok(()));
+            }
+        }
+    }
 
-        match self.http.get(position_url).send().await {
-            Ok(response) => match response.json::<serde_json::Value>().await {
-                Ok(data) => Ok(data["position"].as_f64().unwrap_or(0.0)),
-                Err(_e) => Ok(0.0),
-            },
-            Err(_e) => Ok(0.0),
+    fn opener(&self) -> StreamOpener {
+        StreamOpener {
+            audio: self.audio.clone(),
+            http: self.http.clone(),
+            stream_http: self.stream_http.clone(),
+            cache: self.cache.clone(),
+            stream_stats: self.stream_stats.clone(),
+            read_ahead: self.read_ahead.clone(),
+            output_monitor: self.output_monitor.clone(),
+            equalizer: self.equalizer.clone(),
+            loudness: self.loudness.clone(),
+            crossfeed: self.crossfeed.clone(),
+            spectrum: self.spectrum.clone(),
+            mixer: self.mixer.clone(),
+            clock: self.clock.clone(),
+            transport: self.transport,
         }
     }
 
-    pub async fn play_from(
+    /// Continue straight into the prefetched track when we have it; otherwise stop the
+    /// current sink and open a fresh stream
+    async fn play(
         &mut self,
-        stream_url: &str,
+        track_id: String,
+        stream_url: String,
+        position: f64,
+        reply: oneshot::Sender<Result<()>>,
+    ) {
+        match self.play_prefetched(&track_id, position).await {
+            Ok(true) => {
+                let _ = reply.send(Ok(()));
+            }
+            Ok(false) => self.play_from(track_id, stream_url, position, reply),
+            Err(e) => {
+                println!(
+                    "Audio: Prefetched track unusable, reopening stream: {:#}",
+                    e
+                );
+                self.play_from(track_id, stream_url, position, reply);
+            }
+        }
+    }
+
+    fn play_from(
+        &mut self,
+        track_id: String,
+        stream_url: String,
         start_position: f64,
-        _track_duration: Option<f64>,
-    ) -> Result<()> {
+        reply: oneshot::Sender<Result<()>>,
+    ) {
         // Stop current playback if any
-        self.stop().await?;
-
-        // Fetch current position from server (like frontend does)
-        let server_position = self.fetch_server_position(stream_url).await.unwrap_or(0.0);
+        let (fade_out, fade_in) = self.transition_fades(&track_id);
+        self.stop_with_fade(fade_out);
+        self.open_stream(
+            track_id,
+            stream_url,
+            start_position,
+            true,
+            fade_in,
+            Some(reply),
+        );
+    }
 
-        // Determine which position we'll target for buffering/seek
-        let target_position = if server_position > 0.1 {
-            server_position
-        } else {
-            start_position
-        };
+    /// Fades for moving from the current track to `track_id`: the crossfade when one is set
+    /// and the track actually changes, otherwise the plain fade-out and fade-in
+    fn transition_fades(&self, track_id: &str) -> (Duration, Duration) {
+        let changes_track = self
+            .current_stream
+            .as_ref()
+            .is_some_and(|current| current.track_id != track_id);
+
+        match self.fades.crossfade() {
+            Some(crossfade) if changes_track => (crossfade, crossfade),
+            _ => (self.fades.fade_out(), self.fades.fade_in()),
+        }
+    }
 
-        let request_offset = 0u64;
-        let residual_skip = target_position.max(0.0);
+    /// Open `track_id` at `position` in a task of its own; the result comes back to the actor
+    /// through `install_stream`. With `ask_server` the server's current position wins over
+    /// `position` (like the frontend does).
+    fn open_stream(
+        &mut self,
+        track_id: String,
+        stream_url: String,
+        position: f64,
+        ask_server: bool,
+        fade_in: Duration,
+        reply: Option<oneshot::Sender<Result<()>>>,
+    ) {
+        self.abandon_open();
+        self.pending_open = reply;
+
+        let generation = self.open_generation;
+        let opener = self.opener();
+        let opened = self.opened.clone();
 
-        // Create stream reader and decoder using the computed byte offset
-        let runtime_handle = tokio::runtime::Handle::current();
-        let http_client = self.http.clone();
-        let audio_components = self.audio.clone();
-        let stream_url_owned = stream_url.to_string();
-        let volume = self.volume;
-        let effective_offset = request_offset;
-        let skip_seconds = residual_skip;
-
-        let sink = tokio::task::spawn_blocking(move || -> Result<Sink> {
-            // Follow frontend pattern: stream full track, then seek
-            let timestamp = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
-            let stream_url_with_ts = if stream_url_owned.contains('?') {
-                format!("{}&ts={}", stream_url_owned, timestamp)
+        tokio::spawn(async move {
+            let server_position = if ask_server {
+                opener
+                    .fetch_server_position(&stream_url)
+                    .await
+                    .unwrap_or(0.0)
             } else {
-                format!("{}?ts={}", stream_url_owned, timestamp)
+                0.0
+            };
+            let position = if server_position > 0.1 {
+                server_position
+            } else {
+                position
             };
 
-            // Since rodio doesn't support seeking like HTML audio currentTime,
-            // we use skip_duration to skip the audio samples to the right position
-
-            let reader = HttpStreamReader::new(
-                http_client.clone(),
-                stream_url_with_ts,
-                effective_offset,
-                runtime_handle.clone(),
-            )?;
+            let result = opener.open(&track_id, &stream_url, position).await;
+            let _ = opened.send(OpenedStream {
+                generation,
+                track_id,
+                stream_url,
+                position,
+                fade_in,
+                result,
+            });
+        });
+    }
 
-            // Create decoder
-            let decoder = rodio::Decoder::new(reader)
-                .map_err(|e| anyhow!("Failed to create decoder: {}", e))?;
+    /// Forget the stream open in flight, if any; its result is dropped when it arrives
+    fn abandon_open(&mut self) {
+        self.open_generation += 1;
+        if let Some(reply) = self.pending_open.take() {
+            let _ = reply.send(Err(anyhow!("Playback start was superseded")));
+        }
+    }
 
-            // Use skip_duration to seek to the server position (like frontend's audio.currentTime)
-            let final_source: Box<dyn rodio::Source<Item = i16> + Send> = if skip_seconds > 0.1 {
-                Box::new(decoder.skip_duration(std::time::Duration::from_secs_f64(skip_seconds)))
-            } else {
-                Box::new(decoder)
-            };
+    /// Start playing a stream opened by `open_stream`, unless something newer replaced it
+    async fn install_stream(&mut self, opened: OpenedStream) {
+        if opened.generation != self.open_generation {
+            if let Ok(stream) = opened.result {
+                stream.sink.stop();
+            }
+            return;
+        }
 
-            let sink = Sink::try_new(&audio_components.stream_handle)
-                .map_err(|e| anyhow!("Failed to create audio sink: {}", e))?;
+        let reply = self.pending_open.take();
+        let result = match opened.result {
+            Ok(stream) => {
+                self.start_prepared(
+                    &opened.track_id,
+                    &opened.stream_url,
+                    opened.position,
+                    opened.fade_in,
+                    stream,
+                )
+                .await;
+                Ok(())
+            }
+            Err(e) => Err(e),
+        };
 
-            sink.set_volume(volume);
-            sink.append(final_source);
-            sink.play();
+        match (reply, result) {
+            (Some(reply), result) => {
+                let _ = reply.send(result);
+            }
+            (None, Err(e)) => println!("Audio: Failed to restart playback: {}", e),
+            (None, Ok(())) => {}
+        }
+    }
 
-            Ok(sink)
-        })
-        .await
-        .map_err(|err| anyhow!("Audio initialization task panicked: {}", err))??;
+    async fn start_prepared(
+        &mut self,
+        track_id: &str,
+        stream_url: &str,
+        position: f64,
+        fade_in: Duration,
+        stream: PreparedStream,
+    ) {
+        stream
+            .sink
+            .set_volume(self.mixer.output_volume(self.volume));
+        stream.fader.fade_to(1.0, fade_in);
+        stream.sink.play();
+        self.sink = Some(PlayingSink {
+            sink: stream.sink,
+            fader: stream.fader,
+        });
 
-        let mut sink_guard = self.sink.lock().await;
-        *sink_guard = Some(sink);
+        self.playback_end = stream.remaining.map(|d| Instant::now() + d);
+        self.current_stream = Some(CurrentStream {
+            track_id: track_id.to_string(),
+            stream_url: stream_url.to_string(),
+            clock_id: stream.clock_id,
+            decoder: stream.decoder,
+            hls: stream.hls,
+        });
 
         // Update SSE tracking for the new playback
-        let mut last_position = self.last_sse_position.lock().await;
-        *last_position = Some(target_position);
-
-        let mut last_update = self.last_sse_update.lock().await;
-        *last_update = Some(std::time::Instant::now());
+        self.status.send_modify(|status| {
+            status.last_sync_position = Some(position);
+            status.last_sync_at = Some(Instant::now());
+        });
 
-        let mut buffer_health = self.buffer_health.lock().await;
-        *buffer_health = 1.0; // Fresh start
+        if let Some(path) = stream.measure_path {
+            // Cached before loudness was tracked; measure now so the next play is normalized
+            self.measure_in_background(track_id, TrackData::File(path));
+        }
 
-        //     "Playback started: volume {:.2}, target position {:.2}s (seeking via skip_duration)",
-        //     self.volume, start_position
-        // );
+        if stream.cached {
+            println!("Audio: Playing {} from local cache", track_id);
+        } else if stream.cache_separately {
+            self.cache_in_background(track_id, stream_url).await;
+        }
 
-        // Removed local track completion monitoring - rely entirely on SSE events from backend
-        // The backend handles track timing and advancement, then broadcasts state changes via SSE
+        // The backend advances tracks and announces them over SSE
         println!("Audio: Playback started, relying on SSE for track progression");
+    }
 
-        Ok(())
+    /// Download the full stream for `track_id` into the on-disk cache without touching
+    /// playback, for tracks played over HLS
+    async fn cache_in_background(&self, track_id: &str, stream_url: &str) {
+        // Held until the download is stored, so the track is only fetched once at a time
+        let Some(claim) = self.cache.claim(track_id) else {
+            return;
+        };
+
+        let http = self.http.clone();
+        let loudness = self.loudness.clone();
+        let track_id = track_id.to_string();
+        let url = stream_url.to_string();
+
+        tokio::spawn(async move {
+            match fetch_full_stream(&http, &url, &track_id).await {
+                Ok((data, content_type)) => {
+                    let id = track_id.clone();
+                    let stored = tokio::task::spawn_blocking(move || -> Result<()> {
+                        claim.store(&data, content_type.as_deref())?;
+                        let measured = TrackData::Memory(data)
+                            .open()
+                            .and_then(|source| loudness.ensure_measured(&id, source));
+                        if let Err(e) = measured {
+                            println!("Loudness: Failed to measure {}: {}", id, e);
+                        }
+                        Ok(())
+                    })
+                    .await;
+                    match stored {
+                        Ok(Ok(())) => println!("Cache: Stored {}", track_id),
+                        Ok(Err(e)) => println!("Cache: Failed to store {}: {}", track_id, e),
+                        Err(e) => println!("Cache: Store task panicked: {}", e),
+                    }
+                }
+                Err(e) => println!("Cache: Download of {} failed: {}", track_id, e),
+            }
+        });
     }
 
-    pub async fn stop(&self) -> Result<()> {
-        let mut sink_guard = self.sink.lock().await;
-        if let Some(sink) = sink_guard.take() {
-            sink.stop();
+    fn measure_in_background(&self, track_id: &str, data: TrackData) {
+        let loudness = self.loudness.clone();
+        let track_id = track_id.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let result = data
+                .open()
+                .and_then(|source| loudness.ensure_measured(&track_id, source));
+            if let Err(e) = result {
+                println!("Loudness: Failed to measure {}: {}", track_id, e);
+            }
+        });
+    }
+
+    fn stop(&mut self) {
+        self.stop_with_fade(self.fades.fade_out());
+    }
+
+    fn stop_with_fade(&mut self, fade: Duration) {
+        // A stream still being opened would otherwise start playing after the stop
+        self.abandon_open();
+        self.soft_pause = None;
+
+        if let Some(playing) = self.sink.take() {
+            playing.retire(fade);
         }
 
+        self.playback_end = None;
+        self.clock.reset();
+        self.drift_strikes.store(0, Ordering::Relaxed);
+
         // Clear SSE tracking
-        let mut last_position = self.last_sse_position.lock().await;
-        *last_position = None;
+        self.status
+            .send_modify(|status| status.last_sync_position = None);
 
-        let mut buffer_health = self.buffer_health.lock().await;
-        *buffer_health = 0.0;
+        self.stream_stats.reset(false);
+    }
 
-        Ok(())
+    /// Fade out, then pause the sink with its stream and decoder left open. The clock is
+    /// parked meanwhile, so the reported position follows the server rather than the
+    /// paused output.
+    fn pause_softly(&mut self, keep: Duration) {
+        if !self.is_playing() || self.current_stream.is_none() {
+            self.stop();
+            return;
+        }
+
+        self.abandon_open();
+        let fade = self.fades.fade_out();
+        if let Some(playing) = self.sink.as_ref() {
+            playing.fader.fade_to(0.0, fade);
+        }
+
+        let now = tokio::time::Instant::now();
+        self.soft_pause = Some(SoftPause {
+            position: None,
+            remaining: None,
+            faded_at: now + fade + FADE_STOP_MARGIN,
+            expires_at: now + keep,
+        });
+        println!(
+            "Audio: Soft pause, keeping the stream open for {}s",
+            keep.as_secs()
+        );
     }
 
-    pub fn set_volume(&mut self, volume: f32) -> Result<()> {
+    /// Pause the sink once the fade-out is done, and give the stream up once the pause has
+    /// lasted longer than it is worth keeping open
+    fn advance_soft_pause(&mut self) {
+        let Some(paused) = self.soft_pause.as_mut() else {
+            return;
+        };
+
+        if paused.position.is_some() {
+            println!("Audio: Soft pause expired, releasing the stream");
+            self.stop_with_fade(Duration::ZERO);
+            return;
+        }
+
+        if let Some(playing) = self.sink.as_ref() {
+            playing.sink.pause();
+        }
+        // Everything handed to the output so far has been heard, so this is where to pick up
+        paused.position = self.clock.position();
+        paused.remaining = self
+            .playback_end
+            .take()
+            .map(|end| end.saturating_duration_since(Instant::now()));
+        self.clock.reset();
+        self.drift_strikes.store(0, Ordering::Relaxed);
+
+        if paused.position.is_none() {
+            self.stop_with_fade(Duration::ZERO);
+        }
+    }
+
+    /// Continue the soft-paused stream at `position`, seeking within the buffered data.
+    /// Returns false if the stream had to be given up instead.
+    async fn resume(&mut self, track_id: &str, position: f64) -> bool {
+        let Some(paused) = self.soft_pause.take() else {
+            return false;
+        };
+        let current = match self.current_stream.clone() {
+            Some(current) if current.track_id == track_id && self.sink.is_some() => current,
+            _ => {
+                self.stop_with_fade(Duration::ZERO);
+                return false;
+            }
+        };
+        let sample_rate = self.clock.sample_rate();
+
+        match paused.position {
+            // Still fading out, so the clock kept running; only correct what drifted
+            None => {
+                if let Some(local) = self.clock.played_position(track_id) {
+                    let drift = position - local;
+                    let frames = (drift * sample_rate as f64).round() as i64;
+                    if frames.abs() > self.smooth_limit() {
+                        if let Err(e) = self.seek_playing(&current, position).await {
+                            println!("Audio: Failed to seek resumed stream, reloading: {:#}", e);
+                            self.stop_with_fade(Duration::ZERO);
+                            return false;
+                        }
+                    } else if drift.abs() > DRIFT_THRESHOLD_SECS {
+                        self.clock.request_correction(frames);
+                    }
+                }
+            }
+            Some(paused_at) => {
+                let jump = position - paused_at;
+                if jump > self.buffered_ahead() - RESUME_BUFFER_MARGIN_SECS
+                    || jump < -MAX_CORRECTABLE_DRIFT_SECS
+                {
+                    println!(
+                        "Audio: Resume target {:+.1}s away is outside the buffered data, reloading",
+                        jump
+                    );
+                    self.stop_with_fade(Duration::ZERO);
+                    return false;
+                }
+
+                if jump.abs() * 1000.0 > SMOOTH_LIMIT_MS as f64 {
+                    // Seek while the sink is still paused, rather than have the output
+                    // callback decode and drop the whole gap
+                    self.clock
+                        .claim(current.clock_id, track_id, paused_at, sample_rate);
+                    if let Err(e) = self.seek_playing(&current, position).await {
+                        println!("Audio: Failed to seek paused stream, reloading: {:#}", e);
+                        self.stop_with_fade(Duration::ZERO);
+                        return false;
+                    }
+                } else {
+                    self.clock
+                        .claim(current.clock_id, track_id, paused_at, sample_rate);
+                    self.clock
+                        .request_correction((jump * sample_rate as f64).round() as i64);
+                }
+                self.playback_end = paused.remaining.map(|remaining| {
+                    Instant::now()
+                        + Duration::from_secs_f64((remaining.as_secs_f64() - jump).max(0.0))
+                });
+            }
+        }
+
+        if let Some(playing) = self.sink.as_ref() {
+            playing.fader.fade_to(1.0, self.fades.fade_in());
+            playing.sink.play();
+        }
+        self.status.send_modify(|status| {
+            status.last_sync_position = Some(position);
+            status.last_sync_at = Some(Instant::now());
+        });
+
+        println!("Audio: Resumed {} in place at {:.1}s", track_id, position);
+        true
+    }
+
+    /// Seconds of audio past the read position that can be decoded without the network
+    fn buffered_ahead(&self) -> f64 {
+        if !self.stream_stats.is_streaming() || self.stream_stats.is_complete() {
+            return f64::INFINITY;
+        }
+        self.stream_stats.buffered_seconds().unwrap_or(0.0)
+    }
+
+    fn set_volume(&mut self, volume: f32) {
         self.volume = volume.clamp(0.0, 1.0);
 
-        let sink = self.sink.clone();
-        let new_volume = self.volume;
+        if let Some(playing) = self.sink.as_ref() {
+            playing
+                .sink
+                .set_volume(self.mixer.output_volume(self.volume));
+        }
+    }
+
+    fn output_stalled(&self) -> bool {
+        self.is_playing() && self.output_monitor.idle_secs() > OUTPUT_STALL_SECS
+    }
+
+    fn is_playing(&self) -> bool {
+        self.sink
+            .as_ref()
+            .is_some_and(|playing| !playing.sink.is_paused() && !playing.sink.empty())
+    }
+
+    fn update_from_sse(&self, position_seconds: f64, latency_ms: Option<f64>) {
+        self.status.send_modify(|status| {
+            status.last_sync_position = Some(position_seconds);
+            status.last_sync_at = Some(Instant::now());
+        });
+
+        // The reported position was taken roughly one network latency ago
+        let server_position = position_seconds + latency_ms.unwrap_or(0.0) / 1000.0;
+        self.correct_drift(server_position);
+    }
+
+    /// Compare what the output has actually played with the server position and queue a
+    /// correction on the playing source when they drift apart.
+    fn correct_drift(&self, server_position: f64) {
+        let Some(current) = self.current_stream.as_ref() else {
+            return;
+        };
+
+        // During a gapless hand-over the output may still be on the previous track
+        if self.clock.active_id() != current.clock_id {
+            return;
+        }
+
+        let Some(local_position) = self.clock.played_position(&current.track_id) else {
+            return;
+        };
+        let sample_rate = self.clock.sample_rate();
+
+        let drift = local_position - server_position;
+        if drift.abs() < DRIFT_THRESHOLD_SECS || drift.abs() > MAX_CORRECTABLE_DRIFT_SECS {
+            self.drift_strikes.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        // Let a running correction finish before measuring again
+        if self.clock.pending_correction() != 0 {
+            return;
+        }
+
+        let strikes = self.drift_strikes.fetch_add(1, Ordering::Relaxed) + 1;
+        if strikes < DRIFT_STRIKES {
+            return;
+        }
+        self.drift_strikes.store(0, Ordering::Relaxed);
+
+        // Ahead of the server means holding back (negative), behind means skipping forward
+        let frames = (-drift * sample_rate as f64).round() as i64;
+        println!(
+            "Audio: Drift {:+.0}ms against server, correcting by {} frames",
+            drift * 1000.0,
+            frames
+        );
+        if frames.abs() <= self.smooth_limit() {
+            self.clock.request_correction(frames);
+            return;
+        }
+
+        // Too far to skip or hold back from the output callback, where holding back means
+        // playing silence; seek without holding up the actor
+        let seek = self.seek_playing(current, server_position);
         tokio::spawn(async move {
-            let sink_guard = sink.lock().await;
-            if let Some(ref sink) = *sink_guard {
-                sink.set_volume(new_volume);
+            if let Err(e) = seek.await {
+                println!("Audio: Drift correction seek failed: {:#}", e);
             }
         });
+    }
 
-        Ok(())
+    /// Longest skip the playing source makes by itself, in frames
+    fn smooth_limit(&self) -> i64 {
+        self.clock.sample_rate() as i64 * SMOOTH_LIMIT_MS / 1000
     }
 
-    pub async fn is_playing(&self) -> bool {
-        let sink_guard = self.sink.lock().await;
-        if let Some(ref sink) = *sink_guard {
-            !sink.is_paused() && !sink.empty()
-        } else {
-            false
-        }
-    }
-
-    /// Update buffer based on SSE position information
-    pub async fn update_from_sse(&self, position_seconds: f64, latency_ms: Option<f64>) -> Result<()> {
-        // Update position tracking
-        let mut last_position = self.last_sse_position.lock().await;
-        let previous_position = *last_position;
-        *last_position = Some(position_seconds);
-        drop(last_position);
-
-        // Update last SSE timestamp
-        let mut last_update = self.last_sse_update.lock().await;
-        let previous_update = *last_update;
-        *last_update = Some(std::time::Instant::now());
-        drop(last_update);
-
-        // Calculate buffer health based on SSE updates
-        let mut buffer_health = self.buffer_health.lock().await;
-        if let (Some(prev_pos), Some(prev_time)) = (previous_position, previous_update) {
-            let time_diff = prev_time.elapsed().as_secs_f64();
-            let pos_diff = (position_seconds - prev_pos).abs();
-
-            // Good health if position updates are consistent with time
-            let expected_diff = time_diff; // 1:1 ratio for real-time
-            let health = if pos_diff > 0.0 {
-                (expected_diff / pos_diff.max(expected_diff)).min(1.0)
-            } else {
-                0.8 // Static position, moderate health
+    /// Seek the playing decoder to track position `target` and count the clock from there.
+    /// The seek waits for the decoder thread and possibly the network, so it runs on a
+    /// blocking thread.
+    fn seek_playing(
+        &self,
+        current: &CurrentStream,
+        target: f64,
+    ) -> impl Future<Output = Result<()>> + 'static {
+        let clock = self.clock.clone();
+        let current = current.clone();
+        let http = self.http.clone();
+        let stream_stats = self.stream_stats.clone();
+
+        async move {
+            let decoder = current.decoder.clone();
+            let seek = match current.hls.clone() {
+                // HLS can only be read forwards; reopen it at the segment holding the target
+                // instead, which downloads just that segment before playing on
+                Some(playlist) => {
+                    let runtime = tokio::runtime::Handle::current();
+                    tokio::task::spawn_blocking(move || {
+                        let reopened =
+                            open_hls_decoder(http, playlist, target, stream_stats, runtime)?;
+                        decoder.replace(reopened);
+                        Ok(())
+                    })
+                }
+                None => tokio::task::spawn_blocking(move || decoder.seek(target)),
             };
+            seek.await
+                .map_err(|e| anyhow!("Seek task panicked: {}", e))??;
 
-            *buffer_health = health;
-
-            if let Some(latency) = latency_ms {
-                // Adjust health based on latency
-                let latency_factor = (100.0 / latency.max(100.0)).min(1.0);
-                *buffer_health *= latency_factor;
+            // A gapless hand-over or a stop may have taken the output meanwhile
+            if clock.active_id() == current.clock_id {
+                let sample_rate = clock.sample_rate();
+                clock.claim(current.clock_id, &current.track_id, target, sample_rate);
             }
+            Ok(())
+        }
+    }
 
-            if *buffer_health < 0.7 {
-                println!("Audio: SSE buffer health: {:.2}, position: {:.2}s, latency: {:?}ms",
-                         *buffer_health, position_seconds, latency_ms);
-            }
-        } else {
-            *buffer_health = 0.9; // First update
+    /// Download and pre-decode the next queued track in the background so the transition
+    /// to it can be gapless
+    fn prepare_track_transition(&mut self, track_id: &str, stream_url: &str) {
+        let ready = self.prefetched.try_lock().is_ok_and(|slot| {
+            slot.as_ref()
+                .is_some_and(|prefetched| prefetched.track_id == track_id)
+        });
+        let in_flight = self.prefetch_track_id.as_deref() == Some(track_id)
+            && self
+                .prefetch_task
+                .as_ref()
+                .is_some_and(|task| !task.is_finished());
+        if ready || in_flight {
+            return;
         }
 
-        Ok(())
-    }
+        // A previously prefetched track stays in the slot until the new one is ready, since
+        // it may be the track the backend is switching to right now
+        if let Some(handle) = self.prefetch_task.take() {
+            handle.abort();
+        }
+        self.prefetch_track_id = Some(track_id.to_string());
+
+        println!("Audio: Prefetching next track {}", track_id);
+
+        let http = self.http.clone();
+        let cache = self.cache.clone();
+        let loudness = self.loudness.clone();
+        let slot = self.prefetched.clone();
+        let track_id = track_id.to_string();
+        let url = stream_url.to_string();
+
+        self.prefetch_task = Some(tokio::spawn(async move {
+            // Prefer the on-disk cache; otherwise download the whole track and keep it there
+            let data = match cache.lookup(&track_id) {
+                Some(path) => TrackData::File(path),
+                None => {
+                    let (data, content_type) = match fetch_full_stream(&http, &url, &track_id).await
+                    {
+                        Ok(fetched) => fetched,
+                        Err(e) => {
+                            println!("Audio: Prefetch of {} failed: {}", track_id, e);
+                            return;
+                        }
+                    };
+
+                    let cache = cache.clone();
+                    let id = track_id.clone();
+                    let cached_data = data.clone();
+                    tokio::task::spawn_blocking(move || {
+                        if let Err(e) = cache.store(&id, &cached_data, content_type.as_deref()) {
+                            println!("Cache: Failed to store {}: {}", id, e);
+                        }
+                    });
+                    TrackData::Memory(data)
+                }
+            };
 
-    /// Prepare buffer for upcoming track transition
-    pub async fn prepare_track_transition(&self, next_track_url: &str, start_position: f64) -> Result<()> {
-        println!("Audio: Preparing buffer for track transition at position {:.2}s", start_position);
+            // Measure before publishing so the gain is known when the track starts
+            let id = track_id.clone();
+            let decoded = tokio::task::spawn_blocking(move || {
+                let measured = data
+                    .open()
+                    .and_then(|source| loudness.ensure_measured(&id, source));
+                if let Err(e) = measured {
+                    println!("Loudness: Failed to measure {}: {}", id, e);
+                }
+                StreamDecoder::new(data.open()?, 0.0)
+            })
+            .await;
+
+            match decoded {
+                Ok(Ok(source)) => {
+                    println!("Audio: Prefetched {}, ready for gapless start", track_id);
+                    *slot.lock().await = Some(PrefetchedTrack {
+                        track_id,
+                        stream_url: url,
+                        source,
+                    });
+                }
+                Ok(Err(e)) => println!("Audio: Failed to decode prefetched {}: {:#}", track_id, e),
+                Err(e) => println!("Audio: Prefetch decode task panicked: {}", e),
+            }
+        }));
+    }
 
-        // Pre-warm connection and validate stream availability
-        let response = self.http.head(next_track_url).send().await;
-        match response {
-            Ok(resp) if resp.status().is_success() => {
-                println!("Audio: Next track pre-buffering validated");
-                Ok(())
+    /// Continue into the prefetched track on the existing sink. Returns `Ok(false)` when
+    /// nothing usable was prefetched for `track_id` and a fresh stream has to be opened.
+    async fn play_prefetched(&mut self, track_id: &str, start_position: f64) -> Result<bool> {
+        let prefetched = {
+            let mut slot = self.prefetched.lock().await;
+            match slot.take() {
+                Some(prefetched) if prefetched.track_id == track_id => prefetched,
+                other => {
+                    *slot = other;
+                    return Ok(false);
+                }
             }
-            Ok(resp) => {
-                println!("Audio: Next track unavailable: {}", resp.status());
-                Err(anyhow!("Next track unavailable: {}", resp.status()))
+        };
+        self.prefetch_track_id = None;
+        self.prefetch_task = None;
+        self.abandon_open();
+        let soft_paused = self.soft_pause.take().is_some();
+
+        let mut source = prefetched.source;
+        if start_position > PREFETCH_SEEK_TOLERANCE_SECS {
+            source.seek(start_position)?;
+        }
+        let remaining = source
+            .total_duration()
+            .map(|total| total.saturating_sub(Duration::from_secs_f64(start_position.max(0.0))));
+        let clock_id = self.clock.register();
+        let (source, decoder) = source.shared();
+        let source = ClockedSource::new(
+            self.crossfeed
+                .apply(self.equalizer.apply(self.loudness.apply(track_id, source))),
+            self.clock.clone(),
+            clock_id,
+            track_id,
+            start_position.max(0.0),
+        );
+
+        let now = Instant::now();
+        let current_end = self.playback_end;
+
+        // Only queue behind the current track if it is about to finish anyway; after a skip
+        // the old track may still have minutes left, so start the new one on a fresh sink.
+        // A crossfade needs both tracks on sinks of their own, and a soft-paused sink is
+        // fading out or already paused.
+        let gapless = match (self.sink.as_ref(), current_end) {
+            (Some(playing), Some(end)) => {
+                !playing.sink.empty()
+                    && end.saturating_duration_since(now).as_secs_f64() <= GAPLESS_WINDOW_SECS
+                    && self.fades.crossfade().is_none()
+                    && !soft_paused
             }
-            Err(e) => {
-                println!("Audio: Track transition preparation failed: {}", e);
-                Err(anyhow!("Track transition preparation failed: {}", e))
+            _ => false,
+        };
+
+        let starts_at = if gapless {
+            let playing = self
+                .sink
+                .as_ref()
+                .expect("gapless transition requires a sink");
+            playing.sink.append(MonitoredSource::new(
+                self.mixer
+                    .apply(self.spectrum.tap(playing.fader.apply(source))),
+                self.output_monitor.clone(),
+            ));
+            playing.sink.play();
+            current_end.unwrap_or(now).max(now)
+        } else {
+            let (fade_out, fade_in) = self.transition_fades(track_id);
+            if let Some(playing) = self.sink.take() {
+                playing.retire(fade_out);
             }
+            let sink =
+                Sink::try_new(&self.audio.stream_handle).context("Failed to create audio sink")?;
+            let fader = Fader::new(0.0);
+            sink.set_volume(self.mixer.output_volume(self.volume));
+            sink.append(MonitoredSource::new(
+                self.mixer.apply(self.spectrum.tap(fader.apply(source))),
+                self.output_monitor.clone(),
+            ));
+            fader.fade_to(1.0, fade_in);
+            sink.play();
+            self.sink = Some(PlayingSink { sink, fader });
+            now
+        };
+
+        self.playback_end = remaining.map(|d| starts_at + d);
+        self.current_stream = Some(CurrentStream {
+            track_id: track_id.to_string(),
+            stream_url: prefetched.stream_url,
+            clock_id,
+            decoder,
+            hls: None,
+        });
+        self.status.send_modify(|status| {
+            status.last_sync_position = Some(start_position);
+            status.last_sync_at = Some(now);
+        });
+        self.stream_stats.reset(false);
+
+        println!(
+            "Audio: Started prefetched track {} ({})",
+            track_id,
+            if gapless { "gapless" } else { "fresh sink" }
+        );
+
+        Ok(true)
+    }
+}
+
+impl StreamOpener {
+    /// Playback position the server reports for the current track, or 0 if it cannot be read
+    async fn fetch_server_position(&self, stream_url: &str) -> Result<f64> {
+        let position_url = if stream_url.contains("/api/music/stream") {
+            stream_url.replace("/api/music/stream", "/api/music/position")
+        } else {
+            // Fallback: assume we can find the base URL
+            let base = stream_url.split("/api/").next().unwrap_or(stream_url);
+            format!("{}/api/music/position", base.trim_end_matches('/'))
+        };
+
+        // Remove any query parameters for position request
+        let position_url = position_url.split('?').next().unwrap_or(&position_url);
+
+        match self.http.get(position_url).send().await {
+            Ok(response) => match response.json::<serde_json::Value>().await {
+                Ok(data) => Ok(data["position"].as_f64().unwrap_or(0.0)),
+                Err(_e) => Ok(0.0),
+            },
+            Err(_e) => Ok(0.0),
         }
     }
 
-    /// Get buffer health metrics for monitoring
-    pub async fn get_buffer_metrics(&self) -> Result<BufferMetrics> {
-        let last_update = self.last_sse_update.lock().await;
-        let last_position = self.last_sse_position.lock().await;
-        let buffer_health = self.buffer_health.lock().await;
+    /// Open `track_id` (from the cache when possible) at `target_position` and queue it on a
+    /// paused sink
+    async fn open(
+        &self,
+        track_id: &str,
+        stream_url: &str,
+        target_position: f64,
+    ) -> Result<PreparedStream> {
+        let cached_path = self.cache.lookup(track_id);
+
+        // Create stream reader and decoder; the decoder seeks through the MP4 sample table
+        // so the reader reopens its HTTP range right at the target packet
+        let runtime_handle = tokio::runtime::Handle::current();
+        let http_client = self.http.clone();
+        let audio_components = self.audio.clone();
+        let output_monitor = self.output_monitor.clone();
+        let equalizer = self.equalizer.clone();
+        let loudness = self.loudness.clone();
+        let crossfeed = self.crossfeed.clone();
+        let clock = self.clock.clone();
+        let clock_id = self.clock.register();
+        let fader = Fader::new(0.0);
+        let source_fader = fader.clone();
+        let spectrum = self.spectrum.clone();
+        let mixer = self.mixer.clone();
+        let track_id_owned = track_id.to_string();
+        let start_seconds = target_position.max(0.0);
+        let measure_path = cached_path
+            .clone()
+            .filter(|_| self.loudness.get(track_id).is_none());
+        let cached = cached_path.is_some();
+
+        let stream_stats = self.stream_stats.clone();
+        stream_stats.reset(!cached);
+
+        let playlist = match (cached, self.transport) {
+            (false, StreamTransport::Hls) => {
+                match self.fetch_hls_playlist(track_id, stream_url).await {
+                    Ok(playlist) => Some(Arc::new(playlist)),
+                    Err(e) => {
+                        println!("HLS: {}, falling back to progressive stream", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
 
-        let sse_age_ms = last_update
-            .map(|inst| inst.elapsed().as_millis() as f64)
-            .unwrap_or(f64::INFINITY);
+        // The HTTP reader's first request and fetch task belong on the runtime, so open it
+        // here rather than inside the blocking decoder setup
+        let cache_separately = !cached && playlist.is_some();
+        let source = match (cached_path, playlist) {
+            (Some(path), _) => StreamSource::Cached(path),
+            (None, Some(playlist)) => StreamSource::Hls(playlist),
+            (None, None) => {
+                // Every play streams through a fresh token, so the URL needs no cache-busting
+                let token =
+                    StreamToken::from_url(self.http.clone(), stream_url, track_id).map(Arc::new);
+                let reader = HttpStreamReader::open(
+                    self.stream_http.clone(),
+                    stream_url.to_string(),
+                    token,
+                    stream_stats.clone(),
+                    self.read_ahead.clone(),
+                    0,
+                    self.begin_cache_fill(track_id),
+                )
+                .await?;
+                StreamSource::Http(reader)
+            }
+        };
 
-        let is_active = last_position.is_some() && sse_age_ms < 10000.0;
-        let health_score = if sse_age_ms < 5000.0 { *buffer_health } else { 0.5 };
+        let (sink, remaining, decoder, hls) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let mut playlist_remaining = None;
+            let mut hls = None;
 
-        Ok(BufferMetrics {
-            is_active,
-            sse_age_ms,
-            estimated_buffer_health: health_score,
-            last_position: *last_position,
+            let decoder = match source {
+                StreamSource::Cached(path) => {
+                    StreamDecoder::new(TrackData::File(path).open()?, start_seconds)?
+                }
+                StreamSource::Hls(playlist) => {
+                    // The ADTS stream carries no duration, so HLS takes it from the playlist
+                    playlist_remaining = Some(Duration::from_secs_f64(
+                        (playlist.total_duration() - start_seconds).max(0.0),
+                    ));
+
+                    hls = Some(playlist.clone());
+                    open_hls_decoder(
+                        http_client,
+                        playlist,
+                        start_seconds,
+                        stream_stats.clone(),
+                        runtime_handle,
+                    )?
+                }
+                StreamSource::Http(reader) => {
+                    let content_type = reader.content_type().map(str::to_string);
+                    StreamDecoder::with_content_type(
+                        Box::new(reader),
+                        content_type.as_deref(),
+                        start_seconds,
+                    )?
+                }
+            };
+
+            stream_stats.set_duration(decoder.total_duration());
+            let remaining = decoder
+                .total_duration()
+                .map(|total| total.saturating_sub(Duration::from_secs_f64(start_seconds)))
+                .or(playlist_remaining);
+
+            let sink = Sink::try_new(&audio_components.stream_handle)
+                .context("Failed to create audio sink")?;
+
+            let (decoder, handle) = decoder.shared();
+            let normalized = loudness.apply(&track_id_owned, decoder);
+            let clocked = ClockedSource::new(
+                crossfeed.apply(equalizer.apply(normalized)),
+                clock,
+                clock_id,
+                &track_id_owned,
+                start_seconds,
+            );
+            sink.pause();
+            sink.append(MonitoredSource::new(
+                mixer.apply(spectrum.tap(source_fader.apply(clocked))),
+                output_monitor,
+            ));
+
+            Ok((sink, remaining, handle, hls))
         })
+        .await
+        .map_err(|err| anyhow!("Audio initialization task panicked: {}", err))??;
+
+        Ok(PreparedStream {
+            sink,
+            fader,
+            decoder,
+            hls,
+            remaining,
+            clock_id,
+            cached,
+            cache_separately,
+            measure_path,
+        })
+    }
+
+    /// Have the stream copied into the cache as it downloads, measuring its loudness once
+    /// it is stored
+    fn begin_cache_fill(&self, track_id: &str) -> Option<CacheFill> {
+        let (fill, stored) = self.cache.begin_fill(track_id)?;
+        let loudness = self.loudness.clone();
+        let track_id = track_id.to_string();
+
+        tokio::spawn(async move {
+            let Ok(path) = stored.await else {
+                return;
+            };
+            tokio::task::spawn_blocking(move || {
+                let measured = TrackData::File(path)
+                    .open()
+                    .and_then(|source| loudness.ensure_measured(&track_id, source));
+                if let Err(e) = measured {
+                    println!("Loudness: Failed to measure {}: {}", track_id, e);
+                }
+            });
+        });
+        Some(fill)
+    }
+
+    async fn fetch_hls_playlist(&self, track_id: &str, stream_url: &str) -> Result<HlsPlaylist> {
+        let url = HlsPlaylist::url_for(stream_url, track_id)
+            .ok_or_else(|| anyhow!("Cannot derive HLS playlist from {}", stream_url))?;
+        let playlist = HlsPlaylist::fetch(&self.http, &url).await?;
+        println!(
+            "HLS: Playlist for {} has {} segments ({:.0}s)",
+            track_id,
+            playlist.segments.len(),
+            playlist.total_duration()
+        );
+        Ok(playlist)
     }
+}
 
-    // Removed track end callback methods - track advancement now handled via SSE events
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct BufferMetrics {
-    pub is_active: bool,
-    pub sse_age_ms: f64,
-    pub estimated_buffer_health: f64,
+    /// Playback is fed from the network rather than the local cache or a prefetched track
+    pub streaming: bool,
+    /// Playback is blocked waiting for data right now
+    pub stalled: bool,
+    pub buffered_bytes: u64,
+    pub buffered_seconds: Option<f64>,
+    pub download_complete: bool,
+    pub underruns: u32,
+    pub reconnects: u32,
+    pub throughput_bytes_per_sec: f64,
+    pub buffer_health: f64,
+    /// Current download window, adapted to network conditions
+    pub read_ahead_secs: f64,
+    pub sse_age_ms: Option<f64>,
     pub last_position: Option<f64>,
 }
 
-fn parse_content_range(header: Option<&HeaderValue>) -> Option<u64> {
-    let header_str = header?.to_str().ok()?;
-    // Expected format: bytes start-end/total
-    let parts: Vec<&str> = header_str.split('/').collect();
-    if parts.len() != 2 {
-        return None;
+/// Decoder for HLS playback from track position `position`. Only the segment holding it
+/// and those after it are downloaded; the decoder's timeline starts at that segment.
+fn open_hls_decoder(
+    client: Client,
+    playlist: Arc<HlsPlaylist>,
+    position: f64,
+    stats: Arc<StreamStats>,
+    runtime: tokio::runtime::Handle,
+) -> Result<StreamDecoder> {
+    let index = playlist.segment_at(position);
+    let segment_offset = position - playlist.segments[index].start;
+    let reader = HlsStreamReader::new(client, playlist, index, stats, runtime);
+    StreamDecoder::with_format(Box::new(reader), MediaFormat::Adts, segment_offset)
+}
+
+/// Download a whole stream, along with the `Content-Type` it was served with. Streams over
+/// `MAX_PREFETCH_BYTES` are refused, so the track opens like any other when it starts.
+async fn fetch_full_stream(
+    client: &Client,
+    url: &str,
+    track_id: &str,
+) -> Result<(Bytes, Option<String>)> {
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| anyhow!("HTTP stream error: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "HTTP stream responded with status {}",
+            response.status()
+        ));
     }
 
-    parts[1].trim().parse::<u64>().ok()
-}
+    // The backend tags every stream with the track it serves; refuse to cache the wrong audio
+    if let Some(served_id) = response
+        .headers()
+        .get("x-track-id")
+        .and_then(|value| value.to_str().ok())
+    {
+        if served_id != track_id {
+            return Err(anyhow!(
+                "Backend served track {} instead of {}",
+                served_id,
+                track_id
+            ));
+        }
+    }
 
-// Old decode_audio function removed - now using HTTP streaming directly to rodio
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let too_large = || {
+        anyhow!(
+            "Stream is larger than the {} MiB a prefetch holds",
+            MAX_PREFETCH_BYTES / (1024 * 1024)
+        )
+    };
+    let declared = response.content_length().unwrap_or(0) as usize;
+    if declared > MAX_PREFETCH_BYTES {
+        return Err(too_large());
+    }
+
+    let mut data = BytesMut::with_capacity(declared);
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| anyhow!("Failed to download stream: {}", e))?
+    {
+        if data.len() + chunk.len() > MAX_PREFETCH_BYTES {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok((data.freeze(), content_type))
+}
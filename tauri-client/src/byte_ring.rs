@@ -0,0 +1,393 @@
+use std::cell::UnsafeCell;
+use std::io::{Error as IoError, ErrorKind};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+const RING_CAPACITY: usize = 4 * 1024 * 1024;
+
+/// Fixed-size window over a byte stream, filled by one async writer and drained by one
+/// blocking reader.
+///
+/// Positions are absolute stream offsets. The writer appends at `end`; the reader raises
+/// `floor` behind itself once it no longer needs the data, which frees that space for the
+/// writer. Everything in `floor..end` can be read at any time, so the reader can move around
+/// inside it freely. Reading is lock-free: `read_at` and `release` only go through the two
+/// atomic offsets. Writes take `write_lock`, which only a `reset` ever contends for, and
+/// briefly take `wait_lock` to wake a reader parked in `wait_for_data`.
+///
+/// The reader can `reset` the ring to start over at another offset, e.g. after a seek. Every
+/// reset starts a new epoch, and writes made for an earlier epoch are dropped, so a writer
+/// still busy with the old range can't put its bytes at the new offset.
+pub struct ByteRing {
+    data: Box<[UnsafeCell<u8>]>,
+    floor: AtomicU64,
+    end: AtomicU64,
+    finished: AtomicBool,
+    epoch: AtomicU64,
+    // Held while writing or resetting, so a reset never lands in the middle of a write
+    write_lock: Mutex<()>,
+    error: Mutex<Option<FinishError>>,
+    wait_lock: Mutex<()>,
+    data_ready: Condvar,
+    space_ready: Notify,
+}
+
+/// Error a ring was finished with; `io::Error` can't be cloned, so only the first reader
+/// to ask gets the original
+struct FinishError {
+    kind: ErrorKind,
+    message: String,
+    original: Option<IoError>,
+}
+
+// The writer only touches bytes at or past `end`, the reader only bytes below it, and the
+// writer never laps `floor`, so the two sides never access the same byte at the same time.
+unsafe impl Sync for ByteRing {}
+
+impl ByteRing {
+    /// Empty ring whose first byte will be the stream byte at `offset`
+    pub fn new(offset: u64) -> Self {
+        Self {
+            data: (0..RING_CAPACITY).map(|_| UnsafeCell::new(0)).collect(),
+            floor: AtomicU64::new(offset),
+            end: AtomicU64::new(offset),
+            finished: AtomicBool::new(false),
+            epoch: AtomicU64::new(0),
+            write_lock: Mutex::new(()),
+            error: Mutex::new(None),
+            wait_lock: Mutex::new(()),
+            data_ready: Condvar::new(),
+            space_ready: Notify::new(),
+        }
+    }
+
+    /// Offset just past the last byte written
+    pub fn end(&self) -> u64 {
+        self.end.load(Ordering::Acquire)
+    }
+
+    /// True if `position` lies within the data still held, or right at its end
+    pub fn contains(&self, position: u64) -> bool {
+        position >= self.floor.load(Ordering::Relaxed) && position <= self.end()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    /// Range the ring currently holds data for; raised by every `reset`
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Reader side: drop everything held and start over empty at `offset`. Returns the new
+    /// epoch, which the writer has to write for from now on.
+    pub fn reset(&self, offset: u64) -> u64 {
+        let _guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *self.error.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        self.floor.store(offset, Ordering::Release);
+        self.end.store(offset, Ordering::Release);
+        self.finished.store(false, Ordering::Release);
+        let epoch = self.epoch.fetch_add(1, Ordering::AcqRel) + 1;
+        // A writer waiting for space in the old range has to find out it is stale
+        self.space_ready.notify_one();
+        epoch
+    }
+
+    /// Writer side: append as much of `bytes` as fits and return how much that was
+    pub fn write(&self, bytes: &[u8]) -> usize {
+        self.write_range(self.epoch(), bytes).unwrap_or(0)
+    }
+
+    /// Writer side: like `write`, for the range of `epoch`. Returns `None` once the ring was
+    /// reset for a newer range.
+    pub fn write_range(&self, epoch: u64, bytes: &[u8]) -> Option<usize> {
+        let _guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.epoch() != epoch {
+            return None;
+        }
+
+        let end = self.end.load(Ordering::Relaxed);
+        let floor = self.floor.load(Ordering::Acquire);
+        let free = (floor + RING_CAPACITY as u64 - end) as usize;
+        let count = bytes.len().min(free);
+        if count == 0 {
+            return Some(0);
+        }
+
+        let index = (end % RING_CAPACITY as u64) as usize;
+        let first = count.min(RING_CAPACITY - index);
+        let base = UnsafeCell::raw_get(self.data.as_ptr());
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), base.add(index), first);
+            ptr::copy_nonoverlapping(bytes.as_ptr().add(first), base, count - first);
+        }
+
+        self.end.store(end + count as u64, Ordering::Release);
+        self.wake_reader();
+        Some(count)
+    }
+
+    /// Writer side: wait until the reader has released some space
+    pub async fn wait_for_space(&self) {
+        self.space_ready.notified().await;
+    }
+
+    /// Writer side: no more data will follow; `error` is handed to the reader once it has
+    /// consumed everything written so far
+    pub fn finish(&self, error: Option<IoError>) {
+        self.finish_range(self.epoch(), error);
+    }
+
+    /// Writer side: like `finish`, for the range of `epoch`; ignored once the ring was reset
+    /// for a newer range
+    pub fn finish_range(&self, epoch: u64, error: Option<IoError>) {
+        let _guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.epoch() != epoch {
+            return;
+        }
+
+        if let Some(error) = error {
+            *self.error.lock().unwrap_or_else(PoisonError::into_inner) = Some(FinishError {
+                kind: error.kind(),
+                message: error.to_string(),
+                original: Some(error),
+            });
+        }
+        self.finished.store(true, Ordering::Release);
+        self.wake_reader();
+    }
+
+    /// Reader side: copy bytes starting at `position` into `buf`. Returns 0 if nothing past
+    /// `position` has been written yet or the position is no longer held.
+    pub fn read_at(&self, position: u64, buf: &mut [u8]) -> usize {
+        let end = self.end.load(Ordering::Acquire);
+        if position < self.floor.load(Ordering::Relaxed) || position >= end {
+            return 0;
+        }
+
+        let count = buf.len().min((end - position) as usize);
+        let index = (position % RING_CAPACITY as u64) as usize;
+        let first = count.min(RING_CAPACITY - index);
+        let base = UnsafeCell::raw_get(self.data.as_ptr()) as *const u8;
+        unsafe {
+            ptr::copy_nonoverlapping(base.add(index), buf.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(base, buf.as_mut_ptr().add(first), count - first);
+        }
+        count
+    }
+
+    /// Reader side: data before `position` will not be read again and may be overwritten
+    pub fn release(&self, position: u64) {
+        let position = position.min(self.end());
+        if position > self.floor.load(Ordering::Relaxed) {
+            self.floor.store(position, Ordering::Release);
+            self.space_ready.notify_one();
+        }
+    }
+
+    /// Reader side: block until data past `position` arrives or the writer finishes. Returns
+    /// false if `timeout` passed first.
+    pub fn wait_for_data(&self, position: u64, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self
+            .wait_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        loop {
+            if self.end() > position || self.is_finished() {
+                return true;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            guard = self
+                .data_ready
+                .wait_timeout(guard, deadline - now)
+                .map(|(guard, _)| guard)
+                .unwrap_or_else(|e| e.into_inner().0);
+        }
+    }
+
+    /// Reader side: the error the writer finished with, if any. The first call gets the
+    /// original error with its cause; later calls get a copy of its kind and message.
+    pub fn error(&self) -> Option<IoError> {
+        let mut error = self.error.lock().unwrap_or_else(PoisonError::into_inner);
+        let error = error.as_mut()?;
+        Some(
+            error
+                .original
+                .take()
+                .unwrap_or_else(|| IoError::new(error.kind, error.message.clone())),
+        )
+    }
+
+    fn wake_reader(&self) {
+        // Taking the lock orders this wake-up after the reader's last check of `end`
+        let _guard = self
+            .wait_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.data_ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn pattern(offset: u64, len: usize) -> Vec<u8> {
+        (offset..offset + len as u64)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn write_stops_at_capacity_and_wraps_after_release() {
+        let ring = ByteRing::new(0);
+        assert_eq!(ring.write(&pattern(0, RING_CAPACITY)), RING_CAPACITY);
+        assert_eq!(ring.write(&pattern(RING_CAPACITY as u64, 10)), 0);
+
+        ring.release(1000);
+        let end = RING_CAPACITY as u64;
+        assert_eq!(ring.write(&pattern(end, 2000)), 1000);
+        assert_eq!(ring.end(), end + 1000);
+
+        // Spans the seam where the writer wrapped back to the start of the buffer
+        let mut buf = [0u8; 64];
+        assert_eq!(ring.read_at(end - 32, &mut buf), 64);
+        assert_eq!(buf.to_vec(), pattern(end - 32, 64));
+    }
+
+    #[test]
+    fn release_drops_data_behind_the_position() {
+        let ring = ByteRing::new(500);
+        ring.write(&pattern(500, 100));
+
+        ring.release(550);
+        let mut buf = [0u8; 10];
+        assert_eq!(ring.read_at(549, &mut buf), 0);
+        assert_eq!(ring.read_at(550, &mut buf), 10);
+        assert_eq!(buf.to_vec(), pattern(550, 10));
+
+        // Releasing never moves past what was written, nor back
+        ring.release(10_000);
+        assert!(ring.contains(600));
+        assert!(!ring.contains(599));
+        ring.release(0);
+        assert!(!ring.contains(599));
+    }
+
+    #[test]
+    fn contains_covers_held_data_and_its_end() {
+        let ring = ByteRing::new(1000);
+        assert!(ring.contains(1000));
+        assert!(!ring.contains(999));
+
+        ring.write(&pattern(1000, 50));
+        assert!(ring.contains(1000));
+        assert!(ring.contains(1050));
+        assert!(!ring.contains(1051));
+    }
+
+    #[test]
+    fn reset_starts_over_and_drops_writes_for_the_old_range() {
+        let ring = ByteRing::new(0);
+        let old = ring.epoch();
+        ring.write(&pattern(0, 100));
+        ring.finish(Some(IoError::other("old range failed")));
+
+        let epoch = ring.reset(5000);
+        assert_ne!(epoch, old);
+        assert!(!ring.is_finished());
+        assert!(ring.error().is_none());
+        assert!(ring.contains(5000));
+        assert!(!ring.contains(50));
+
+        assert_eq!(ring.write_range(old, &pattern(100, 10)), None);
+        ring.finish_range(old, None);
+        assert!(!ring.is_finished());
+        assert_eq!(ring.end(), 5000);
+
+        assert_eq!(ring.write_range(epoch, &pattern(5000, 10)), Some(10));
+        let mut buf = [0u8; 10];
+        assert_eq!(ring.read_at(5000, &mut buf), 10);
+        assert_eq!(buf.to_vec(), pattern(5000, 10));
+    }
+
+    #[test]
+    fn finish_hands_the_original_error_out_first() {
+        let ring = ByteRing::new(0);
+        ring.finish(Some(IoError::new(ErrorKind::ConnectionReset, "reset")));
+        assert!(ring.is_finished());
+        assert!(ring.wait_for_data(0, Duration::ZERO));
+
+        let first = ring.error().unwrap();
+        assert_eq!(first.kind(), ErrorKind::ConnectionReset);
+        let second = ring.error().unwrap();
+        assert_eq!(second.kind(), ErrorKind::ConnectionReset);
+        assert_eq!(second.to_string(), "reset");
+    }
+
+    #[test]
+    fn single_writer_single_reader_pass_every_byte_in_order() {
+        const TOTAL: usize = 3 * RING_CAPACITY + 12_345;
+        let ring = Arc::new(ByteRing::new(0));
+
+        let writer_ring = ring.clone();
+        let writer = thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let mut written = 0;
+                while written < TOTAL {
+                    let len = (TOTAL - written).min(70_001);
+                    let count = writer_ring.write(&pattern(written as u64, len));
+                    if count == 0 {
+                        writer_ring.wait_for_space().await;
+                    }
+                    written += count;
+                }
+                writer_ring.finish(None);
+            });
+        });
+
+        let mut position = 0u64;
+        let mut buf = vec![0u8; 50_003];
+        loop {
+            let read = ring.read_at(position, &mut buf);
+            if read == 0 {
+                assert!(ring.wait_for_data(position, Duration::from_secs(10)));
+                if ring.read_at(position, &mut buf[..1]) == 0 && ring.is_finished() {
+                    break;
+                }
+                continue;
+            }
+            assert_eq!(buf[..read], pattern(position, read)[..]);
+            position += read as u64;
+            ring.release(position);
+        }
+
+        writer.join().unwrap();
+        assert_eq!(position, TOTAL as u64);
+        assert!(ring.error().is_none());
+    }
+}
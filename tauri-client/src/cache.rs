@@ -0,0 +1,594 @@
+use crate::config::AppConfig;
+use crate::media_format::{MediaFormat, SNIFF_LEN};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::oneshot;
+
+// Extension for entries whose container was not recognized; the decoder probes those
+const UNKNOWN_FORMAT_EXTENSION: &str = "bin";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheUsage {
+    pub path: String,
+    pub entries: usize,
+    pub used_bytes: u64,
+    pub max_bytes: u64,
+}
+
+/// Size-limited on-disk LRU cache of completed audio streams, keyed by youtube id.
+///
+/// Recency is tracked through file modification times: a hit touches the file, and
+/// eviction removes the oldest files first until the cache fits its limit again. Each file
+/// is named after the container found in its own bytes, whatever the server claimed.
+pub struct AudioCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    // Tracks held by a `CacheClaim`, so a track is only downloaded and written once at a time
+    filling: Mutex<HashSet<String>>,
+}
+
+impl AudioCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self {
+            dir,
+            max_bytes,
+            filling: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self::new(
+            config.audio_cache_dir(),
+            config.cache_max_mb.saturating_mul(1024 * 1024),
+        )
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// Path of the cached stream for `youtube_id`, if present. Marks the entry as recently used.
+    pub fn lookup(&self, youtube_id: &str) -> Option<PathBuf> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let path = self.find_entry(youtube_id)?;
+
+        if let Ok(file) = File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Some(path)
+    }
+
+    pub fn contains(&self, youtube_id: &str) -> bool {
+        self.find_entry(youtube_id).is_some()
+    }
+
+    /// Store a completed stream and evict old entries if the cache grew past its limit.
+    /// `content_type` is what the server declared, for data whose first bytes don't tell.
+    /// Skipped while the track is already being written elsewhere.
+    pub fn store(
+        self: &Arc<Self>,
+        youtube_id: &str,
+        data: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<()> {
+        match self.claim(youtube_id) {
+            Some(claim) => claim.store(data, content_type),
+            None => Ok(()),
+        }
+    }
+
+    /// Reserve `youtube_id` for a writer that downloads it before storing it. `None` if the
+    /// cache is off, already holds the track or another writer has it; the reservation ends
+    /// when the claim is dropped.
+    pub fn claim(self: &Arc<Self>, youtube_id: &str) -> Option<CacheClaim> {
+        if !self.is_enabled() || self.contains(youtube_id) {
+            return None;
+        }
+
+        self.entry_path(youtube_id, UNKNOWN_FORMAT_EXTENSION)?;
+        let claimed = self
+            .filling
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(youtube_id.to_string());
+
+        claimed.then(|| CacheClaim {
+            cache: self.clone(),
+            youtube_id: youtube_id.to_string(),
+        })
+    }
+
+    fn write_entry(&self, youtube_id: &str, data: &[u8], content_type: Option<&str>) -> Result<()> {
+        if data.len() as u64 > self.max_bytes {
+            return Err(anyhow!(
+                "Stream for {} is larger than the whole cache",
+                youtube_id
+            ));
+        }
+
+        let tmp_path = self
+            .tmp_path(youtube_id, "store")
+            .ok_or_else(|| anyhow!("Invalid track id for cache: {}", youtube_id))?;
+
+        // Write to a temporary file first so a crash never leaves a truncated entry behind
+        let stored = fs::create_dir_all(&self.dir)
+            .map_err(|e| anyhow!("Failed to create cache directory: {}", e))
+            .and_then(|_| {
+                fs::write(&tmp_path, data)
+                    .map_err(|e| anyhow!("Failed to write cache entry: {}", e))
+            })
+            .and_then(|_| self.finalize(youtube_id, &tmp_path, content_type));
+        if stored.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        stored.map(|_| ())
+    }
+
+    /// Start copying a stream for `youtube_id` into the cache as it downloads. `None` if the
+    /// cache is off, already holds the track or is being filled with it elsewhere. The
+    /// receiver gets the entry's path once the fill has been committed.
+    pub fn begin_fill(
+        self: &Arc<Self>,
+        youtube_id: &str,
+    ) -> Option<(CacheFill, oneshot::Receiver<PathBuf>)> {
+        let claim = self.claim(youtube_id)?;
+        let tmp_path = self.tmp_path(youtube_id, "fill")?;
+
+        let file = fs::create_dir_all(&self.dir).and_then(|_| File::create(&tmp_path));
+        let file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                println!("Cache: Failed to create {}: {}", tmp_path.display(), e);
+                return None;
+            }
+        };
+
+        let (stored, stored_rx) = oneshot::channel();
+        let fill = CacheFill {
+            claim,
+            content_type: None,
+            tmp_path,
+            file: tokio::fs::File::from_std(file),
+            written: Vec::new(),
+            stored: Some(stored),
+        };
+        Some((fill, stored_rx))
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        let entries = self.entries();
+        CacheUsage {
+            path: self.dir.to_string_lossy().into_owned(),
+            entries: entries.len(),
+            used_bytes: entries.iter().map(|entry| entry.size).sum(),
+            max_bytes: self.max_bytes,
+        }
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        for entry in self.entries() {
+            fs::remove_file(&entry.path)
+                .map_err(|e| anyhow!("Failed to remove {}: {}", entry.path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    fn entry_path(&self, youtube_id: &str, extension: &str) -> Option<PathBuf> {
+        let valid = !youtube_id.is_empty()
+            && youtube_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if !valid {
+            return None;
+        }
+
+        Some(self.dir.join(format!("{}.{}", youtube_id, extension)))
+    }
+
+    /// Temporary file `youtube_id` is written to before it becomes an entry
+    fn tmp_path(&self, youtube_id: &str, kind: &str) -> Option<PathBuf> {
+        self.entry_path(youtube_id, &format!("{}.part", kind))
+    }
+
+    /// Move a completely written temporary file into place as the entry for `youtube_id`
+    fn finalize(
+        &self,
+        youtube_id: &str,
+        tmp_path: &Path,
+        content_type: Option<&str>,
+    ) -> Result<PathBuf> {
+        let mut header = Vec::with_capacity(SNIFF_LEN);
+        File::open(tmp_path)
+            .and_then(|file| file.take(SNIFF_LEN as u64).read_to_end(&mut header))
+            .map_err(|e| anyhow!("Failed to read cache entry: {}", e))?;
+
+        let path = self
+            .entry_path(youtube_id, entry_extension(&header, content_type))
+            .ok_or_else(|| anyhow!("Invalid track id for cache: {}", youtube_id))?;
+
+        self.remove_entry(youtube_id);
+        fs::rename(tmp_path, &path)
+            .map_err(|e| anyhow!("Failed to finalize cache entry: {}", e))?;
+
+        self.evict_to_limit();
+        Ok(path)
+    }
+
+    fn release_claim(&self, youtube_id: &str) {
+        self.filling
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(youtube_id);
+    }
+
+    /// Cached file for `youtube_id`, whichever container it holds
+    fn find_entry(&self, youtube_id: &str) -> Option<PathBuf> {
+        cache_extensions()
+            .filter_map(|extension| self.entry_path(youtube_id, extension))
+            .find(|path| path.is_file())
+    }
+
+    /// Drop an existing entry for `youtube_id`, which may be stored under another extension
+    fn remove_entry(&self, youtube_id: &str) {
+        while let Some(path) = self.find_entry(youtube_id) {
+            if let Err(e) = fs::remove_file(&path) {
+                println!("Cache: Failed to replace {}: {}", path.display(), e);
+                return;
+            }
+        }
+    }
+
+    fn entries(&self) -> Vec<CacheEntry> {
+        let Ok(read_dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        read_dir
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| is_cache_file(&entry.path()))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some(CacheEntry {
+                    path: entry.path(),
+                    size: metadata.len(),
+                    last_used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                })
+            })
+            .collect()
+    }
+
+    fn evict_to_limit(&self) {
+        let mut entries = self.entries();
+        let mut used: u64 = entries.iter().map(|entry| entry.size).sum();
+        if used <= self.max_bytes {
+            return;
+        }
+
+        entries.sort_by_key(|entry| entry.last_used);
+
+        for entry in entries {
+            if used <= self.max_bytes {
+                break;
+            }
+
+            match fs::remove_file(&entry.path) {
+                Ok(()) => {
+                    used = used.saturating_sub(entry.size);
+                    println!("Cache: Evicted {}", entry.path.display());
+                }
+                Err(e) => println!("Cache: Failed to evict {}: {}", entry.path.display(), e),
+            }
+        }
+    }
+}
+
+/// A track reserved for one writer, see `AudioCache::claim`
+pub struct CacheClaim {
+    cache: Arc<AudioCache>,
+    youtube_id: String,
+}
+
+impl CacheClaim {
+    /// Store the completed stream for the claimed track, like `AudioCache::store`
+    pub fn store(self, data: &[u8], content_type: Option<&str>) -> Result<()> {
+        self.cache.write_entry(&self.youtube_id, data, content_type)
+    }
+}
+
+impl Drop for CacheClaim {
+    fn drop(&mut self) {
+        self.cache.release_claim(&self.youtube_id);
+    }
+}
+
+/// Copy of a stream written into the cache while it plays.
+///
+/// Bytes land at their own offsets, so a seek that reopens the stream further on only leaves
+/// a gap; `missing` lists the gaps to download before `commit` turns the file into a cache
+/// entry. A fill dropped before it was committed deletes its file.
+pub struct CacheFill {
+    claim: CacheClaim,
+    content_type: Option<String>,
+    tmp_path: PathBuf,
+    file: tokio::fs::File,
+    // Sorted, non-overlapping byte ranges written so far
+    written: Vec<Range<u64>>,
+    // Taken once committed
+    stored: Option<oneshot::Sender<PathBuf>>,
+}
+
+impl CacheFill {
+    /// `Content-Type` the stream was served with, for data whose first bytes don't tell
+    pub fn set_content_type(&mut self, content_type: Option<String>) {
+        self.content_type = content_type;
+    }
+
+    pub async fn write_at(&mut self, offset: u64, bytes: &[u8]) -> Result<()> {
+        let end = offset + bytes.len() as u64;
+        if end > self.claim.cache.max_bytes {
+            return Err(anyhow!(
+                "Stream for {} is larger than the whole cache",
+                self.claim.youtube_id
+            ));
+        }
+
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| anyhow!("Failed to write cache entry: {}", e))?;
+        self.file
+            .write_all(bytes)
+            .await
+            .map_err(|e| anyhow!("Failed to write cache entry: {}", e))?;
+        self.mark_written(offset..end);
+        Ok(())
+    }
+
+    /// Ranges of a `total_length` byte stream that have not been written yet
+    pub fn missing(&self, total_length: u64) -> Vec<Range<u64>> {
+        let mut missing = Vec::new();
+        let mut position = 0;
+        for range in &self.written {
+            if range.start > position {
+                missing.push(position..range.start.min(total_length));
+            }
+            position = position.max(range.end);
+        }
+        if position < total_length {
+            missing.push(position..total_length);
+        }
+        missing
+    }
+
+    /// Make the written file the cache entry for its track
+    pub async fn commit(mut self) -> Result<PathBuf> {
+        self.file
+            .flush()
+            .await
+            .map_err(|e| anyhow!("Failed to write cache entry: {}", e))?;
+
+        let cache = self.claim.cache.clone();
+        let youtube_id = self.claim.youtube_id.clone();
+        let tmp_path = self.tmp_path.clone();
+        let content_type = self.content_type.clone();
+        let path = tokio::task::spawn_blocking(move || {
+            cache.finalize(&youtube_id, &tmp_path, content_type.as_deref())
+        })
+        .await
+        .map_err(|e| anyhow!("Cache commit task panicked: {}", e))??;
+
+        if let Some(stored) = self.stored.take() {
+            let _ = stored.send(path.clone());
+        }
+        Ok(path)
+    }
+
+    fn mark_written(&mut self, range: Range<u64>) {
+        self.written.push(range);
+        self.written.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(self.written.len());
+        for range in self.written.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.written = merged;
+    }
+}
+
+impl Drop for CacheFill {
+    fn drop(&mut self) {
+        if self.stored.is_some() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    last_used: SystemTime,
+}
+
+fn cache_extensions() -> impl Iterator<Item = &'static str> {
+    MediaFormat::ALL
+        .into_iter()
+        .map(MediaFormat::extension)
+        .chain([UNKNOWN_FORMAT_EXTENSION])
+}
+
+fn is_cache_file(path: &Path) -> bool {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    cache_extensions().any(|candidate| extension == Some(candidate))
+}
+
+/// Extension matching the container of a stream that starts with `header`
+fn entry_extension(header: &[u8], content_type: Option<&str>) -> &'static str {
+    MediaFormat::detect(header, content_type)
+        .map_or(UNKNOWN_FORMAT_EXTENSION, MediaFormat::extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    // Cache directory under the system temp dir, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "miu-cache-test-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn set_last_used(path: &Path, secs: u64) {
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)))
+            .unwrap();
+    }
+
+    fn entry_names(cache: &AudioCache) -> Vec<String> {
+        let mut names: Vec<String> = cache
+            .entries()
+            .iter()
+            .map(|entry| {
+                entry
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn store_evicts_least_recently_used_entries() {
+        let dir = TempDir::new();
+        let cache = Arc::new(AudioCache::new(dir.0.clone(), 10));
+
+        cache.store("a", &[1; 4], None).unwrap();
+        cache.store("b", &[2; 4], None).unwrap();
+        set_last_used(&cache.find_entry("a").unwrap(), 100);
+        set_last_used(&cache.find_entry("b").unwrap(), 200);
+
+        // A hit makes "a" the most recent entry, leaving "b" to go first
+        assert!(cache.lookup("a").is_some());
+        cache.store("c", &[3; 4], None).unwrap();
+
+        assert_eq!(entry_names(&cache), ["a.bin", "c.bin"]);
+        assert_eq!(cache.usage().used_bytes, 8);
+    }
+
+    #[test]
+    fn store_rejects_streams_larger_than_the_cache() {
+        let dir = TempDir::new();
+        let cache = Arc::new(AudioCache::new(dir.0.clone(), 10));
+
+        assert!(cache.store("a", &[1; 11], None).is_err());
+        assert!(!cache.contains("a"));
+        assert!(entry_names(&cache).is_empty());
+    }
+
+    #[test]
+    fn claim_is_held_by_one_writer_at_a_time() {
+        let dir = TempDir::new();
+        let cache = Arc::new(AudioCache::new(dir.0.clone(), 10));
+
+        let claim = cache.claim("a").unwrap();
+        assert!(cache.claim("a").is_none());
+        assert!(cache.claim("../a").is_none());
+
+        // A store while the track is claimed is skipped
+        cache.store("a", &[1; 4], None).unwrap();
+        assert!(!cache.contains("a"));
+
+        claim.store(&[2; 4], None).unwrap();
+        assert_eq!(fs::read(cache.find_entry("a").unwrap()).unwrap(), [2; 4]);
+        assert!(cache.claim("a").is_none());
+
+        // A disabled cache hands out no claims
+        let disabled = Arc::new(AudioCache::new(dir.0.clone(), 0));
+        assert!(disabled.claim("b").is_none());
+    }
+
+    #[tokio::test]
+    async fn fill_lists_missing_ranges_and_commits_an_entry() {
+        let dir = TempDir::new();
+        let cache = Arc::new(AudioCache::new(dir.0.clone(), 100));
+
+        let (mut fill, stored) = cache.begin_fill("a").unwrap();
+        assert!(cache.begin_fill("a").is_none());
+        assert_eq!(fill.missing(20), [Range { start: 0, end: 20 }]);
+
+        fill.write_at(5, &[5; 5]).await.unwrap();
+        fill.write_at(15, &[15; 3]).await.unwrap();
+        assert_eq!(fill.missing(20), [0..5, 10..15, 18..20]);
+
+        // Overlapping and adjoining writes merge into one range
+        fill.write_at(0, &[0; 6]).await.unwrap();
+        fill.write_at(10, &[10; 5]).await.unwrap();
+        assert_eq!(fill.missing(20), [Range { start: 18, end: 20 }]);
+        fill.write_at(18, &[18; 2]).await.unwrap();
+        assert!(fill.missing(20).is_empty());
+
+        assert!(fill.write_at(95, &[0; 10]).await.is_err());
+
+        let path = fill.commit().await.unwrap();
+        assert_eq!(stored.await.unwrap(), path);
+        assert_eq!(cache.lookup("a"), Some(path.clone()));
+        assert_eq!(fs::read(&path).unwrap().len(), 20);
+        assert_eq!(entry_names(&cache), ["a.bin"]);
+        assert!(cache.begin_fill("b").is_some());
+    }
+
+    #[test]
+    fn dropped_fill_removes_its_file_and_claim() {
+        let dir = TempDir::new();
+        let cache = Arc::new(AudioCache::new(dir.0.clone(), 100));
+
+        let (fill, _stored) = cache.begin_fill("a").unwrap();
+        let tmp_path = fill.tmp_path.clone();
+        assert!(tmp_path.is_file());
+
+        drop(fill);
+        assert!(!tmp_path.exists());
+        assert!(!cache.contains("a"));
+        assert!(cache.claim("a").is_some());
+    }
+}
@@ -0,0 +1,322 @@
+use rodio::Source;
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Corrections up to this size are spread out by dropping or repeating single frames;
+// anything larger is filled with silence in one go or skipped this much per frame. The
+// actor seeks the decoder for larger corrections either way, so the output never decodes
+// them itself or plays long silences mid-track.
+pub const SMOOTH_LIMIT_MS: i64 = 100;
+// One frame dropped or repeated every this many frames, i.e. a 1% rate change
+const SMOOTH_INTERVAL_FRAMES: u64 = 100;
+// The output pulls in bursts, one per device callback. Pulls are timestamped every few
+// frames; a pause longer than BURST_GAP between them marks the start of a new callback.
+const BURST_PROBE_FRAMES: u64 = 32;
+const BURST_GAP: Duration = Duration::from_millis(2);
+
+/// Track position as seen by the output: counts the frames of the current track that were
+/// actually handed to the sink, and carries pending drift corrections back to the source.
+pub struct PlaybackClock {
+    next_id: AtomicU64,
+    // Source currently feeding the output; 0 while nothing has started
+    active_id: AtomicU64,
+    // Track position (f64 seconds) at which the active source started
+    base_position: AtomicU64,
+    frames: AtomicU64,
+    sample_rate: AtomicU32,
+    // Frames still to correct: positive skips ahead, negative holds back
+    correction: AtomicI64,
+    // Estimated frames handed over but not yet audible (one output callback's worth)
+    queued_frames: AtomicU64,
+    track_id: Mutex<Option<String>>,
+}
+
+impl PlaybackClock {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            active_id: AtomicU64::new(0),
+            base_position: AtomicU64::new(0f64.to_bits()),
+            frames: AtomicU64::new(0),
+            sample_rate: AtomicU32::new(0),
+            correction: AtomicI64::new(0),
+            queued_frames: AtomicU64::new(0),
+            track_id: Mutex::new(None),
+        }
+    }
+
+    /// Id for a new source; it becomes active once the output starts pulling from it
+    pub fn register(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn active_id(&self) -> u64 {
+        self.active_id.load(Ordering::Acquire)
+    }
+
+    /// Position in the active track, in seconds, of the last frame handed to the output
+    pub fn position(&self) -> Option<f64> {
+        if self.active_id() == 0 {
+            return None;
+        }
+
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        if sample_rate == 0 {
+            return None;
+        }
+
+        let base = f64::from_bits(self.base_position.load(Ordering::Relaxed));
+        let frames = self.frames.load(Ordering::Relaxed);
+        Some(base + frames as f64 / sample_rate as f64)
+    }
+
+    /// Position in `track_id` that is audible right now: frames handed to the output minus
+    /// what is still queued in the device buffer. `None` unless that track is playing locally.
+    pub fn played_position(&self, track_id: &str) -> Option<f64> {
+        let active_track = self.track_id.lock().ok()?.clone();
+        if active_track.as_deref() != Some(track_id) {
+            return None;
+        }
+
+        let position = self.position()?;
+        let base = f64::from_bits(self.base_position.load(Ordering::Relaxed));
+        let queued = self.queued_frames.load(Ordering::Relaxed) as f64
+            / self.sample_rate.load(Ordering::Relaxed).max(1) as f64;
+        Some((position - queued).max(base))
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    pub fn pending_correction(&self) -> i64 {
+        self.correction.load(Ordering::Relaxed)
+    }
+
+    /// Ask the active source to move by `frames`: positive skips audio, negative delays it
+    pub fn request_correction(&self, frames: i64) {
+        self.correction.store(frames, Ordering::Relaxed);
+    }
+
+    /// Forget the active source, e.g. when playback stops
+    pub fn reset(&self) {
+        self.active_id.store(0, Ordering::Release);
+        self.correction.store(0, Ordering::Relaxed);
+        if let Ok(mut track_id) = self.track_id.lock() {
+            *track_id = None;
+        }
+    }
+
+    /// Make source `id` the active one, counting from `base_position`. Sources claim the
+    /// clock themselves when the output first pulls from them; this hands it back to one
+    /// that was parked by `reset`, e.g. when a soft pause ends.
+    pub fn claim(&self, id: u64, track_id: &str, base_position: f64, sample_rate: u32) {
+        self.base_position
+            .store(base_position.to_bits(), Ordering::Relaxed);
+        self.frames.store(0, Ordering::Relaxed);
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.correction.store(0, Ordering::Relaxed);
+        if let Ok(mut active_track) = self.track_id.lock() {
+            *active_track = Some(track_id.to_string());
+        }
+        self.active_id.store(id, Ordering::Release);
+    }
+
+    fn record_burst(&self, frames: u64) {
+        // Smooth over the odd short or long callback
+        let previous = self.queued_frames.load(Ordering::Relaxed);
+        let estimate = if previous == 0 {
+            frames
+        } else {
+            (previous * 3 + frames) / 4
+        };
+        self.queued_frames.store(estimate, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FrameMode {
+    Normal,
+    Silence,
+    Repeat,
+}
+
+/// Source wrapper that reports consumed frames to a [`PlaybackClock`] and applies the
+/// clock's drift corrections on frame boundaries.
+pub struct ClockedSource<S> {
+    inner: S,
+    clock: Arc<PlaybackClock>,
+    id: u64,
+    track_id: String,
+    start_position: f64,
+    claimed: bool,
+    // False once another source took over the clock or playback was stopped, e.g. while
+    // this one fades out; its frames then no longer move the position
+    counting: bool,
+    channels: usize,
+    channel: usize,
+    mode: FrameMode,
+    last_frame: Vec<i16>,
+    frame_count: u64,
+    last_probe: Option<Instant>,
+    burst_frames: u64,
+}
+
+impl<S> ClockedSource<S>
+where
+    S: Source<Item = i16>,
+{
+    pub fn new(
+        inner: S,
+        clock: Arc<PlaybackClock>,
+        id: u64,
+        track_id: &str,
+        start_position: f64,
+    ) -> Self {
+        Self {
+            inner,
+            clock,
+            id,
+            track_id: track_id.to_string(),
+            start_position,
+            claimed: false,
+            counting: false,
+            channels: 1,
+            channel: 0,
+            mode: FrameMode::Normal,
+            last_frame: Vec::new(),
+            frame_count: 0,
+            last_probe: None,
+            burst_frames: 0,
+        }
+    }
+
+    /// Decide how the next frame is produced. Returns `None` once the inner source is done.
+    fn start_frame(&mut self) -> Option<()> {
+        if !self.claimed {
+            self.clock.claim(
+                self.id,
+                &self.track_id,
+                self.start_position,
+                self.inner.sample_rate(),
+            );
+            self.claimed = true;
+        }
+
+        self.channels = self.inner.channels().max(1) as usize;
+        self.last_frame.resize(self.channels, 0);
+        self.frame_count += 1;
+        self.mode = FrameMode::Normal;
+
+        self.counting = self.clock.active_id() == self.id;
+        if !self.counting {
+            return Some(());
+        }
+
+        self.burst_frames += 1;
+        if self.frame_count.is_multiple_of(BURST_PROBE_FRAMES) {
+            let now = Instant::now();
+            if let Some(last) = self.last_probe {
+                if now.duration_since(last) > BURST_GAP {
+                    self.clock.record_burst(self.burst_frames);
+                    self.burst_frames = 0;
+                }
+            }
+            self.last_probe = Some(now);
+        }
+
+        let pending = self.clock.pending_correction();
+        let smooth_turn = self.frame_count.is_multiple_of(SMOOTH_INTERVAL_FRAMES);
+        let smooth_limit = self.inner.sample_rate() as i64 * SMOOTH_LIMIT_MS / 1000;
+
+        if pending > smooth_limit || (pending > 0 && smooth_turn) {
+            let skip = if pending > smooth_limit {
+                smooth_limit
+            } else {
+                1
+            };
+            let mut skipped = 0;
+            let mut ended = false;
+            'skip: while skipped < skip {
+                for _ in 0..self.channels {
+                    if self.inner.next().is_none() {
+                        ended = true;
+                        break 'skip;
+                    }
+                }
+                skipped += 1;
+            }
+            self.clock
+                .frames
+                .fetch_add(skipped as u64, Ordering::Relaxed);
+            self.clock.correction.fetch_sub(skipped, Ordering::Relaxed);
+            if ended {
+                return None;
+            }
+        } else if pending < -smooth_limit || (pending < 0 && smooth_turn) {
+            self.mode = if pending < -smooth_limit {
+                FrameMode::Silence
+            } else {
+                FrameMode::Repeat
+            };
+            self.clock.correction.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Some(())
+    }
+}
+
+impl<S> Iterator for ClockedSource<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.channel == 0 {
+            self.start_frame()?;
+        }
+
+        let sample = match self.mode {
+            FrameMode::Silence => 0,
+            FrameMode::Repeat => self.last_frame[self.channel],
+            FrameMode::Normal => {
+                let sample = self.inner.next()?;
+                self.last_frame[self.channel] = sample;
+                sample
+            }
+        };
+
+        self.channel += 1;
+        if self.channel >= self.channels {
+            self.channel = 0;
+            if self.mode == FrameMode::Normal && self.counting {
+                self.clock.frames.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        Some(sample)
+    }
+}
+
+impl<S> Source for ClockedSource<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
@@ -1,7 +1,12 @@
+use crate::crossfeed::CrossfeedLevel;
+use crate::equalizer::EqualizerConfig;
+use crate::fade::FadeConfig;
+use crate::mixer::MixerConfig;
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
@@ -9,6 +14,50 @@ pub struct AppConfig {
     pub volume: f32,
     #[serde(default)]
     pub theme_css_path: Option<String>,
+    /// Directory for cached audio; defaults to the XDG cache dir when unset
+    #[serde(default)]
+    pub cache_dir: Option<String>,
+    /// Upper bound for the audio cache in megabytes; 0 disables caching
+    #[serde(default = "AppConfig::default_cache_max_mb")]
+    pub cache_max_mb: u64,
+    /// Name of the cpal output device; the system default is used when unset
+    #[serde(default)]
+    pub output_device: Option<String>,
+    #[serde(default)]
+    pub equalizer: EqualizerConfig,
+    #[serde(default = "AppConfig::default_normalize_loudness")]
+    pub normalize_loudness: bool,
+    /// Headphone crossfeed strength
+    #[serde(default)]
+    pub crossfeed: CrossfeedLevel,
+    #[serde(default)]
+    pub stream_transport: StreamTransport,
+    #[serde(default)]
+    pub fades: FadeConfig,
+    /// Volume curve, balance and mono downmix of the output
+    #[serde(default)]
+    pub mixer: MixerConfig,
+    /// Pause by muting and pausing the open stream instead of stopping it, so a resume
+    /// continues in place
+    #[serde(default)]
+    pub soft_pause: bool,
+    /// How long a soft-paused stream is kept open before it is released
+    #[serde(default = "AppConfig::default_soft_pause_keep_secs")]
+    pub soft_pause_keep_secs: u64,
+    /// Run the FFT behind the `audio_spectrum` event; off saves the CPU it costs
+    #[serde(default = "AppConfig::default_spectrum_visualizer")]
+    pub spectrum_visualizer: bool,
+}
+
+/// How audio that is not in the local cache is fetched from the backend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamTransport {
+    /// Single progressive download of `/api/music/stream` with HTTP range requests
+    #[default]
+    Progressive,
+    /// Segmented playlist from `/api/music/hls`; seeks only re-fetch one segment
+    Hls,
 }
 
 impl Default for AppConfig {
@@ -16,6 +65,18 @@ impl Default for AppConfig {
         Self {
             volume: Self::default_volume(),
             theme_css_path: None,
+            cache_dir: None,
+            cache_max_mb: Self::default_cache_max_mb(),
+            output_device: None,
+            equalizer: EqualizerConfig::default(),
+            normalize_loudness: Self::default_normalize_loudness(),
+            crossfeed: CrossfeedLevel::default(),
+            stream_transport: StreamTransport::default(),
+            fades: FadeConfig::default(),
+            mixer: MixerConfig::default(),
+            soft_pause: false,
+            soft_pause_keep_secs: Self::default_soft_pause_keep_secs(),
+            spectrum_visualizer: Self::default_spectrum_visualizer(),
         }
     }
 }
@@ -25,6 +86,49 @@ impl AppConfig {
         0.8
     }
 
+    const fn default_cache_max_mb() -> u64 {
+        1024
+    }
+
+    const fn default_normalize_loudness() -> bool {
+        true
+    }
+
+    const fn default_soft_pause_keep_secs() -> u64 {
+        60
+    }
+
+    const fn default_spectrum_visualizer() -> bool {
+        true
+    }
+
+    /// How long to keep a paused stream open, or `None` when pausing stops playback
+    pub fn soft_pause_keep(&self) -> Option<Duration> {
+        self.soft_pause
+            .then(|| Duration::from_secs(self.soft_pause_keep_secs))
+    }
+
+    pub fn audio_cache_dir(&self) -> PathBuf {
+        if let Some(dir) = self
+            .cache_dir
+            .as_ref()
+            .map(|dir| dir.trim())
+            .filter(|dir| !dir.is_empty())
+        {
+            return PathBuf::from(dir);
+        }
+
+        dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("miu-player")
+            .join("audio")
+    }
+
+    /// Measured per-track loudness lives next to the cached audio it was taken from
+    pub fn loudness_db_path(&self) -> PathBuf {
+        self.audio_cache_dir().join("loudness.json")
+    }
+
     pub fn load() -> Result<Self> {
         let config_path = Self::config_file_path()?;
 
@@ -0,0 +1,315 @@
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How much of each channel bleeds into the other on headphones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossfeedLevel {
+    #[default]
+    Off,
+    /// Jan Meier's setting (650 Hz, 9.5 dB)
+    Light,
+    /// Chu Moy's setting (700 Hz, 6 dB)
+    Medium,
+    /// Bauer's default (700 Hz, 4.5 dB)
+    Strong,
+}
+
+impl CrossfeedLevel {
+    const ALL: [Self; 4] = [Self::Off, Self::Light, Self::Medium, Self::Strong];
+
+    /// Low-pass cutoff in Hz and feed level in dB; `None` when off
+    fn parameters(self) -> Option<(f32, f32)> {
+        match self {
+            Self::Off => None,
+            Self::Light => Some((650.0, 9.5)),
+            Self::Medium => Some((700.0, 6.0)),
+            Self::Strong => Some((700.0, 4.5)),
+        }
+    }
+}
+
+/// Live crossfeed setting shared between the UI commands and every playing source
+pub struct Crossfeed {
+    level: AtomicU8,
+}
+
+impl Crossfeed {
+    pub fn new(level: CrossfeedLevel) -> Self {
+        let crossfeed = Self {
+            level: AtomicU8::new(0),
+        };
+        crossfeed.set_level(level);
+        crossfeed
+    }
+
+    pub fn level(&self) -> CrossfeedLevel {
+        CrossfeedLevel::ALL
+            .get(self.level.load(Ordering::Relaxed) as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_level(&self, level: CrossfeedLevel) {
+        let index = CrossfeedLevel::ALL
+            .iter()
+            .position(|candidate| *candidate == level)
+            .unwrap_or(0);
+        self.level.store(index as u8, Ordering::Relaxed);
+    }
+
+    /// Wrap `source` in a crossfeed stage that follows this setting
+    pub fn apply<S>(self: &Arc<Self>, source: S) -> CrossfeedSource<S>
+    where
+        S: Source<Item = i16>,
+    {
+        CrossfeedSource {
+            inner: source,
+            crossfeed: self.clone(),
+            level: CrossfeedLevel::Off,
+            sample_rate: 0,
+            channels: 0,
+            filter: None,
+            frame: Vec::with_capacity(2),
+            next: 0,
+        }
+    }
+}
+
+/// Bauer stereophonic-to-binaural filter (as in bs2b): each ear gets the other channel
+/// low-passed and attenuated, while its own channel gets a matching high-shelf boost so the
+/// overall tone stays level
+struct Bs2b {
+    a0_lo: f32,
+    b1_lo: f32,
+    a0_hi: f32,
+    a1_hi: f32,
+    b1_hi: f32,
+    gain: f32,
+    lo: [f32; 2],
+    hi: [f32; 2],
+    previous: [f32; 2],
+}
+
+impl Bs2b {
+    fn new(cutoff: f32, feed_db: f32, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+        let gain_lo_db = feed_db * -5.0 / 6.0 - 3.0;
+        let gain_hi_db = feed_db / 6.0 - 3.0;
+        let gain_lo = 10f32.powf(gain_lo_db / 20.0);
+        let gain_hi = 1.0 - 10f32.powf(gain_hi_db / 20.0);
+        let cutoff_hi = cutoff * 2f32.powf((gain_lo_db - 20.0 * gain_hi.log10()) / 12.0);
+
+        let x_lo = (-2.0 * PI * cutoff / sample_rate).exp();
+        let x_hi = (-2.0 * PI * cutoff_hi / sample_rate).exp();
+
+        Self {
+            a0_lo: gain_lo * (1.0 - x_lo),
+            b1_lo: x_lo,
+            a0_hi: 1.0 - gain_hi * (1.0 - x_hi),
+            a1_hi: -x_hi,
+            b1_hi: x_hi,
+            gain: 1.0 / (1.0 - gain_hi + gain_lo),
+            lo: [0.0; 2],
+            hi: [0.0; 2],
+            previous: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        for (channel, &input) in frame.iter().enumerate() {
+            self.lo[channel] = self.a0_lo * input + self.b1_lo * self.lo[channel];
+            self.hi[channel] = self.a0_hi * input
+                + self.a1_hi * self.previous[channel]
+                + self.b1_hi * self.hi[channel];
+        }
+        self.previous = frame;
+
+        [
+            (self.hi[0] + self.lo[1]) * self.gain,
+            (self.hi[1] + self.lo[0]) * self.gain,
+        ]
+    }
+}
+
+/// `rodio::Source` adapter applying the crossfeed to stereo sources; anything else passes
+/// through untouched
+pub struct CrossfeedSource<S> {
+    inner: S,
+    crossfeed: Arc<Crossfeed>,
+    level: CrossfeedLevel,
+    sample_rate: u32,
+    channels: u16,
+    // `None` while off or for non-stereo audio
+    filter: Option<Bs2b>,
+    frame: Vec<i16>,
+    next: usize,
+}
+
+impl<S> CrossfeedSource<S>
+where
+    S: Source<Item = i16>,
+{
+    fn refresh(&mut self) {
+        let level = self.crossfeed.level();
+        let sample_rate = self.inner.sample_rate();
+        let channels = self.inner.channels();
+        if level == self.level && sample_rate == self.sample_rate && channels == self.channels {
+            return;
+        }
+
+        self.filter = level
+            .parameters()
+            .filter(|_| channels == 2 && sample_rate > 0)
+            .map(|(cutoff, feed_db)| Bs2b::new(cutoff, feed_db, sample_rate));
+        self.level = level;
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+    }
+
+    fn fill_frame(&mut self) -> bool {
+        self.refresh();
+        let channels = self.inner.channels().max(1) as usize;
+        self.frame.clear();
+        self.next = 0;
+        self.frame.extend(self.inner.by_ref().take(channels));
+        if self.frame.is_empty() {
+            return false;
+        }
+
+        if let (Some(filter), [left, right]) = (self.filter.as_mut(), self.frame.as_mut_slice()) {
+            let scale = i16::MAX as f32;
+            let [out_left, out_right] =
+                filter.process([*left as f32 / scale, *right as f32 / scale]);
+            *left = (out_left.clamp(-1.0, 1.0) * scale) as i16;
+            *right = (out_right.clamp(-1.0, 1.0) * scale) as i16;
+        }
+        true
+    }
+}
+
+impl<S> Iterator for CrossfeedSource<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.next >= self.frame.len() && !self.fill_frame() {
+            return None;
+        }
+
+        let sample = self.frame[self.next];
+        self.next += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for CrossfeedSource<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        // The rest of the buffered frame still belongs to the inner source's current span
+        let buffered = self.frame.len() - self.next;
+        self.inner.current_frame_len().map(|len| len + buffered)
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    // Output once a constant `frame` has been fed long enough for the filters to settle
+    fn settled(filter: &mut Bs2b, frame: [f32; 2]) -> [f32; 2] {
+        (0..SAMPLE_RATE).fold([0.0; 2], |_, _| filter.process(frame))
+    }
+
+    fn db(ratio: f32) -> f32 {
+        20.0 * ratio.log10()
+    }
+
+    #[test]
+    fn levels_use_the_bs2b_presets() {
+        assert_eq!(CrossfeedLevel::Off.parameters(), None);
+        assert_eq!(CrossfeedLevel::Light.parameters(), Some((650.0, 9.5)));
+        assert_eq!(CrossfeedLevel::Medium.parameters(), Some((700.0, 6.0)));
+        assert_eq!(CrossfeedLevel::Strong.parameters(), Some((700.0, 4.5)));
+
+        let crossfeed = Crossfeed::new(CrossfeedLevel::Medium);
+        assert_eq!(crossfeed.level(), CrossfeedLevel::Medium);
+        crossfeed.set_level(CrossfeedLevel::Off);
+        assert_eq!(crossfeed.level(), CrossfeedLevel::Off);
+    }
+
+    #[test]
+    fn low_frequencies_cross_over_at_the_feed_level() {
+        for level in &CrossfeedLevel::ALL[1..] {
+            let (cutoff, feed_db) = level.parameters().unwrap();
+            let mut filter = Bs2b::new(cutoff, feed_db, SAMPLE_RATE);
+
+            // A left-only tone at DC reaches the right ear `feed_db` quieter
+            let [left, right] = settled(&mut filter, [0.5, 0.0]);
+            assert!((db(left / right) - feed_db).abs() < 0.01, "{:?}", level);
+        }
+    }
+
+    #[test]
+    fn centered_low_frequencies_keep_their_level() {
+        for level in &CrossfeedLevel::ALL[1..] {
+            let (cutoff, feed_db) = level.parameters().unwrap();
+            let mut filter = Bs2b::new(cutoff, feed_db, SAMPLE_RATE);
+
+            let [left, right] = settled(&mut filter, [0.5, 0.5]);
+            assert!((left - 0.5).abs() < 1e-4, "{:?}", level);
+            assert!((right - 0.5).abs() < 1e-4, "{:?}", level);
+        }
+    }
+
+    #[test]
+    fn high_frequencies_barely_cross_over() {
+        let mut filter = Bs2b::new(700.0, 4.5, SAMPLE_RATE);
+        // Alternating samples put all energy at the Nyquist frequency
+        let mut last = [0.0; 2];
+        for i in 0..SAMPLE_RATE {
+            let sample = if i % 2 == 0 { 0.5 } else { -0.5 };
+            last = filter.process([sample, 0.0]);
+        }
+        assert!(db(last[0].abs() / last[1].abs()) > 30.0);
+    }
+
+    #[test]
+    fn off_or_non_stereo_sources_pass_through() {
+        let samples: Vec<i16> = (0..1000).map(|i| (i * 31 % 2000 - 1000) as i16).collect();
+
+        let off = Arc::new(Crossfeed::new(CrossfeedLevel::Off));
+        let source = off.apply(SamplesBuffer::new(2, SAMPLE_RATE, samples.clone()));
+        assert_eq!(source.collect::<Vec<_>>(), samples);
+
+        let strong = Arc::new(Crossfeed::new(CrossfeedLevel::Strong));
+        let source = strong.apply(SamplesBuffer::new(1, SAMPLE_RATE, samples.clone()));
+        assert_eq!(source.collect::<Vec<_>>(), samples);
+
+        let source = strong.apply(SamplesBuffer::new(2, SAMPLE_RATE, samples.clone()));
+        assert_ne!(source.collect::<Vec<_>>(), samples);
+    }
+}
@@ -0,0 +1,598 @@
+use crate::media_format::{codec_name, MediaFormat, UnsupportedFormat, SNIFF_LEN};
+use crate::opus::OpusDecoder;
+use anyhow::{anyhow, Context, Result};
+use rodio::Source;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread;
+use std::time::Duration;
+use symphonia::core::audio::{Channels, SampleBuffer, SignalSpec};
+use symphonia::core::codecs::{CodecRegistry, Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream, ReadBytes, SeekBuffered};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+use symphonia::default::formats::{
+    AdtsReader, FlacReader, IsoMp4Reader, MkvReader, MpaReader, OggReader, WavReader,
+};
+
+// Same tolerance as rodio's own symphonia decoder: a few corrupt packets in a row are
+// skipped, anything beyond that ends the stream.
+const MAX_DECODE_ERRORS: usize = 3;
+// Packets a shared decoder's thread decodes ahead of the output
+const DECODE_AHEAD_PACKETS: usize = 16;
+// Silence handed to the output at a time while nothing is decoded yet, about 10ms
+const STARVED_FRAMES: usize = 480;
+
+/// Symphonia-backed rodio source that can start at an arbitrary timestamp.
+///
+/// Seeking goes through the container's sample table (stts/stsz/stco for MP4), so the
+/// underlying reader jumps straight to the byte offset of the target packet instead of
+/// downloading and decoding everything that comes before it.
+pub struct StreamDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    buffer: SampleBuffer<i16>,
+    spec: SignalSpec,
+    frame_offset: usize,
+    // Timestamp (in track time base units) of the first frame we actually want to hear.
+    // Packets before it are decoded to prime the codec but their samples are dropped.
+    required_ts: u64,
+    // Unit of packet timestamps; `None` when the container leaves it out, in which case
+    // timestamps are taken to count frames
+    time_base: Option<TimeBase>,
+    total_duration: Option<Duration>,
+    // Why decoding stopped early, if it did; logged once the source is dropped rather than
+    // from the sample path, which runs on the output callback
+    error: Option<anyhow::Error>,
+}
+
+impl StreamDecoder {
+    /// Decode a stream whose container is recognized from its first bytes, or by probing
+    /// when they don't tell
+    pub fn new(source: Box<dyn MediaSource>, start_position: f64) -> Result<Self> {
+        Self::open(source, None, None, start_position)
+    }
+
+    /// Like [`StreamDecoder::new`], with the `Content-Type` the server sent for the stream
+    pub fn with_content_type(
+        source: Box<dyn MediaSource>,
+        content_type: Option<&str>,
+        start_position: f64,
+    ) -> Result<Self> {
+        Self::open(source, content_type, None, start_position)
+    }
+
+    /// Like [`StreamDecoder::new`], for streams known to come in the `expected` container
+    pub fn with_format(
+        source: Box<dyn MediaSource>,
+        expected: MediaFormat,
+        start_position: f64,
+    ) -> Result<Self> {
+        Self::open(source, None, Some(expected), start_position)
+    }
+
+    fn open(
+        source: Box<dyn MediaSource>,
+        content_type: Option<&str>,
+        expected: Option<MediaFormat>,
+        start_position: f64,
+    ) -> Result<Self> {
+        let mut mss = MediaSourceStream::new(source, Default::default());
+        let header = peek_header(&mut mss)?;
+        let media_format = MediaFormat::detect(&header, content_type).or(expected);
+
+        let format = open_format(mss, &header, media_format).map_err(|e| match e {
+            SymphoniaError::Unsupported(_) => {
+                let format = media_format
+                    .map(MediaFormat::name)
+                    .or(content_type)
+                    .unwrap_or("unrecognized container");
+                UnsupportedFormat::new(format).into()
+            }
+            e => anyhow::Error::new(e).context("Failed to open audio stream"),
+        })?;
+
+        let track = format
+            .default_track()
+            .ok_or_else(|| anyhow!("Audio stream contains no playable track"))?;
+        let track_id = track.id;
+        let time_base = track.codec_params.time_base;
+
+        let total_duration = match (track.codec_params.time_base, track.codec_params.n_frames) {
+            (Some(tb), Some(frames)) => {
+                let time = tb.calc_time(frames);
+                Some(Duration::from_secs_f64(time.seconds as f64 + time.frac))
+            }
+            _ => None,
+        };
+
+        let codecs = codecs();
+        if codecs.get_codec(track.codec_params.codec).is_none() {
+            return Err(UnsupportedFormat::new(codec_name(track.codec_params.codec)).into());
+        }
+
+        let decoder = codecs
+            .make(&track.codec_params, &DecoderOptions::default())
+            .context("Failed to create decoder")?;
+
+        // Placeholder spec, replaced by the first decoded packet below; it needs a channel,
+        // since an empty layout makes `SampleBuffer::new` divide by zero
+        let spec = SignalSpec::new(0, Channels::FRONT_LEFT);
+        let mut stream_decoder = Self {
+            format,
+            decoder,
+            track_id,
+            buffer: SampleBuffer::new(0, spec),
+            spec,
+            frame_offset: 0,
+            required_ts: 0,
+            time_base,
+            total_duration,
+            error: None,
+        };
+
+        if start_position > 0.0 {
+            stream_decoder.seek_to(start_position)?;
+        }
+
+        // Decode the first audible packet up front so channel count and sample rate are
+        // known before the source is handed to the sink.
+        if !stream_decoder.decode_next_packet()? {
+            return Err(anyhow!("Audio stream ended before any audio was decoded"));
+        }
+
+        Ok(stream_decoder)
+    }
+
+    /// Split into a source for the sink and a handle that can still seek it from elsewhere.
+    /// Decoding moves to a thread of its own, so a read that waits on the network holds up
+    /// that thread instead of the output callback.
+    pub fn shared(self) -> (SharedDecoder, DecoderHandle) {
+        let total_duration = self.total_duration;
+        let handle = DecoderHandle {
+            decoder: Arc::new(Mutex::new(self)),
+            seeks: Arc::new(AtomicU64::new(0)),
+        };
+
+        // The first packet is already decoded; taking it here gives the sink its format
+        let mut packet = Vec::new();
+        let (channels, sample_rate) = handle.lock().take_packet(&mut packet);
+
+        let (sender, packets) = mpsc::sync_channel(DECODE_AHEAD_PACKETS);
+        let (spare_sender, spare) = mpsc::sync_channel(DECODE_AHEAD_PACKETS);
+        let worker = handle.clone();
+        let spawned = thread::Builder::new()
+            .name("decoder".to_string())
+            .spawn(move || worker.decode_ahead(sender, spare));
+        if let Err(e) = spawned {
+            // The source then ends after the first packet
+            println!("Audio: Failed to start decoder thread: {}", e);
+        }
+
+        let source = SharedDecoder {
+            packets,
+            spare: spare_sender,
+            seeks: handle.seeks.clone(),
+            seen_seeks: 0,
+            packet,
+            next: 0,
+            channels,
+            sample_rate,
+            owed: 0,
+            total_duration,
+        };
+        (source, handle)
+    }
+
+    /// Reposition the stream so the next sample produced is the one at `position` seconds.
+    pub fn seek(&mut self, position: f64) -> Result<()> {
+        self.seek_to(position)?;
+        self.error = None;
+        if !self.decode_next_packet()? {
+            return Err(anyhow!(
+                "Seek to {:.2}s is past the end of the stream",
+                position
+            ));
+        }
+        Ok(())
+    }
+
+    fn seek_to(&mut self, position: f64) -> Result<()> {
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(position.max(0.0)),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .with_context(|| format!("Failed to seek to {:.2}s", position))?;
+
+        self.decoder.reset();
+        self.required_ts = seeked.required_ts;
+        self.frame_offset = self.buffer.len();
+        Ok(())
+    }
+
+    /// Decode packets until one yields audible samples. Returns `Ok(false)` at end of stream.
+    fn decode_next_packet(&mut self) -> Result<bool> {
+        let mut decode_errors = 0;
+
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(ref e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(false);
+                }
+                Err(SymphoniaError::ResetRequired) => {
+                    self.decoder.reset();
+                    continue;
+                }
+                Err(e) => return Err(anyhow::Error::new(e).context("Failed to read audio packet")),
+            };
+
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let packet_ts = packet.ts();
+            let packet_end = packet_ts.saturating_add(packet.dur());
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(e)) => {
+                    decode_errors += 1;
+                    if decode_errors > MAX_DECODE_ERRORS {
+                        return Err(anyhow::Error::new(SymphoniaError::DecodeError(e))
+                            .context("Too many consecutive decode errors"));
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    return Err(anyhow::Error::new(e).context("Failed to decode audio packet"))
+                }
+            };
+
+            // Packet lies entirely before the seek target; it only served to prime the codec
+            if packet_end <= self.required_ts {
+                continue;
+            }
+
+            let spec = *decoded.spec();
+            let channels = spec.channels.count();
+            if self.spec != spec || self.buffer.capacity() < decoded.capacity() * channels {
+                self.buffer = SampleBuffer::new(decoded.capacity() as u64, spec);
+            }
+            self.spec = spec;
+            self.buffer.copy_interleaved_ref(decoded);
+
+            let skip_frames =
+                self.ts_to_frames(self.required_ts.saturating_sub(packet_ts), spec.rate);
+            self.frame_offset = (skip_frames * channels).min(self.buffer.len());
+
+            if self.frame_offset < self.buffer.len() {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Move what is left of the current packet into `out`, then decode the next one.
+    /// Returns the channel count and sample rate of the moved samples.
+    fn take_packet(&mut self, out: &mut Vec<i16>) -> (u16, u32) {
+        out.clear();
+        out.extend_from_slice(&self.buffer.samples()[self.frame_offset..]);
+        let spec = (self.channels(), self.sample_rate());
+        self.frame_offset = self.buffer.len();
+        self.refill();
+        spec
+    }
+
+    /// Decode the next packet as soon as the current one is used up, so the buffer only
+    /// runs empty at the end of the stream
+    fn refill(&mut self) {
+        if let Err(e) = self.decode_next_packet() {
+            self.frame_offset = self.buffer.len();
+            self.error = Some(e);
+        }
+    }
+
+    /// Number of audio frames spanned by `ts` timestamp units
+    fn ts_to_frames(&self, ts: u64, sample_rate: u32) -> usize {
+        match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(ts);
+                ((time.seconds as f64 + time.frac) * sample_rate as f64).round() as usize
+            }
+            None => ts as usize,
+        }
+    }
+}
+
+/// Symphonia's built-in decoders plus libopus for Opus, which symphonia has no decoder for
+fn codecs() -> &'static CodecRegistry {
+    static CODECS: OnceLock<CodecRegistry> = OnceLock::new();
+    CODECS.get_or_init(|| {
+        let mut registry = CodecRegistry::new();
+        symphonia::default::register_enabled_codecs(&mut registry);
+        registry.register_all::<OpusDecoder>();
+        registry
+    })
+}
+
+/// Open the reader for `media_format` directly, so a stream is never handed to a reader
+/// for some other container. Unknown formats, and streams fronted by an ID3 tag that only
+/// the probe knows to skip, are probed instead.
+fn open_format(
+    mss: MediaSourceStream,
+    header: &[u8],
+    media_format: Option<MediaFormat>,
+) -> symphonia::core::errors::Result<Box<dyn FormatReader>> {
+    let options = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+
+    match media_format.filter(|_| !header.starts_with(b"ID3")) {
+        Some(MediaFormat::Mp4) => read_format::<IsoMp4Reader>(mss, &options),
+        Some(MediaFormat::Adts) => read_format::<AdtsReader>(mss, &options),
+        Some(MediaFormat::Mp3) => read_format::<MpaReader>(mss, &options),
+        Some(MediaFormat::Flac) => read_format::<FlacReader>(mss, &options),
+        Some(MediaFormat::Ogg) => read_format::<OggReader>(mss, &options),
+        Some(MediaFormat::Matroska) => read_format::<MkvReader>(mss, &options),
+        Some(MediaFormat::Wav) => read_format::<WavReader>(mss, &options),
+        None => symphonia::default::get_probe()
+            .format(&Hint::new(), mss, &options, &MetadataOptions::default())
+            .map(|probed| probed.format),
+    }
+}
+
+fn read_format<R: FormatReader + 'static>(
+    mss: MediaSourceStream,
+    options: &FormatOptions,
+) -> symphonia::core::errors::Result<Box<dyn FormatReader>> {
+    Ok(Box::new(R::try_new(mss, options)?))
+}
+
+/// Read the first bytes of the stream for sniffing, then rewind so the reader still sees
+/// the stream from its start
+fn peek_header(mss: &mut MediaSourceStream) -> Result<Vec<u8>> {
+    let mut header = [0u8; SNIFF_LEN];
+    let mut filled = 0;
+    while filled < SNIFF_LEN {
+        match mss.read_buf(&mut header[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(anyhow::Error::new(e).context("Failed to read audio stream")),
+        }
+    }
+
+    mss.seek_buffered_rev(filled);
+    Ok(header[..filled].to_vec())
+}
+
+impl Source for StreamDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        // Packets are decoded eagerly, so this is only 0 once the stream has ended
+        Some(self.buffer.len() - self.frame_offset)
+    }
+
+    fn channels(&self) -> u16 {
+        self.spec.channels.count() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.spec.rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+}
+
+impl Iterator for StreamDecoder {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = *self.buffer.samples().get(self.frame_offset)?;
+        self.frame_offset += 1;
+        if self.frame_offset >= self.buffer.len() {
+            self.refill();
+        }
+        Some(sample)
+    }
+}
+
+/// Packet decoded ahead by a shared decoder's thread
+struct DecodedPacket {
+    samples: Vec<i16>,
+    channels: u16,
+    sample_rate: u32,
+    // Seeks done before it was decoded; it is stale once another one happens
+    seek: u64,
+}
+
+/// Seeks a [`SharedDecoder`] that is already queued on a sink
+#[derive(Clone)]
+pub struct DecoderHandle {
+    decoder: Arc<Mutex<StreamDecoder>>,
+    seeks: Arc<AtomicU64>,
+}
+
+impl DecoderHandle {
+    /// Reposition the stream at `position` seconds. This waits for the decoder thread and
+    /// may block on the network, so call it off the runtime's async threads.
+    pub fn seek(&self, position: f64) -> Result<()> {
+        let mut decoder = self.lock();
+        decoder.seek(position)?;
+        self.seeks.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Play `decoder` from here on in place of the current one, e.g. one reopened at a seek
+    /// target. Packets decoded ahead from the old one are dropped, as after a seek.
+    pub fn replace(&self, decoder: StreamDecoder) {
+        let mut current = self.lock();
+        *current = decoder;
+        self.seeks.fetch_add(1, Ordering::Release);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, StreamDecoder> {
+        self.decoder.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Decoder thread: decode into `packets` until the stream ends or the source is dropped,
+    /// reusing the buffers the source hands back through `spare`
+    fn decode_ahead(&self, packets: SyncSender<DecodedPacket>, spare: Receiver<Vec<i16>>) {
+        loop {
+            let mut samples = spare.try_recv().unwrap_or_default();
+            let packet = {
+                let mut decoder = self.lock();
+                let seek = self.seeks.load(Ordering::Acquire);
+                let (channels, sample_rate) = decoder.take_packet(&mut samples);
+                DecodedPacket {
+                    samples,
+                    channels,
+                    sample_rate,
+                    seek,
+                }
+            };
+
+            if packet.samples.is_empty() || packets.send(packet).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// [`StreamDecoder`] as a sink source that a [`DecoderHandle`] can still seek. A thread of
+/// its own decodes ahead; the output only picks up finished packets and never waits for
+/// one. When none is ready it plays silence, and drops as much audio once decoding catches
+/// up, so the track keeps its timeline.
+pub struct SharedDecoder {
+    packets: Receiver<DecodedPacket>,
+    spare: SyncSender<Vec<i16>>,
+    seeks: Arc<AtomicU64>,
+    seen_seeks: u64,
+    packet: Vec<i16>,
+    next: usize,
+    channels: u16,
+    sample_rate: u32,
+    // Samples of silence played in place of audio that was not decoded in time
+    owed: usize,
+    total_duration: Option<Duration>,
+}
+
+impl SharedDecoder {
+    fn load_packet(&mut self) {
+        let seeks = self.seeks.load(Ordering::Acquire);
+        if seeks > self.seen_seeks {
+            // A seek starts a new timeline, with nothing to catch up on
+            self.seen_seeks = seeks;
+            self.owed = 0;
+        }
+
+        loop {
+            match self.packets.try_recv() {
+                Ok(packet) => {
+                    if packet.seek < self.seen_seeks {
+                        // Decoded before the latest seek
+                        self.recycle(packet.samples);
+                        continue;
+                    }
+                    if packet.seek > self.seen_seeks {
+                        self.seen_seeks = packet.seek;
+                        self.owed = 0;
+                    }
+
+                    self.channels = packet.channels;
+                    self.sample_rate = packet.sample_rate;
+                    let used = std::mem::replace(&mut self.packet, packet.samples);
+                    self.recycle(used);
+
+                    let frame = self.channels.max(1) as usize;
+                    let skip = self.owed.min(self.packet.len()) / frame * frame;
+                    self.owed -= skip;
+                    self.next = skip;
+                    if self.next < self.packet.len() {
+                        return;
+                    }
+                }
+                Err(TryRecvError::Empty) => {
+                    let len = STARVED_FRAMES * self.channels.max(1) as usize;
+                    self.packet.clear();
+                    self.packet.resize(len, 0);
+                    self.next = 0;
+                    self.owed += len;
+                    return;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    // End of stream
+                    self.packet.clear();
+                    self.next = 0;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Hand a used buffer back to the decoder thread rather than free it here
+    fn recycle(&self, mut samples: Vec<i16>) {
+        samples.clear();
+        let _ = self.spare.try_send(samples);
+    }
+}
+
+impl Source for SharedDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.packet.len() - self.next)
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+}
+
+impl Iterator for SharedDecoder {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        // Drop the rest of a packet decoded before a seek, keeping whole frames
+        let frame_start = self.next.is_multiple_of(self.channels.max(1) as usize);
+        if frame_start && self.seeks.load(Ordering::Relaxed) != self.seen_seeks {
+            self.load_packet();
+        }
+
+        let sample = *self.packet.get(self.next)?;
+        self.next += 1;
+        if self.next >= self.packet.len() {
+            self.load_packet();
+        }
+        Some(sample)
+    }
+}
+
+impl Drop for StreamDecoder {
+    fn drop(&mut self) {
+        if let Some(e) = self.error.take() {
+            println!("Audio: Decoder stopped: {:#}", e);
+        }
+    }
+}
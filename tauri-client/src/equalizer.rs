@@ -0,0 +1,583 @@
+use anyhow::{anyhow, Result};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const PRESET_FLAT: &str = "flat";
+pub const PRESET_BASS_BOOST: &str = "bass_boost";
+pub const PRESET_VOCAL: &str = "vocal";
+pub const PRESET_CUSTOM: &str = "custom";
+
+const MAX_GAIN_DB: f32 = 12.0;
+const DEFAULT_Q: f32 = 1.0;
+const SHELF_Q: f32 = 0.707;
+// Bands closer to 0 dB than this are bypassed
+const FLAT_GAIN_DB: f32 = 0.05;
+// How often (in samples) the audio thread checks for new settings
+const PARAMS_CHECK_INTERVAL: usize = 1024;
+
+const BAND_FREQUENCIES: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+const BASS_BOOST_GAINS: [f32; 10] = [6.0, 5.0, 4.0, 2.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0];
+const VOCAL_GAINS: [f32; 10] = [-2.0, -2.0, -1.0, 0.0, 1.5, 3.0, 3.0, 2.0, 0.0, -1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BandKind {
+    LowShelf,
+    Peaking,
+    HighShelf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EqBand {
+    pub kind: BandKind,
+    pub frequency: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+/// Persisted equalizer settings. `bands` is what is currently applied; `preset` names where
+/// it came from, or `custom` once individual bands were edited.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EqualizerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "EqualizerConfig::default_preset")]
+    pub preset: String,
+    #[serde(default = "EqualizerConfig::default_bands")]
+    pub bands: Vec<EqBand>,
+    #[serde(default)]
+    pub user_presets: BTreeMap<String, Vec<EqBand>>,
+}
+
+impl Default for EqualizerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            preset: Self::default_preset(),
+            bands: Self::default_bands(),
+            user_presets: BTreeMap::new(),
+        }
+    }
+}
+
+impl EqualizerConfig {
+    fn default_preset() -> String {
+        PRESET_FLAT.to_string()
+    }
+
+    fn default_bands() -> Vec<EqBand> {
+        bands_with_gains(&[0.0; 10])
+    }
+
+    pub fn preset_names(&self) -> Vec<String> {
+        [PRESET_FLAT, PRESET_BASS_BOOST, PRESET_VOCAL]
+            .iter()
+            .map(|name| name.to_string())
+            .chain(self.user_presets.keys().cloned())
+            .collect()
+    }
+
+    pub fn apply_preset(&mut self, name: &str) -> Result<()> {
+        let bands = match name {
+            PRESET_FLAT => bands_with_gains(&[0.0; 10]),
+            PRESET_BASS_BOOST => bands_with_gains(&BASS_BOOST_GAINS),
+            PRESET_VOCAL => bands_with_gains(&VOCAL_GAINS),
+            _ => self
+                .user_presets
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown equalizer preset: {}", name))?,
+        };
+
+        self.bands = bands;
+        self.preset = name.to_string();
+        Ok(())
+    }
+
+    /// Store the current bands as a user-defined preset
+    pub fn save_preset(&mut self, name: &str) -> Result<()> {
+        let name = name.trim();
+        if name.is_empty() || is_reserved_preset(name) {
+            return Err(anyhow!("Invalid preset name: {}", name));
+        }
+
+        self.user_presets
+            .insert(name.to_string(), self.bands.clone());
+        self.preset = name.to_string();
+        Ok(())
+    }
+
+    pub fn delete_preset(&mut self, name: &str) -> Result<()> {
+        if self.user_presets.remove(name).is_none() {
+            return Err(anyhow!("Unknown equalizer preset: {}", name));
+        }
+        if self.preset == name {
+            self.preset = PRESET_CUSTOM.to_string();
+        }
+        Ok(())
+    }
+
+    pub fn set_band_gain(&mut self, index: usize, gain_db: f32) -> Result<()> {
+        let band = self
+            .bands
+            .get_mut(index)
+            .ok_or_else(|| anyhow!("Equalizer band {} does not exist", index))?;
+        band.gain_db = gain_db.clamp(-MAX_GAIN_DB, MAX_GAIN_DB);
+        self.preset = PRESET_CUSTOM.to_string();
+        Ok(())
+    }
+
+    /// Replace all bands at once, e.g. from a parametric editor
+    pub fn set_bands(&mut self, bands: Vec<EqBand>) {
+        self.bands = bands
+            .into_iter()
+            .map(|band| EqBand {
+                gain_db: band.gain_db.clamp(-MAX_GAIN_DB, MAX_GAIN_DB),
+                frequency: band.frequency.max(10.0),
+                q: band.q.max(0.1),
+                ..band
+            })
+            .collect();
+        self.preset = PRESET_CUSTOM.to_string();
+    }
+}
+
+fn is_reserved_preset(name: &str) -> bool {
+    matches!(
+        name,
+        PRESET_FLAT | PRESET_BASS_BOOST | PRESET_VOCAL | PRESET_CUSTOM
+    )
+}
+
+fn bands_with_gains(gains: &[f32; 10]) -> Vec<EqBand> {
+    let last = BAND_FREQUENCIES.len() - 1;
+    BAND_FREQUENCIES
+        .iter()
+        .zip(gains.iter())
+        .enumerate()
+        .map(|(index, (&frequency, &gain_db))| {
+            let (kind, q) = match index {
+                0 => (BandKind::LowShelf, SHELF_Q),
+                i if i == last => (BandKind::HighShelf, SHELF_Q),
+                _ => (BandKind::Peaking, DEFAULT_Q),
+            };
+            EqBand {
+                kind,
+                frequency,
+                gain_db,
+                q,
+            }
+        })
+        .collect()
+}
+
+struct EqParams {
+    enabled: bool,
+    bands: Vec<EqBand>,
+}
+
+/// Live equalizer settings shared between the UI commands and every playing source.
+///
+/// Sources poll `generation` from the audio thread and only take the lock when the
+/// settings actually changed, so updates apply without restarting the stream.
+pub struct Equalizer {
+    params: Mutex<EqParams>,
+    generation: AtomicU64,
+}
+
+impl Equalizer {
+    pub fn new(config: &EqualizerConfig) -> Self {
+        Self {
+            params: Mutex::new(EqParams {
+                enabled: config.enabled,
+                bands: config.bands.clone(),
+            }),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn update(&self, config: &EqualizerConfig) {
+        if let Ok(mut params) = self.params.lock() {
+            params.enabled = config.enabled;
+            params.bands = config.bands.clone();
+        }
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Wrap `source` in an EQ stage that follows this equalizer's settings
+    pub fn apply<S>(self: &Arc<Self>, source: S) -> EqualizerSource<S>
+    where
+        S: Source<Item = i16>,
+    {
+        let mut eq_source = EqualizerSource {
+            inner: source,
+            equalizer: self.clone(),
+            generation: u64::MAX,
+            sample_rate: 0,
+            channels: 0,
+            filters: Vec::new(),
+            preamp: 1.0,
+            channel: 0,
+            since_check: 0,
+        };
+        eq_source.refresh();
+        eq_source
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    // RBJ audio EQ cookbook formulas
+    fn for_band(band: &EqBand, sample_rate: u32) -> Self {
+        let nyquist = sample_rate as f32 / 2.0;
+        let frequency = band.frequency.clamp(10.0, nyquist * 0.95);
+        let a = 10f32.powf(band.gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q.max(0.1));
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            BandKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            BandKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+            BandKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+/// One biquad section with separate transposed direct form II state per channel
+struct Biquad {
+    kind: BandKind,
+    frequency: f32,
+    // Flat bands keep their filter but leave the signal alone
+    bypassed: bool,
+    coefficients: Coefficients,
+    state: Vec<[f32; 2]>,
+}
+
+impl Biquad {
+    fn process(&mut self, channel: usize, input: f32) -> f32 {
+        let c = self.coefficients;
+        let z = &mut self.state[channel];
+        let output = c.b0 * input + z[0];
+        z[0] = c.b1 * input - c.a1 * output + z[1];
+        z[1] = c.b2 * input - c.a2 * output;
+        output
+    }
+}
+
+/// `rodio::Source` adapter running the band filters over interleaved samples
+pub struct EqualizerSource<S> {
+    inner: S,
+    equalizer: Arc<Equalizer>,
+    generation: u64,
+    sample_rate: u32,
+    channels: u16,
+    // Empty when the EQ is disabled or flat, which makes the stage a pass-through
+    filters: Vec<Biquad>,
+    // Cut ahead of the filters by the largest boost, so boosted bands don't clip
+    preamp: f32,
+    channel: usize,
+    since_check: usize,
+}
+
+impl<S> EqualizerSource<S>
+where
+    S: Source<Item = i16>,
+{
+    fn refresh(&mut self) {
+        let generation = self.equalizer.generation.load(Ordering::Acquire);
+        let sample_rate = self.inner.sample_rate();
+        let channels = self.inner.channels();
+
+        if generation == self.generation
+            && sample_rate == self.sample_rate
+            && channels == self.channels
+        {
+            return;
+        }
+
+        let Ok(params) = self.equalizer.params.try_lock() else {
+            // Settings are being written right now; pick them up on the next check
+            return;
+        };
+
+        let layout_changed = sample_rate != self.sample_rate || channels != self.channels;
+        let mut filters: Vec<Biquad> = if params.enabled && sample_rate > 0 {
+            params
+                .bands
+                .iter()
+                .map(|band| Biquad {
+                    kind: band.kind,
+                    frequency: band.frequency,
+                    bypassed: band.gain_db.abs() < FLAT_GAIN_DB,
+                    coefficients: Coefficients::for_band(band, sample_rate),
+                    state: vec![[0.0; 2]; channels as usize],
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let max_boost_db = params
+            .bands
+            .iter()
+            .map(|band| band.gain_db)
+            .fold(0.0, f32::max);
+        drop(params);
+
+        if filters.iter().all(|filter| filter.bypassed) {
+            filters.clear();
+        }
+
+        // Keep filter memory across pure setting changes to avoid clicks. Memory follows the
+        // band it belongs to, and a band coming out of bypass starts from silence.
+        if !layout_changed {
+            for filter in filters.iter_mut().filter(|filter| !filter.bypassed) {
+                let previous = self.filters.iter().find(|previous| {
+                    !previous.bypassed
+                        && previous.kind == filter.kind
+                        && previous.frequency == filter.frequency
+                });
+                if let Some(previous) = previous {
+                    filter.state.clone_from(&previous.state);
+                }
+            }
+        }
+
+        self.preamp = if filters.is_empty() {
+            1.0
+        } else {
+            10f32.powf(-max_boost_db / 20.0)
+        };
+        self.filters = filters;
+        self.generation = generation;
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+    }
+}
+
+impl<S> Iterator for EqualizerSource<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        // Only swap filters on a frame boundary so channels stay aligned
+        if self.channel == 0 {
+            self.since_check += 1;
+            if self.since_check >= PARAMS_CHECK_INTERVAL {
+                self.since_check = 0;
+                self.refresh();
+            }
+        }
+
+        let sample = self.inner.next()?;
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.channels.max(1) as usize;
+
+        if self.filters.is_empty() {
+            return Some(sample);
+        }
+
+        let mut value = sample as f32 / i16::MAX as f32 * self.preamp;
+        for filter in self.filters.iter_mut().filter(|filter| !filter.bypassed) {
+            value = filter.process(channel, value);
+        }
+
+        Some((value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+    }
+}
+
+impl<S> Source for EqualizerSource<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn band(kind: BandKind, frequency: f32, gain_db: f32) -> EqBand {
+        EqBand {
+            kind,
+            frequency,
+            gain_db,
+            q: if kind == BandKind::Peaking {
+                DEFAULT_Q
+            } else {
+                SHELF_Q
+            },
+        }
+    }
+
+    fn equalizer(preset: &str) -> Arc<Equalizer> {
+        let mut config = EqualizerConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        config.apply_preset(preset).unwrap();
+        Arc::new(Equalizer::new(&config))
+    }
+
+    // Magnitude response in dB at `frequency`
+    fn response_db(c: &Coefficients, frequency: f32) -> f32 {
+        let w = 2.0 * PI * frequency / SAMPLE_RATE as f32;
+        let magnitude = |x0: f32, x1: f32, x2: f32| {
+            let re = x0 + x1 * w.cos() + x2 * (2.0 * w).cos();
+            let im = -x1 * w.sin() - x2 * (2.0 * w).sin();
+            re.hypot(im)
+        };
+        20.0 * (magnitude(c.b0, c.b1, c.b2) / magnitude(1.0, c.a1, c.a2)).log10()
+    }
+
+    #[test]
+    fn peaking_band_reaches_its_gain_at_the_center_frequency() {
+        let c = Coefficients::for_band(&band(BandKind::Peaking, 1000.0, 6.0), SAMPLE_RATE);
+        assert!((response_db(&c, 1000.0) - 6.0).abs() < 0.01);
+        assert!(response_db(&c, 0.0).abs() < 0.01);
+        assert!(response_db(&c, 20000.0).abs() < 0.1);
+
+        // At 0 dB numerator and denominator cancel out
+        let flat = Coefficients::for_band(&band(BandKind::Peaking, 1000.0, 0.0), SAMPLE_RATE);
+        assert!((flat.b0 - 1.0).abs() < 1e-6);
+        assert!((flat.b1 - flat.a1).abs() < 1e-6);
+        assert!((flat.b2 - flat.a2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn shelves_apply_their_gain_past_the_corner() {
+        let low = Coefficients::for_band(&band(BandKind::LowShelf, 100.0, 6.0), SAMPLE_RATE);
+        assert!((response_db(&low, 0.0) - 6.0).abs() < 0.01);
+        assert!(response_db(&low, 24000.0).abs() < 0.01);
+
+        let high = Coefficients::for_band(&band(BandKind::HighShelf, 8000.0, -6.0), SAMPLE_RATE);
+        assert!((response_db(&high, 24000.0) + 6.0).abs() < 0.01);
+        assert!(response_db(&high, 0.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn flat_or_disabled_equalizer_passes_samples_through() {
+        let samples: Vec<i16> = (0..4096)
+            .map(|i| ((i * 37) % 20000 - 10000) as i16)
+            .collect();
+
+        let flat = equalizer(PRESET_FLAT);
+        let source = flat.apply(SamplesBuffer::new(2, SAMPLE_RATE, samples.clone()));
+        assert!(source.filters.is_empty());
+        assert_eq!(source.preamp, 1.0);
+        assert_eq!(source.collect::<Vec<_>>(), samples);
+
+        let disabled = Arc::new(Equalizer::new(&EqualizerConfig {
+            enabled: false,
+            bands: bands_with_gains(&BASS_BOOST_GAINS),
+            ..Default::default()
+        }));
+        let source = disabled.apply(SamplesBuffer::new(2, SAMPLE_RATE, samples.clone()));
+        assert_eq!(source.collect::<Vec<_>>(), samples);
+    }
+
+    #[test]
+    fn preamp_leaves_headroom_for_the_largest_boost() {
+        let boosted = equalizer(PRESET_BASS_BOOST);
+        let source = boosted.apply(SamplesBuffer::new(2, SAMPLE_RATE, vec![0i16; 16]));
+        assert!((source.preamp - 10f32.powf(-6.0 / 20.0)).abs() < 1e-6);
+        // The 500 Hz band at 0.5 dB is filtered, the flat ones above it are bypassed
+        assert_eq!(source.filters.iter().filter(|f| !f.bypassed).count(), 5);
+
+        // Cuts alone cannot clip, so they get no preamp
+        let cut = Arc::new(Equalizer::new(&EqualizerConfig {
+            enabled: true,
+            bands: bands_with_gains(&[-3.0; 10]),
+            ..Default::default()
+        }));
+        let source = cut.apply(SamplesBuffer::new(2, SAMPLE_RATE, vec![0i16; 16]));
+        assert_eq!(source.preamp, 1.0);
+
+        // A full-scale low tone through the bass boost stays within range
+        let tone: Vec<i16> = (0..SAMPLE_RATE as usize)
+            .map(|i| ((2.0 * PI * 40.0 * i as f32 / SAMPLE_RATE as f32).sin() * 32767.0) as i16)
+            .collect();
+        let source = boosted.apply(SamplesBuffer::new(1, SAMPLE_RATE, tone));
+        let peak = source.map(|s| s.unsigned_abs()).max().unwrap();
+        assert!(peak < i16::MAX as u16);
+    }
+
+    #[test]
+    fn band_edits_are_clamped_and_mark_the_preset_custom() {
+        let mut config = EqualizerConfig::default();
+        config.set_band_gain(0, 20.0).unwrap();
+        assert_eq!(config.bands[0].gain_db, MAX_GAIN_DB);
+        assert_eq!(config.preset, PRESET_CUSTOM);
+        assert!(config.set_band_gain(10, 1.0).is_err());
+
+        assert!(config.save_preset(PRESET_FLAT).is_err());
+        config.save_preset("mine").unwrap();
+        config.apply_preset(PRESET_FLAT).unwrap();
+        config.apply_preset("mine").unwrap();
+        assert_eq!(config.bands[0].gain_db, MAX_GAIN_DB);
+    }
+}
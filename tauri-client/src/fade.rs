@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Result};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const MAX_FADE_MS: u32 = 5000;
+const MAX_CROSSFADE_MS: u32 = 12000;
+
+/// Persisted fade settings, in milliseconds; 0 turns that fade off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FadeConfig {
+    /// Ramp up whenever playback starts or resumes
+    #[serde(default = "FadeConfig::default_fade_in_ms")]
+    pub fade_in_ms: u32,
+    /// Ramp down before playback stops or pauses
+    #[serde(default = "FadeConfig::default_fade_out_ms")]
+    pub fade_out_ms: u32,
+    /// Overlap of the outgoing and incoming track on a track change
+    #[serde(default)]
+    pub crossfade_ms: u32,
+}
+
+impl Default for FadeConfig {
+    fn default() -> Self {
+        Self {
+            fade_in_ms: Self::default_fade_in_ms(),
+            fade_out_ms: Self::default_fade_out_ms(),
+            crossfade_ms: 0,
+        }
+    }
+}
+
+impl FadeConfig {
+    const fn default_fade_in_ms() -> u32 {
+        150
+    }
+
+    const fn default_fade_out_ms() -> u32 {
+        200
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.fade_in_ms > MAX_FADE_MS || self.fade_out_ms > MAX_FADE_MS {
+            return Err(anyhow!("Fades are limited to {}ms", MAX_FADE_MS));
+        }
+        if self.crossfade_ms > MAX_CROSSFADE_MS {
+            return Err(anyhow!("Crossfade is limited to {}ms", MAX_CROSSFADE_MS));
+        }
+        Ok(())
+    }
+}
+
+/// Live fade settings shared between the UI commands and the audio task
+pub struct FadeSettings {
+    fade_in_ms: AtomicU32,
+    fade_out_ms: AtomicU32,
+    crossfade_ms: AtomicU32,
+}
+
+impl FadeSettings {
+    pub fn new(config: &FadeConfig) -> Self {
+        let settings = Self {
+            fade_in_ms: AtomicU32::new(0),
+            fade_out_ms: AtomicU32::new(0),
+            crossfade_ms: AtomicU32::new(0),
+        };
+        settings.update(config);
+        settings
+    }
+
+    pub fn update(&self, config: &FadeConfig) {
+        self.fade_in_ms
+            .store(config.fade_in_ms.min(MAX_FADE_MS), Ordering::Relaxed);
+        self.fade_out_ms
+            .store(config.fade_out_ms.min(MAX_FADE_MS), Ordering::Relaxed);
+        self.crossfade_ms
+            .store(config.crossfade_ms.min(MAX_CROSSFADE_MS), Ordering::Relaxed);
+    }
+
+    pub fn fade_in(&self) -> Duration {
+        Duration::from_millis(self.fade_in_ms.load(Ordering::Relaxed) as u64)
+    }
+
+    pub fn fade_out(&self) -> Duration {
+        Duration::from_millis(self.fade_out_ms.load(Ordering::Relaxed) as u64)
+    }
+
+    /// `None` when track changes should cut over without overlapping
+    pub fn crossfade(&self) -> Option<Duration> {
+        match self.crossfade_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms as u64)),
+        }
+    }
+}
+
+/// Gain ramp for everything queued on one sink.
+///
+/// The audio task sets a target and a ramp length; sources pick the change up on their next
+/// frame and step towards the target sample by sample. Only the gain moves: every frame is
+/// still played, so the playback clock and the synced position are unaffected.
+pub struct Fader {
+    target: AtomicU32,
+    ramp_ms: AtomicU32,
+    generation: AtomicU64,
+}
+
+impl Fader {
+    /// Fader that starts out at `gain` (0.0 to fade in from silence, 1.0 for no fade)
+    pub fn new(gain: f32) -> Arc<Self> {
+        Arc::new(Self {
+            target: AtomicU32::new(gain.to_bits()),
+            ramp_ms: AtomicU32::new(0),
+            generation: AtomicU64::new(0),
+        })
+    }
+
+    /// Move to `gain` over `duration`, starting from wherever the ramp currently is
+    pub fn fade_to(&self, gain: f32, duration: Duration) {
+        self.target
+            .store(gain.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+        self.ramp_ms.store(
+            duration.as_millis().min(u32::MAX as u128) as u32,
+            Ordering::Relaxed,
+        );
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Wrap `source` in a stage that follows this fader
+    pub fn apply<S>(self: &Arc<Self>, source: S) -> FadeSource<S>
+    where
+        S: Source<Item = i16>,
+    {
+        FadeSource {
+            inner: source,
+            fader: self.clone(),
+            generation: u64::MAX,
+            gain: f32::from_bits(self.target.load(Ordering::Relaxed)),
+            target: f32::from_bits(self.target.load(Ordering::Relaxed)),
+            step: 0.0,
+            channel: 0,
+        }
+    }
+}
+
+pub struct FadeSource<S> {
+    inner: S,
+    fader: Arc<Fader>,
+    generation: u64,
+    gain: f32,
+    target: f32,
+    // Gain change per frame while ramping
+    step: f32,
+    channel: usize,
+}
+
+impl<S> FadeSource<S>
+where
+    S: Source<Item = i16>,
+{
+    /// Pick up a new target and advance the ramp by one frame
+    fn start_frame(&mut self) {
+        let generation = self.fader.generation.load(Ordering::Acquire);
+        if generation != self.generation {
+            self.generation = generation;
+            self.target = f32::from_bits(self.fader.target.load(Ordering::Relaxed));
+
+            let ramp_frames = self.fader.ramp_ms.load(Ordering::Relaxed) as f32 / 1000.0
+                * self.inner.sample_rate() as f32;
+            if ramp_frames >= 1.0 {
+                self.step = (self.target - self.gain) / ramp_frames;
+            } else {
+                self.gain = self.target;
+                self.step = 0.0;
+            }
+        }
+
+        if self.step != 0.0 {
+            self.gain += self.step;
+            let reached = if self.step > 0.0 {
+                self.gain >= self.target
+            } else {
+                self.gain <= self.target
+            };
+            if reached {
+                self.gain = self.target;
+                self.step = 0.0;
+            }
+        }
+    }
+}
+
+impl<S> Iterator for FadeSource<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.channel == 0 {
+            self.start_frame();
+        }
+
+        let sample = self.inner.next()?;
+        self.channel = (self.channel + 1) % self.inner.channels().max(1) as usize;
+
+        if self.gain >= 1.0 {
+            Some(sample)
+        } else {
+            // Squared for a roughly even perceived ramp
+            Some((sample as f32 * self.gain * self.gain) as i16)
+        }
+    }
+}
+
+impl<S> Source for FadeSource<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
@@ -0,0 +1,617 @@
+use crate::byte_ring::ByteRing;
+use crate::http_stream::{HttpStatusError, READ_TIMEOUT};
+use crate::stream_stats::StreamStats;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use reqwest::Client;
+use std::io::{Error as IoError, ErrorKind, Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use symphonia::core::io::MediaSource;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+const SEGMENT_RETRIES: u32 = 3;
+const SEGMENT_RETRY_DELAY_MS: u64 = 500;
+const SEGMENT_TIMEOUT_SECS: u64 = 30;
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0x0000;
+const STREAM_TYPE_ADTS_AAC: u8 = 0x0F;
+
+#[derive(Debug, Clone)]
+pub struct HlsSegment {
+    pub url: String,
+    pub start: f64,
+    pub duration: f64,
+}
+
+/// Media playlist as served by `/api/music/hls/:youtubeId/playlist.m3u8`
+#[derive(Debug, Clone)]
+pub struct HlsPlaylist {
+    pub segments: Vec<HlsSegment>,
+}
+
+impl HlsPlaylist {
+    /// Playlist location for `youtube_id` on the backend that serves `stream_url`
+    pub fn url_for(stream_url: &str, youtube_id: &str) -> Option<String> {
+        let (backend, _) = stream_url.split_once("/api/")?;
+        Some(format!(
+            "{}/api/music/hls/{}/playlist.m3u8",
+            backend, youtube_id
+        ))
+    }
+
+    pub async fn fetch(client: &Client, url: &str) -> Result<Self> {
+        let response = client
+            .get(url)
+            .timeout(Duration::from_secs(SEGMENT_TIMEOUT_SECS))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to fetch HLS playlist: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "HLS playlist responded with status {}",
+                response.status()
+            ));
+        }
+
+        let text = response
+            .text()
+            .await
+            .map_err(|e| anyhow!("Failed to read HLS playlist: {}", e))?;
+
+        Self::parse(url, &text)
+    }
+
+    fn parse(playlist_url: &str, text: &str) -> Result<Self> {
+        if !text.trim_start().starts_with("#EXTM3U") {
+            return Err(anyhow!("Not an HLS playlist"));
+        }
+
+        // Segment URIs are relative to the playlist location
+        let base = playlist_url
+            .split('?')
+            .next()
+            .and_then(|url| url.rsplit_once('/'))
+            .map(|(base, _)| base)
+            .unwrap_or(playlist_url);
+
+        let mut segments = Vec::new();
+        let mut start = 0.0;
+        let mut pending_duration = None;
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if let Some(info) = line.strip_prefix("#EXTINF:") {
+                let duration = info
+                    .split(',')
+                    .next()
+                    .and_then(|value| value.trim().parse::<f64>().ok())
+                    .ok_or_else(|| anyhow!("Invalid EXTINF line: {}", line))?;
+                pending_duration = Some(duration);
+            } else if !line.starts_with('#') {
+                let duration = pending_duration
+                    .take()
+                    .ok_or_else(|| anyhow!("Segment {} has no EXTINF", line))?;
+                let url = if line.starts_with("http://") || line.starts_with("https://") {
+                    line.to_string()
+                } else {
+                    format!("{}/{}", base, line)
+                };
+
+                segments.push(HlsSegment {
+                    url,
+                    start,
+                    duration,
+                });
+                start += duration;
+            }
+        }
+
+        if segments.is_empty() {
+            return Err(anyhow!("HLS playlist contains no segments"));
+        }
+
+        Ok(Self { segments })
+    }
+
+    pub fn total_duration(&self) -> f64 {
+        self.segments
+            .last()
+            .map(|segment| segment.start + segment.duration)
+            .unwrap_or(0.0)
+    }
+
+    /// Index of the segment containing `position` seconds
+    pub fn segment_at(&self, position: f64) -> usize {
+        self.segments
+            .iter()
+            .rposition(|segment| segment.start <= position)
+            .unwrap_or(0)
+    }
+}
+
+/// Sequential reader over the ADTS audio of consecutive HLS segments.
+///
+/// A background task downloads segments ahead into a ring buffer for as long as it has
+/// room, retrying each one on its own, and demuxes the MPEG-TS payload so the decoder sees
+/// one continuous ADTS stream. As with `HttpStreamReader`, `Read` blocks for at most
+/// `READ_TIMEOUT` waiting for data. Seeking is done by opening a new reader at the segment
+/// that contains the target.
+pub struct HlsStreamReader {
+    ring: Arc<ByteRing>,
+    position: u64,
+    fetch_task: JoinHandle<()>,
+    stats: Arc<StreamStats>,
+}
+
+impl HlsStreamReader {
+    pub fn new(
+        client: Client,
+        playlist: Arc<HlsPlaylist>,
+        first_segment: usize,
+        stats: Arc<StreamStats>,
+        runtime: Handle,
+    ) -> Self {
+        let ring = Arc::new(ByteRing::new(0));
+        stats.begin_range(0, None);
+
+        let fetch_ring = ring.clone();
+        let fetch_stats = stats.clone();
+        let fetch_task = runtime.spawn(async move {
+            for (index, segment) in playlist.segments.iter().enumerate().skip(first_segment) {
+                match fetch_segment(&client, segment, &fetch_stats).await {
+                    Ok(data) => {
+                        fetch_stats.record_download(data.len());
+                        write_segment(&fetch_ring, data).await;
+                    }
+                    Err(e) => {
+                        println!("HLS: Giving up on segment {}: {}", index, e);
+                        fetch_ring.finish(Some(IoError::other(e)));
+                        return;
+                    }
+                }
+            }
+            fetch_stats.mark_complete();
+            fetch_ring.finish(None);
+        });
+
+        Self {
+            ring,
+            position: 0,
+            fetch_task,
+            stats,
+        }
+    }
+}
+
+/// Append a demuxed segment to the ring, waiting for the reader to make room as needed
+async fn write_segment(ring: &ByteRing, mut data: Bytes) {
+    while !data.is_empty() {
+        let written = ring.write(&data);
+        data = data.slice(written..);
+        if !data.is_empty() {
+            ring.wait_for_space().await;
+        }
+    }
+}
+
+async fn fetch_segment(
+    client: &Client,
+    segment: &HlsSegment,
+    stats: &StreamStats,
+) -> Result<Bytes> {
+    let mut last_error = anyhow!("Segment was never requested");
+
+    for attempt in 0..=SEGMENT_RETRIES {
+        if attempt > 0 {
+            stats.record_reconnect();
+            tokio::time::sleep(Duration::from_millis(
+                SEGMENT_RETRY_DELAY_MS * attempt as u64,
+            ))
+            .await;
+        }
+
+        let response = client
+            .get(&segment.url)
+            .timeout(Duration::from_secs(SEGMENT_TIMEOUT_SECS))
+            .send()
+            .await;
+
+        let data = match response {
+            Ok(response) if response.status().is_success() => response.bytes().await,
+            Ok(response) => {
+                last_error = HttpStatusError::new("HLS segment", response.status()).into();
+                continue;
+            }
+            Err(e) => Err(e),
+        };
+
+        match data {
+            Ok(data) => return demux_adts(&data).map(Bytes::from),
+            Err(e) => last_error = anyhow::Error::new(e).context("HLS segment download failed"),
+        }
+    }
+
+    Err(last_error)
+}
+
+impl Read for HlsStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut read = self.ring.read_at(self.position, buf);
+        if read == 0 {
+            let wait_started = Instant::now();
+            self.stats.set_waiting(true);
+            let ready = self.ring.wait_for_data(self.position, READ_TIMEOUT);
+            self.stats.set_waiting(false);
+            self.stats.record_wait(wait_started.elapsed());
+
+            if !ready {
+                return Err(IoError::new(
+                    ErrorKind::TimedOut,
+                    format!("No HLS data arrived within {}s", READ_TIMEOUT.as_secs()),
+                ));
+            }
+
+            read = self.ring.read_at(self.position, buf);
+            if read == 0 {
+                // The fetch task finished, or gave up on a segment
+                return match self.ring.error() {
+                    Some(err) => Err(err),
+                    None => Ok(0),
+                };
+            }
+        }
+
+        self.position += read as u64;
+        // The reader never seeks back, so read data can make room for the next segment
+        self.ring.release(self.position);
+        self.stats.set_read_position(self.position);
+        Ok(read)
+    }
+}
+
+impl Seek for HlsStreamReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.position),
+            _ => Err(IoError::new(
+                ErrorKind::Unsupported,
+                "HLS streams are repositioned by segment, not by byte",
+            )),
+        }
+    }
+}
+
+impl MediaSource for HlsStreamReader {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl Drop for HlsStreamReader {
+    fn drop(&mut self) {
+        self.fetch_task.abort();
+    }
+}
+
+/// Extract the ADTS AAC elementary stream from an MPEG transport stream segment
+fn demux_adts(ts: &[u8]) -> Result<Vec<u8>> {
+    let start = ts
+        .iter()
+        .position(|&byte| byte == TS_SYNC_BYTE)
+        .ok_or_else(|| anyhow!("Segment is not an MPEG transport stream"))?;
+
+    let mut pmt_pid = None;
+    let mut audio_pid = None;
+    let mut audio = Vec::with_capacity(ts.len());
+
+    for packet in ts[start..].chunks_exact(TS_PACKET_SIZE) {
+        if packet[0] != TS_SYNC_BYTE {
+            return Err(anyhow!("Lost MPEG-TS sync"));
+        }
+
+        let payload_start = packet[1] & 0x40 != 0;
+        let pid = (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16;
+        let adaptation = (packet[3] >> 4) & 0x03;
+
+        let mut offset = 4;
+        if adaptation & 0x02 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+        if adaptation & 0x01 == 0 || offset >= TS_PACKET_SIZE {
+            continue;
+        }
+        let payload = &packet[offset..];
+
+        if pid == PAT_PID && payload_start {
+            pmt_pid = parse_pat(payload);
+        } else if Some(pid) == pmt_pid && payload_start {
+            audio_pid = parse_pmt(payload);
+        } else if Some(pid) == audio_pid {
+            if payload_start {
+                audio.extend_from_slice(skip_pes_header(payload)?);
+            } else {
+                audio.extend_from_slice(payload);
+            }
+        }
+    }
+
+    if audio_pid.is_none() {
+        return Err(anyhow!("Segment contains no AAC audio stream"));
+    }
+
+    Ok(audio)
+}
+
+/// PSI section body following the pointer field, bounded by its section length
+fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    let length = ((*section.get(1)? as usize & 0x0F) << 8) | *section.get(2)? as usize;
+    // Header is 3 bytes; the trailing CRC32 is not needed
+    section.get(..(3 + length).saturating_sub(4))
+}
+
+fn parse_pat(payload: &[u8]) -> Option<u16> {
+    let section = psi_section(payload)?;
+    section
+        .get(8..)?
+        .chunks_exact(4)
+        .find(|program| program[0] != 0 || program[1] != 0)
+        .map(|program| (((program[2] & 0x1F) as u16) << 8) | program[3] as u16)
+}
+
+fn parse_pmt(payload: &[u8]) -> Option<u16> {
+    let section = psi_section(payload)?;
+    let program_info_length =
+        ((*section.get(10)? as usize & 0x0F) << 8) | *section.get(11)? as usize;
+    let mut streams = section.get(12 + program_info_length..)?;
+
+    while streams.len() >= 5 {
+        let stream_type = streams[0];
+        let pid = (((streams[1] & 0x1F) as u16) << 8) | streams[2] as u16;
+        let info_length = (((streams[3] & 0x0F) as usize) << 8) | streams[4] as usize;
+
+        if stream_type == STREAM_TYPE_ADTS_AAC {
+            return Some(pid);
+        }
+        streams = streams.get(5 + info_length..)?;
+    }
+
+    None
+}
+
+fn skip_pes_header(payload: &[u8]) -> Result<&[u8]> {
+    if payload.len() < 9 || payload[..3] != [0x00, 0x00, 0x01] {
+        return Err(anyhow!("Malformed PES packet in HLS segment"));
+    }
+
+    let header_length = payload[8] as usize;
+    payload
+        .get(9 + header_length..)
+        .ok_or_else(|| anyhow!("Truncated PES header in HLS segment"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PMT_PID: u16 = 0x1000;
+    const AUDIO_PID: u16 = 0x0101;
+    const PLAYLIST_URL: &str = "https://miu.example/api/music/hls/abc/playlist.m3u8?t=1";
+
+    /// One transport stream packet, padded to size with adaptation field stuffing
+    fn packet(pid: u16, payload_start: bool, payload: &[u8]) -> Vec<u8> {
+        let max_payload = TS_PACKET_SIZE - 4;
+        assert!(payload.len() <= max_payload);
+
+        let mut packet = vec![
+            TS_SYNC_BYTE,
+            ((payload_start as u8) << 6) | (pid >> 8) as u8,
+            pid as u8,
+        ];
+        if payload.len() == max_payload {
+            packet.push(0x10);
+        } else {
+            let adaptation_length = max_payload - 1 - payload.len();
+            packet.extend([0x30, adaptation_length as u8]);
+            if adaptation_length > 0 {
+                packet.push(0x00);
+                packet.resize(packet.len() + adaptation_length - 1, 0xFF);
+            }
+        }
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// PSI section with a pointer field, `body` following the 8 byte header, and a dummy CRC
+    fn psi(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let length = 5 + body.len() + 4;
+        let mut section = vec![
+            0x00,
+            table_id,
+            0xB0 | (length >> 8) as u8,
+            length as u8,
+            0x00,
+            0x01,
+            0xC1,
+            0x00,
+            0x00,
+        ];
+        section.extend_from_slice(body);
+        section.extend([0xDE, 0xAD, 0xBE, 0xEF]);
+        section
+    }
+
+    fn pat() -> Vec<u8> {
+        // The network entry (program 0) comes first and is skipped
+        psi(
+            0x00,
+            &[
+                0x00,
+                0x00,
+                0xE0,
+                0x10,
+                0x00,
+                0x01,
+                0xE0 | (PMT_PID >> 8) as u8,
+                PMT_PID as u8,
+            ],
+        )
+    }
+
+    fn pmt() -> Vec<u8> {
+        psi(
+            0x02,
+            &[
+                // PCR PID, no program info
+                0xE1,
+                0x00,
+                0xF0,
+                0x00,
+                // H.264 video with two bytes of stream info
+                0x1B,
+                0xE1,
+                0x00,
+                0xF0,
+                0x02,
+                0x0A,
+                0x0B,
+                // The AAC audio to extract
+                STREAM_TYPE_ADTS_AAC,
+                0xE0 | (AUDIO_PID >> 8) as u8,
+                AUDIO_PID as u8,
+                0xF0,
+                0x00,
+            ],
+        )
+    }
+
+    /// PES packet start with a PTS, followed by `data`
+    fn pes(data: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0x00, 0x00, 0x01, 0xC0, 0x00, 0x00, 0x80, 0x80, 0x05];
+        pes.extend([0x21, 0x00, 0x01, 0x00, 0x01]);
+        pes.extend_from_slice(data);
+        pes
+    }
+
+    fn adts(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn parse_accumulates_durations_and_resolves_segment_uris() {
+        let text = "#EXTM3U\n\
+            #EXT-X-VERSION:3\n\
+            #EXT-X-TARGETDURATION:10\n\
+            #EXTINF:10.0,\n\
+            segment0.ts\n\
+            \n\
+            #EXTINF:9.5, title\n\
+            https://cdn.example/segment1.ts\n\
+            #EXTINF:4.25\n\
+            segment2.ts?part=2\n\
+            #EXT-X-ENDLIST\n";
+        let playlist = HlsPlaylist::parse(PLAYLIST_URL, text).unwrap();
+
+        let urls: Vec<&str> = playlist.segments.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://miu.example/api/music/hls/abc/segment0.ts",
+                "https://cdn.example/segment1.ts",
+                "https://miu.example/api/music/hls/abc/segment2.ts?part=2",
+            ]
+        );
+        let starts: Vec<f64> = playlist.segments.iter().map(|s| s.start).collect();
+        assert_eq!(starts, [0.0, 10.0, 19.5]);
+        assert_eq!(playlist.total_duration(), 23.75);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_playlists() {
+        assert!(HlsPlaylist::parse(PLAYLIST_URL, "segment0.ts\n").is_err());
+        assert!(HlsPlaylist::parse(PLAYLIST_URL, "#EXTM3U\nsegment0.ts\n").is_err());
+        assert!(HlsPlaylist::parse(PLAYLIST_URL, "#EXTM3U\n#EXTINF:abc,\nsegment0.ts\n").is_err());
+        assert!(HlsPlaylist::parse(PLAYLIST_URL, "#EXTM3U\n#EXT-X-ENDLIST\n").is_err());
+    }
+
+    #[test]
+    fn segment_at_finds_the_segment_holding_a_position() {
+        let text = "#EXTM3U\n#EXTINF:10,\na.ts\n#EXTINF:10,\nb.ts\n#EXTINF:5,\nc.ts\n";
+        let playlist = HlsPlaylist::parse(PLAYLIST_URL, text).unwrap();
+
+        assert_eq!(playlist.segment_at(-1.0), 0);
+        assert_eq!(playlist.segment_at(0.0), 0);
+        assert_eq!(playlist.segment_at(9.99), 0);
+        assert_eq!(playlist.segment_at(10.0), 1);
+        assert_eq!(playlist.segment_at(24.0), 2);
+        assert_eq!(playlist.segment_at(100.0), 2);
+    }
+
+    #[test]
+    fn url_for_points_at_the_playlist_of_the_track() {
+        assert_eq!(
+            HlsPlaylist::url_for("https://miu.example/api/music/stream?x=1", "abc").as_deref(),
+            Some("https://miu.example/api/music/hls/abc/playlist.m3u8")
+        );
+        assert_eq!(
+            HlsPlaylist::url_for("https://miu.example/stream", "abc"),
+            None
+        );
+    }
+
+    #[test]
+    fn demux_reassembles_pes_payloads_across_packets() {
+        let first = adts(150);
+        let second = adts(400);
+        let pes_first = pes(&first);
+        let pes_second = pes(&second);
+
+        let mut ts = vec![0x00, 0x00];
+        ts.extend(packet(PAT_PID, true, &pat()));
+        ts.extend(packet(PMT_PID, true, &pmt()));
+        // Packets of other streams are ignored
+        ts.extend(packet(0x0100, true, &[0xAA; 184]));
+        ts.extend(packet(AUDIO_PID, true, &pes_first));
+        ts.extend(packet(AUDIO_PID, true, &pes_second[..184]));
+        ts.extend(packet(AUDIO_PID, false, &pes_second[184..368]));
+        ts.extend(packet(AUDIO_PID, false, &pes_second[368..]));
+
+        let mut expected = first;
+        expected.extend(second);
+        assert_eq!(demux_adts(&ts).unwrap(), expected);
+    }
+
+    #[test]
+    fn demux_rejects_segments_without_aac_audio() {
+        assert!(demux_adts(&[0u8; 400]).is_err());
+
+        let mut ts = packet(PAT_PID, true, &pat());
+        ts.extend(packet(AUDIO_PID, true, &pes(&adts(10))));
+        assert!(demux_adts(&ts).is_err());
+
+        // A payload unit that does not start with a PES header
+        let mut ts = packet(PAT_PID, true, &pat());
+        ts.extend(packet(PMT_PID, true, &pmt()));
+        ts.extend(packet(AUDIO_PID, true, &adts(20)));
+        assert!(demux_adts(&ts).is_err());
+
+        // Sync lost halfway through
+        let mut ts = packet(PAT_PID, true, &pat());
+        ts.extend(packet(PMT_PID, true, &pmt()));
+        ts.extend(vec![0x00; TS_PACKET_SIZE]);
+        assert!(demux_adts(&ts).is_err());
+    }
+}
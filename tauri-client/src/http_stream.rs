@@ -0,0 +1,723 @@
+use crate::byte_ring::ByteRing;
+use crate::cache::CacheFill;
+use crate::read_ahead::ReadAhead;
+use crate::stream_stats::StreamStats;
+use crate::stream_token::StreamToken;
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use reqwest::header::{HeaderValue, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::{Client, Response, StatusCode};
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use symphonia::core::io::MediaSource;
+use tokio::sync::mpsc;
+
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_BASE_DELAY_MS: u64 = 250; // Doubled after every failed attempt
+const RECONNECT_MAX_DELAY_MS: u64 = 4000;
+// All reconnect attempts together, backoff included. A stalled chunk plus the reconnect
+// stays below READ_TIMEOUT, so a reconnect that works out is never reported as a read timeout
+const RECONNECT_BUDGET: Duration = Duration::from_secs(30);
+// Waiting for the response headers of a stream request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Waiting for the next chunk of a response body; flow-control pauses do not count
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(10);
+// Longest a read blocks on the network; the fetcher gives up on a dead connection before that
+pub const READ_TIMEOUT: Duration = Duration::from_secs(45);
+// Already-read data kept in memory for seeking backwards
+const SEEK_BACK_WINDOW: u64 = 1024 * 1024;
+// Forward seeks at most this far past the downloaded data wait for it instead of reconnecting
+const SEEK_AHEAD_WAIT: u64 = 256 * 1024;
+
+/// Non-success response to a stream, token or segment request
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub request: &'static str,
+    pub status: StatusCode,
+}
+
+impl HttpStatusError {
+    pub fn new(request: &'static str, status: StatusCode) -> Self {
+        Self { request, status }
+    }
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} responded with status {}", self.request, self.status)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// Byte range the fetcher should continue from, written into the ring once it has been
+/// reset for it
+struct RangeRequest {
+    offset: u64,
+    epoch: u64,
+}
+
+/// Seekable symphonia media source over an HTTP stream.
+///
+/// A background task downloads into a ring buffer, paced by the read-ahead window. `Read`
+/// only copies out bytes that have already arrived, blocking for at most `READ_TIMEOUT`
+/// when it catches up with the download. Seeks inside the ring are served from memory;
+/// anything else resets the ring and hands the fetcher the range to open.
+///
+/// Given a `CacheFill`, the fetcher also copies everything it downloads into the cache, so
+/// a track that plays to the end is cached without being downloaded a second time. Only
+/// the parts a seek skipped over are fetched separately, once the reader is done.
+pub struct HttpStreamReader {
+    ring: Arc<ByteRing>,
+    position: u64,
+    total_length: Option<u64>,
+    content_type: Option<String>,
+    requests: mpsc::UnboundedSender<RangeRequest>,
+    stats: Arc<StreamStats>,
+    read_ahead: Arc<ReadAhead>,
+}
+
+impl HttpStreamReader {
+    pub async fn open(
+        client: Client,
+        url: String,
+        token: Option<Arc<StreamToken>>,
+        stats: Arc<StreamStats>,
+        read_ahead: Arc<ReadAhead>,
+        start_offset: u64,
+        mut cache_fill: Option<CacheFill>,
+    ) -> Result<Self> {
+        let mut url = url;
+        let response = open_range(&client, &mut url, token.as_deref(), start_offset)
+            .await
+            .context("Failed to start audio stream")?;
+
+        let total_length = total_length_from_response(&response);
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        if let Some(fill) = cache_fill.as_mut() {
+            fill.set_content_type(content_type.clone());
+        }
+        let ring = Arc::new(ByteRing::new(start_offset));
+        stats.begin_range(start_offset, total_length);
+
+        let (requests, request_rx) = mpsc::unbounded_channel();
+        let fetcher = Fetcher {
+            client,
+            url,
+            token,
+            stats: stats.clone(),
+            read_ahead: read_ahead.clone(),
+            ring: ring.clone(),
+            epoch: ring.epoch(),
+            offset: start_offset,
+            response: Some(response),
+            total_length,
+            cache_fill,
+            reached_end: false,
+        };
+        // Ends by itself once the reader, and with it the request channel, is dropped
+        tokio::spawn(fetcher.run(request_rx));
+
+        Ok(Self {
+            ring,
+            position: start_offset,
+            total_length,
+            content_type,
+            requests,
+            stats,
+            read_ahead,
+        })
+    }
+
+    /// `Content-Type` the server declared for the stream
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Drop the downloaded data and have the fetcher continue from `offset`
+    fn reopen_at(&mut self, offset: u64) -> std::io::Result<()> {
+        let epoch = self.ring.reset(offset);
+        if Some(offset) == self.total_length {
+            self.ring.finish_range(epoch, None);
+        }
+
+        self.requests
+            .send(RangeRequest { offset, epoch })
+            .map_err(|_| IoError::other("Stream fetcher has stopped"))?;
+
+        self.position = offset;
+        Ok(())
+    }
+}
+
+impl Read for HttpStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut read = self.ring.read_at(self.position, buf);
+        if read == 0 {
+            let wait_started = Instant::now();
+            self.stats.set_waiting(true);
+            let ready = self.ring.wait_for_data(self.position, READ_TIMEOUT);
+            self.stats.set_waiting(false);
+            if self.stats.record_wait(wait_started.elapsed()) {
+                self.read_ahead.record_stall();
+            }
+
+            if !ready {
+                return Err(IoError::new(
+                    ErrorKind::TimedOut,
+                    format!("No stream data arrived within {}s", READ_TIMEOUT.as_secs()),
+                ));
+            }
+
+            read = self.ring.read_at(self.position, buf);
+            if read == 0 {
+                // The fetcher finished without reaching this position
+                return match self.ring.error() {
+                    Some(err) => Err(err),
+                    None => Ok(0),
+                };
+            }
+        }
+
+        self.position += read as u64;
+        self.ring
+            .release(self.position.saturating_sub(SEEK_BACK_WINDOW));
+        self.stats.set_read_position(self.position);
+        self.read_ahead.notify_consumed();
+
+        Ok(read)
+    }
+}
+
+impl Seek for HttpStreamReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target: i128 = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::Current(delta) => self.position as i128 + delta as i128,
+            SeekFrom::End(delta) => {
+                let total = self.total_length.ok_or_else(|| {
+                    IoError::new(
+                        ErrorKind::Unsupported,
+                        "Total length unknown for SeekFrom::End",
+                    )
+                })?;
+                total as i128 + delta as i128
+            }
+        };
+
+        if target < 0 {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "Seek before start of stream",
+            ));
+        }
+
+        let target = target as u64;
+
+        if let Some(total) = self.total_length {
+            if target > total {
+                return Err(IoError::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Seek beyond end of stream: target={}, total={}",
+                        target, total
+                    ),
+                ));
+            }
+        }
+
+        let end = self.ring.end();
+        let arriving_soon =
+            target > end && target - end <= SEEK_AHEAD_WAIT && !self.ring.is_finished();
+
+        if self.ring.contains(target) || arriving_soon {
+            self.position = target;
+            // A full ring would leave the fetcher waiting for space it never gets while
+            // this reader waits for the data at `target`
+            self.ring.release(target.saturating_sub(SEEK_BACK_WINDOW));
+        } else {
+            self.reopen_at(target)?;
+        }
+
+        Ok(self.position)
+    }
+}
+
+impl MediaSource for HttpStreamReader {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.total_length
+    }
+}
+
+/// Download side of an `HttpStreamReader`
+struct Fetcher {
+    client: Client,
+    url: String,
+    // Set when streaming through a secure token, which has to be renewed now and then
+    token: Option<Arc<StreamToken>>,
+    stats: Arc<StreamStats>,
+    read_ahead: Arc<ReadAhead>,
+    ring: Arc<ByteRing>,
+    // Ring epoch of the range being downloaded; writes for it stop counting once the reader
+    // resets the ring for a seek
+    epoch: u64,
+    // Stream offset of the next byte the current range delivers
+    offset: u64,
+    // None once the current range is fully downloaded or has failed
+    response: Option<Response>,
+    total_length: Option<u64>,
+    cache_fill: Option<CacheFill>,
+    // The current range was downloaded all the way to the end of the stream
+    reached_end: bool,
+}
+
+impl Fetcher {
+    async fn run(mut self, mut requests: mpsc::UnboundedReceiver<RangeRequest>) {
+        loop {
+            let request = tokio::select! {
+                request = requests.recv() => request,
+                _ = self.pump(), if self.response.is_some() => continue,
+            };
+
+            let Some(mut request) = request else {
+                // The reader is gone; a stream that was played to its end gets cached
+                if self.reached_end {
+                    self.complete_fill();
+                }
+                return;
+            };
+
+            // Only the latest seek matters
+            while let Ok(newer) = requests.try_recv() {
+                request = newer;
+            }
+            self.open(request).await;
+        }
+    }
+
+    /// Move one chunk from the network into the ring
+    async fn pump(&mut self) {
+        let Some(response) = self.response.as_mut() else {
+            return;
+        };
+
+        // Flow control: stop pulling from the socket while the window is full, and time only
+        // the wait for data so pauses do not read as network jitter
+        self.read_ahead.wait_for_room(&self.stats).await;
+        let requested = Instant::now();
+
+        match next_chunk(response).await {
+            Ok(Some(chunk)) => {
+                self.stats.record_download(chunk.len());
+                self.read_ahead.record_chunk(
+                    chunk.len(),
+                    requested.elapsed(),
+                    self.stats.byte_rate(),
+                );
+                let offset = self.offset;
+                self.offset += chunk.len() as u64;
+                self.write(chunk.clone()).await;
+                self.tee(offset, &chunk).await;
+            }
+            Ok(None) => {
+                self.stats.mark_complete();
+                self.ring.finish_range(self.epoch, None);
+                self.response = None;
+                self.reached_end = true;
+            }
+            Err(err) => {
+                let cause = format!("HTTP chunk error: {}", err);
+                let resumed = resume_stream(
+                    &self.client,
+                    &mut self.url,
+                    self.token.as_deref(),
+                    self.offset,
+                    self.total_length,
+                    cause,
+                )
+                .await;
+
+                match resumed {
+                    Ok(response) => {
+                        self.stats.record_reconnect();
+                        self.read_ahead.record_stall();
+                        self.response = Some(response);
+                    }
+                    Err(err) => {
+                        self.ring.finish_range(self.epoch, Some(err));
+                        self.response = None;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn write(&self, mut chunk: Bytes) {
+        while !chunk.is_empty() {
+            // The reader moved on to another range; that request is up next
+            let Some(written) = self.ring.write_range(self.epoch, &chunk) else {
+                return;
+            };
+            chunk = chunk.slice(written..);
+            if !chunk.is_empty() {
+                self.ring.wait_for_space().await;
+            }
+        }
+    }
+
+    /// Copy a downloaded chunk into the cache fill; a fill that can't be written is dropped
+    async fn tee(&mut self, offset: u64, chunk: &[u8]) {
+        let Some(fill) = self.cache_fill.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = fill.write_at(offset, chunk).await {
+            println!("Cache: Not caching stream: {}", e);
+            self.cache_fill = None;
+        }
+    }
+
+    /// Download whatever seeks skipped over, then commit the fill. Runs detached, since the
+    /// reader that started the stream is gone by now.
+    fn complete_fill(&mut self) {
+        let Some(mut fill) = self.cache_fill.take() else {
+            return;
+        };
+
+        // Without a declared length, reaching the end is what tells it
+        let total_length = self.total_length.unwrap_or(self.offset);
+        let client = self.client.clone();
+        let mut url = self.url.clone();
+        let token = self.token.clone();
+
+        tokio::spawn(async move {
+            for gap in fill.missing(total_length) {
+                let filled = fill_range(
+                    &client,
+                    &mut url,
+                    token.as_deref(),
+                    gap,
+                    total_length,
+                    &mut fill,
+                )
+                .await;
+                if let Err(e) = filled {
+                    println!("Cache: Not caching stream: {}", e);
+                    return;
+                }
+            }
+
+            match fill.commit().await {
+                Ok(path) => println!("Cache: Stored {}", path.display()),
+                Err(e) => println!("Cache: Failed to store stream: {}", e),
+            }
+        });
+    }
+
+    /// Switch to the range of a seek and request it
+    async fn open(&mut self, request: RangeRequest) {
+        self.epoch = request.epoch;
+        self.offset = request.offset;
+        self.response = None;
+        self.reached_end = false;
+        self.stats.begin_range(request.offset, self.total_length);
+
+        if Some(request.offset) == self.total_length {
+            // Seek to the very end; there is nothing left to download
+            return;
+        }
+
+        let opened = open_range(
+            &self.client,
+            &mut self.url,
+            self.token.as_deref(),
+            request.offset,
+        )
+        .await
+        .and_then(|response| {
+            check_resumed_range(&response, request.offset, self.total_length)?;
+            Ok(response)
+        });
+
+        match opened {
+            Ok(response) => self.response = Some(response),
+            Err(err) => self.ring.finish_range(self.epoch, Some(err)),
+        }
+    }
+}
+
+/// Request the stream from `offset`, renewing an expired or rejected token on the way
+async fn open_range(
+    client: &Client,
+    url: &mut String,
+    token: Option<&StreamToken>,
+    offset: u64,
+) -> std::io::Result<Response> {
+    if let Some(token) = token.filter(|token| token.needs_refresh()) {
+        *url = renew_token(token).await?;
+    }
+
+    let mut response = send_range_request(client, url, offset).await?;
+
+    if let Some(token) = token.filter(|_| is_token_rejected(response.status())) {
+        *url = renew_token(token).await?;
+        response = send_range_request(client, url, offset).await?;
+    }
+
+    if !(response.status() == StatusCode::PARTIAL_CONTENT || response.status().is_success()) {
+        return Err(IoError::other(HttpStatusError::new(
+            "HTTP stream",
+            response.status(),
+        )));
+    }
+
+    Ok(response)
+}
+
+async fn send_range_request(client: &Client, url: &str, offset: u64) -> std::io::Result<Response> {
+    let request = build_stream_request(client, url, offset).send();
+    match tokio::time::timeout(REQUEST_TIMEOUT, request).await {
+        Ok(response) => response.map_err(request_error),
+        Err(_) => Err(IoError::new(
+            ErrorKind::TimedOut,
+            format!("No response within {}s", REQUEST_TIMEOUT.as_secs()),
+        )),
+    }
+}
+
+/// Next chunk of a response body, giving up on a connection that stalls for `CHUNK_TIMEOUT`
+async fn next_chunk(response: &mut Response) -> std::io::Result<Option<Bytes>> {
+    match tokio::time::timeout(CHUNK_TIMEOUT, response.chunk()).await {
+        Ok(chunk) => chunk.map_err(request_error),
+        Err(_) => Err(IoError::new(
+            ErrorKind::TimedOut,
+            format!("No data within {}s", CHUNK_TIMEOUT.as_secs()),
+        )),
+    }
+}
+
+/// Keep the `reqwest` error as the cause, so callers can tell what went wrong
+fn request_error(e: reqwest::Error) -> IoError {
+    let kind = if e.is_timeout() {
+        ErrorKind::TimedOut
+    } else {
+        ErrorKind::Other
+    };
+    IoError::new(kind, e)
+}
+
+async fn renew_token(token: &StreamToken) -> std::io::Result<String> {
+    token.refresh().await.map_err(IoError::other)
+}
+
+/// The backend answers an expired or unknown token with 404, and one it refuses with 401
+fn is_token_rejected(status: StatusCode) -> bool {
+    status == StatusCode::NOT_FOUND || status == StatusCode::UNAUTHORIZED
+}
+
+fn build_stream_request(client: &Client, url: &str, offset: u64) -> reqwest::RequestBuilder {
+    client.get(url).header(RANGE, format!("bytes={}-", offset))
+}
+
+/// Re-request the stream from `offset` after it broke off mid-transfer. Network errors and
+/// server-side failures are retried with exponential backoff within `RECONNECT_BUDGET`, as is
+/// a rejected token once it has been renewed; anything else, or a response that does not
+/// continue exactly at `offset`, ends the stream.
+async fn resume_stream(
+    client: &Client,
+    url: &mut String,
+    token: Option<&StreamToken>,
+    offset: u64,
+    total_length: Option<u64>,
+    cause: String,
+) -> std::io::Result<Response> {
+    let mut last_error = cause;
+    let mut token_rejected = false;
+    let deadline = tokio::time::Instant::now() + RECONNECT_BUDGET;
+
+    for attempt in 0..MAX_RECONNECT_ATTEMPTS {
+        let delay = (RECONNECT_BASE_DELAY_MS << attempt).min(RECONNECT_MAX_DELAY_MS);
+        let delay_until = tokio::time::Instant::now() + Duration::from_millis(delay);
+        if delay_until >= deadline {
+            break;
+        }
+        println!(
+            "Audio: Stream interrupted at byte {} ({}), reconnecting in {}ms ({}/{})",
+            offset,
+            last_error,
+            delay,
+            attempt + 1,
+            MAX_RECONNECT_ATTEMPTS
+        );
+        tokio::time::sleep_until(delay_until).await;
+
+        if let Some(token) = token.filter(|token| token_rejected || token.needs_refresh()) {
+            match tokio::time::timeout_at(deadline, token.refresh()).await {
+                Ok(Ok(new_url)) => {
+                    *url = new_url;
+                    token_rejected = false;
+                }
+                Ok(Err(e)) => {
+                    last_error = e.to_string();
+                    continue;
+                }
+                Err(_) => {
+                    last_error = "reconnect budget used up".to_string();
+                    break;
+                }
+            }
+        }
+
+        // Only waiting for the response counts against the budget, not its body
+        let request = build_stream_request(client, url, offset).send();
+        let response = match tokio::time::timeout_at(deadline, request).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => {
+                last_error = format!("HTTP stream error: {}", e);
+                continue;
+            }
+            Err(_) => {
+                last_error = "reconnect budget used up".to_string();
+                break;
+            }
+        };
+
+        let status = response.status();
+        if status.is_server_error()
+            || status == StatusCode::REQUEST_TIMEOUT
+            || status == StatusCode::TOO_MANY_REQUESTS
+        {
+            last_error = format!("HTTP stream responded with status {}", status);
+            continue;
+        }
+
+        if token.is_some() && is_token_rejected(status) {
+            last_error = format!("Stream token was rejected with status {}", status);
+            token_rejected = true;
+            continue;
+        }
+
+        check_resumed_range(&response, offset, total_length)?;
+        return Ok(response);
+    }
+
+    Err(IoError::other(format!(
+        "Stream could not be resumed within {}s: {}",
+        RECONNECT_BUDGET.as_secs(),
+        last_error
+    )))
+}
+
+/// Download `range` of the stream straight into `fill`, reconnecting like playback does
+/// when the transfer breaks off
+async fn fill_range(
+    client: &Client,
+    url: &mut String,
+    token: Option<&StreamToken>,
+    range: Range<u64>,
+    total_length: u64,
+    fill: &mut CacheFill,
+) -> Result<()> {
+    let mut offset = range.start;
+    let mut response = open_range(client, url, token, offset)
+        .await
+        .and_then(|response| {
+            check_resumed_range(&response, offset, Some(total_length))?;
+            Ok(response)
+        })
+        .map_err(|e| anyhow!("Failed to request bytes {}-: {}", offset, e))?;
+
+    while offset < range.end {
+        let chunk = match next_chunk(&mut response).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => {
+                return Err(anyhow!(
+                    "Stream ended at byte {} instead of {}",
+                    offset,
+                    range.end
+                ))
+            }
+            Err(err) => {
+                let cause = format!("HTTP chunk error: {}", err);
+                response = resume_stream(client, url, token, offset, Some(total_length), cause)
+                    .await
+                    .map_err(|e| anyhow!("Failed to download bytes {}-: {}", offset, e))?;
+                continue;
+            }
+        };
+
+        let wanted = chunk.len().min((range.end - offset) as usize);
+        fill.write_at(offset, &chunk[..wanted]).await?;
+        offset += wanted as u64;
+    }
+    Ok(())
+}
+
+/// A resumed response must be the partial content starting at `offset` of the same file
+fn check_resumed_range(
+    response: &Response,
+    offset: u64,
+    total_length: Option<u64>,
+) -> std::io::Result<()> {
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(IoError::other(format!(
+            "Resumed stream responded with status {} instead of partial content",
+            response.status()
+        )));
+    }
+
+    let header = response.headers().get(CONTENT_RANGE);
+    let start = parse_content_range_start(header);
+    let total = parse_content_range(header);
+    let total_matches = match (total_length, total) {
+        (Some(expected), Some(total)) => expected == total,
+        _ => true,
+    };
+
+    if start != Some(offset) || !total_matches {
+        return Err(IoError::other(format!(
+            "Resumed stream returned range {:?}, expected bytes {}-",
+            header, offset
+        )));
+    }
+
+    Ok(())
+}
+
+fn total_length_from_response(response: &Response) -> Option<u64> {
+    parse_content_range(response.headers().get(CONTENT_RANGE)).or(response.content_length())
+}
+
+fn parse_content_range(header: Option<&HeaderValue>) -> Option<u64> {
+    let header_str = header?.to_str().ok()?;
+    // Expected format: bytes start-end/total
+    let parts: Vec<&str> = header_str.split('/').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+
+    parts[1].trim().parse::<u64>().ok()
+}
+
+fn parse_content_range_start(header: Option<&HeaderValue>) -> Option<u64> {
+    let header_str = header?.to_str().ok()?;
+    // Expected format: bytes start-end/total
+    let range = header_str.trim().strip_prefix("bytes")?.trim_start();
+    let (start, _) = range.split_once('-')?;
+    start.trim().parse::<u64>().ok()
+}
@@ -0,0 +1,533 @@
+use crate::decoder::StreamDecoder;
+use anyhow::{anyhow, Result};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use symphonia::core::io::MediaSource;
+
+// YouTube's own playback reference level
+const TARGET_LUFS: f64 = -14.0;
+const MAX_BOOST_DB: f64 = 6.0;
+const MAX_CUT_DB: f64 = -12.0;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+// Gating blocks are 400ms long and start every 100ms (75% overlap)
+const SUB_BLOCK_SECS: f64 = 0.1;
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+// How often (in frames) a playing source looks for a measurement of its track
+const MEASUREMENT_CHECK_INTERVAL: usize = 4096;
+// Gain changes during playback are spread over this long so there is no audible step
+const GAIN_RAMP_SECS: f32 = 1.0;
+
+/// Measured loudness of one track
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TrackLoudness {
+    pub integrated_lufs: f64,
+    pub sample_peak: f64,
+}
+
+impl TrackLoudness {
+    /// Gain that brings the track to the target level without pushing peaks over full scale
+    pub fn gain_db(&self) -> f64 {
+        let gain = (TARGET_LUFS - self.integrated_lufs).clamp(MAX_CUT_DB, MAX_BOOST_DB);
+        if self.sample_peak > 0.0 {
+            gain.min(-20.0 * self.sample_peak.log10())
+        } else {
+            gain
+        }
+    }
+
+    fn linear_gain(&self) -> f32 {
+        10f64.powf(self.gain_db() / 20.0) as f32
+    }
+}
+
+/// Per-track loudness measurements persisted by youtube id, plus the live on/off switch.
+///
+/// Playing sources poll `generation` and only take the lock once a new measurement came in,
+/// so a track first played before it was measured is normalized as soon as it is.
+pub struct LoudnessNormalizer {
+    path: PathBuf,
+    enabled: AtomicBool,
+    tracks: Mutex<HashMap<String, TrackLoudness>>,
+    generation: AtomicU64,
+}
+
+impl LoudnessNormalizer {
+    pub fn new(path: PathBuf, enabled: bool) -> Self {
+        let tracks = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            enabled: AtomicBool::new(enabled),
+            tracks: Mutex::new(tracks),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, youtube_id: &str) -> Option<TrackLoudness> {
+        self.tracks.lock().ok()?.get(youtube_id).copied()
+    }
+
+    fn insert(&self, youtube_id: &str, loudness: TrackLoudness) -> Result<()> {
+        let contents = {
+            let mut tracks = self
+                .tracks
+                .lock()
+                .map_err(|_| anyhow!("Loudness table is poisoned"))?;
+            tracks.insert(youtube_id.to_string(), loudness);
+            serde_json::to_string(&*tracks)
+                .map_err(|e| anyhow!("Failed to serialize loudness table: {}", e))?
+        };
+        self.generation.fetch_add(1, Ordering::Release);
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("Failed to create loudness directory: {}", e))?;
+        }
+        fs::write(&self.path, contents)
+            .map_err(|e| anyhow!("Failed to write loudness table: {}", e))
+    }
+
+    /// Decode the whole stream once and remember its loudness. Blocking; call it from a
+    /// blocking task. Does nothing if the track was already measured.
+    pub fn ensure_measured(&self, youtube_id: &str, source: Box<dyn MediaSource>) -> Result<()> {
+        if self.get(youtube_id).is_some() {
+            return Ok(());
+        }
+
+        let decoder = StreamDecoder::new(source, 0.0)?;
+        let loudness = measure(decoder)
+            .ok_or_else(|| anyhow!("Track {} is too short or silent to measure", youtube_id))?;
+
+        println!(
+            "Loudness: {} measured at {:.1} LUFS (peak {:.3}), gain {:+.1} dB",
+            youtube_id,
+            loudness.integrated_lufs,
+            loudness.sample_peak,
+            loudness.gain_db()
+        );
+
+        self.insert(youtube_id, loudness)
+    }
+
+    /// Wrap `source` with the stored gain for `youtube_id`. A track that has not been
+    /// measured yet plays at unity gain until its measurement is stored. The enable switch
+    /// is read live, so toggling normalization also ramps the playing track.
+    pub fn apply<S>(self: &Arc<Self>, youtube_id: &str, source: S) -> GainSource<S>
+    where
+        S: Source<Item = i16>,
+    {
+        let generation = self.generation.load(Ordering::Acquire);
+        let measured = self.get(youtube_id).map(|loudness| loudness.linear_gain());
+        let gain = match measured {
+            Some(gain) if self.enabled.load(Ordering::Relaxed) => gain,
+            _ => 1.0,
+        };
+
+        GainSource {
+            inner: source,
+            normalizer: self.clone(),
+            youtube_id: youtube_id.to_string(),
+            generation,
+            measured,
+            gain,
+            target: gain,
+            step: 0.0,
+            channel: 0,
+            since_check: 0,
+        }
+    }
+}
+
+/// ITU-R BS.1770 K-weighting pre-filter and RLB high-pass as a single cascade
+struct KWeighting {
+    stages: [[f64; 5]; 2],
+    state: Vec<[[f64; 2]; 2]>,
+}
+
+impl KWeighting {
+    // Coefficients derived for an arbitrary rate, as in libebur128
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        let rate = sample_rate as f64;
+
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        ];
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let highpass = [
+            1.0,
+            -2.0,
+            1.0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        ];
+
+        Self {
+            stages: [shelf, highpass],
+            state: vec![[[0.0; 2]; 2]; channels],
+        }
+    }
+
+    fn process(&mut self, channel: usize, input: f64) -> f64 {
+        let mut value = input;
+        for (stage, z) in self.stages.iter().zip(self.state[channel].iter_mut()) {
+            let [b0, b1, b2, a1, a2] = *stage;
+            let output = b0 * value + z[0];
+            z[0] = b1 * value - a1 * output + z[1];
+            z[1] = b2 * value - a2 * output;
+            value = output;
+        }
+        value
+    }
+}
+
+/// Integrated loudness with absolute and relative gating (EBU R128)
+fn measure<S>(mut source: S) -> Option<TrackLoudness>
+where
+    S: Source<Item = i16>,
+{
+    let channels = source.channels().max(1) as usize;
+    let sample_rate = source.sample_rate();
+    let sub_block_frames = (sample_rate as f64 * SUB_BLOCK_SECS) as usize;
+    if sub_block_frames == 0 {
+        return None;
+    }
+
+    let mut filter = KWeighting::new(sample_rate, channels);
+    let mut sub_blocks: Vec<f64> = Vec::new();
+    let mut energy = 0.0;
+    let mut frames = 0;
+    let mut channel = 0;
+    let mut peak: f64 = 0.0;
+
+    for sample in source.by_ref() {
+        let value = sample as f64 / i16::MAX as f64;
+        peak = peak.max(value.abs());
+
+        // Only the front channels carry weight 1.0; anything beyond stereo is ignored
+        let filtered = filter.process(channel, value);
+        if channel < 2 {
+            energy += filtered * filtered;
+        }
+
+        channel += 1;
+        if channel == channels {
+            channel = 0;
+            frames += 1;
+            if frames == sub_block_frames {
+                sub_blocks.push(energy);
+                energy = 0.0;
+                frames = 0;
+            }
+        }
+    }
+
+    let block_frames = (sub_block_frames * SUB_BLOCKS_PER_BLOCK) as f64;
+    let blocks: Vec<f64> = sub_blocks
+        .windows(SUB_BLOCKS_PER_BLOCK)
+        .map(|window| window.iter().sum::<f64>() / block_frames)
+        .collect();
+
+    let above_absolute: Vec<f64> = blocks
+        .into_iter()
+        .filter(|&z| block_loudness(z) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if above_absolute.is_empty() {
+        return None;
+    }
+
+    let relative_gate = block_loudness(mean(&above_absolute)) + RELATIVE_GATE_LU;
+    let gated: Vec<f64> = above_absolute
+        .into_iter()
+        .filter(|&z| block_loudness(z) > relative_gate)
+        .collect();
+    if gated.is_empty() {
+        return None;
+    }
+
+    Some(TrackLoudness {
+        integrated_lufs: block_loudness(mean(&gated)),
+        sample_peak: peak,
+    })
+}
+
+fn block_loudness(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.max(f64::MIN_POSITIVE).log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Applies the per-track gain while normalization is enabled, ramping whenever the gain
+/// changes mid-track
+pub struct GainSource<S> {
+    inner: S,
+    normalizer: Arc<LoudnessNormalizer>,
+    youtube_id: String,
+    // Generation of the table the measurement was last looked up in
+    generation: u64,
+    // `None` until the track has been measured
+    measured: Option<f32>,
+    gain: f32,
+    // Gain being ramped towards, by `step` per frame
+    target: f32,
+    step: f32,
+    channel: usize,
+    since_check: usize,
+}
+
+impl<S> GainSource<S>
+where
+    S: Source<Item = i16>,
+{
+    /// Pick up a measurement stored since the last look, without blocking the audio thread
+    fn check_measurement(&mut self) {
+        let generation = self.normalizer.generation.load(Ordering::Acquire);
+        if generation == self.generation {
+            return;
+        }
+
+        let Ok(tracks) = self.normalizer.tracks.try_lock() else {
+            // Being written right now; look again on the next check
+            return;
+        };
+        self.measured = tracks
+            .get(&self.youtube_id)
+            .map(|loudness| loudness.linear_gain());
+        self.generation = generation;
+    }
+
+    fn update_gain(&mut self) {
+        if self.measured.is_none() {
+            self.since_check += 1;
+            if self.since_check >= MEASUREMENT_CHECK_INTERVAL {
+                self.since_check = 0;
+                self.check_measurement();
+            }
+        }
+
+        let target = match self.measured {
+            Some(gain) if self.normalizer.enabled.load(Ordering::Relaxed) => gain,
+            _ => 1.0,
+        };
+        if target != self.target {
+            let ramp_frames = (GAIN_RAMP_SECS * self.inner.sample_rate() as f32).max(1.0);
+            self.target = target;
+            self.step = (target - self.gain) / ramp_frames;
+        }
+
+        if self.gain != self.target {
+            self.gain += self.step;
+            let reached = if self.step > 0.0 {
+                self.gain >= self.target
+            } else {
+                self.gain <= self.target
+            };
+            if reached {
+                self.gain = self.target;
+            }
+        }
+    }
+}
+
+impl<S> Iterator for GainSource<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        // Only change the gain on a frame boundary so channels stay level
+        if self.channel == 0 {
+            self.update_gain();
+        }
+
+        let sample = self.inner.next()?;
+        self.channel = (self.channel + 1) % self.inner.channels().max(1) as usize;
+        if self.gain == 1.0 {
+            return Some(sample);
+        }
+
+        let value = sample as f32 * self.gain;
+        Some(value.clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+    }
+}
+
+impl<S> Source for GainSource<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+    use std::sync::atomic::AtomicUsize;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Stereo 1 kHz sine with `amplitude` in both channels, which EBU Tech 3341 rates at
+    /// 20 * log10(amplitude) LUFS
+    fn sine(amplitude: f64, secs: f64) -> Vec<i16> {
+        (0..(SAMPLE_RATE as f64 * secs) as usize)
+            .flat_map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let sample = ((2.0 * PI * 1000.0 * t).sin() * amplitude * 32767.0) as i16;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn dbfs(db: f64) -> f64 {
+        10f64.powf(db / 20.0)
+    }
+
+    fn loudness(samples: Vec<i16>) -> Option<TrackLoudness> {
+        measure(SamplesBuffer::new(2, SAMPLE_RATE, samples))
+    }
+
+    // Normalizer backed by a table under the system temp dir
+    fn normalizer(enabled: bool) -> (Arc<LoudnessNormalizer>, PathBuf) {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "miu-loudness-test-{}-{}.json",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_file(&path);
+        (
+            Arc::new(LoudnessNormalizer::new(path.clone(), enabled)),
+            path,
+        )
+    }
+
+    #[test]
+    fn sine_at_minus_23_dbfs_measures_minus_23_lufs() {
+        let measured = loudness(sine(dbfs(-23.0), 10.0)).unwrap();
+        assert!((measured.integrated_lufs + 23.0).abs() < 0.1);
+        assert!((measured.sample_peak - dbfs(-23.0)).abs() < 1e-3);
+
+        // 9 dB below the target, but the boost stops at +6 dB
+        assert_eq!(measured.gain_db(), MAX_BOOST_DB);
+
+        let measured = loudness(sine(dbfs(-17.0), 10.0)).unwrap();
+        assert!((measured.gain_db() - 3.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn gain_is_clamped_and_keeps_peaks_below_full_scale() {
+        let gain = |integrated_lufs, sample_peak| {
+            TrackLoudness {
+                integrated_lufs,
+                sample_peak,
+            }
+            .gain_db()
+        };
+
+        assert_eq!(gain(-40.0, 0.1), MAX_BOOST_DB);
+        assert_eq!(gain(0.0, 1.0), MAX_CUT_DB);
+        assert_eq!(gain(-14.0, 0.5), 0.0);
+        // A 4 dB boost would push a peak at -2 dBFS over full scale
+        assert!((gain(-18.0, dbfs(-2.0)) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quiet_passages_are_gated_out() {
+        // Silence falls below the absolute gate and does not pull the level down; only the
+        // few blocks straddling the edge count at a lower level
+        let mut samples = sine(dbfs(-20.0), 5.0);
+        samples.extend(vec![0; samples.len()]);
+        let measured = loudness(samples).unwrap();
+        assert!((measured.integrated_lufs + 20.0).abs() < 0.25);
+
+        // -45 LUFS is above the absolute gate but more than 10 LU below the rest
+        let mut samples = sine(dbfs(-20.0), 5.0);
+        samples.extend(sine(dbfs(-45.0), 5.0));
+        let measured = loudness(samples).unwrap();
+        assert!((measured.integrated_lufs + 20.0).abs() < 0.25);
+
+        assert!(loudness(vec![0; SAMPLE_RATE as usize * 2]).is_none());
+        assert!(loudness(sine(dbfs(-20.0), 0.2)).is_none());
+    }
+
+    #[test]
+    fn playing_source_ramps_to_a_measurement_stored_meanwhile() {
+        let (normalizer, path) = normalizer(true);
+        let samples = vec![10000i16; SAMPLE_RATE as usize * 4];
+        let mut source = normalizer.apply("a", SamplesBuffer::new(1, SAMPLE_RATE, samples));
+
+        assert!(source.by_ref().take(1000).all(|sample| sample == 10000));
+
+        let cut = TrackLoudness {
+            integrated_lufs: -8.0,
+            sample_peak: 0.5,
+        };
+        normalizer.insert("a", cut).unwrap();
+
+        let played: Vec<i16> = source.by_ref().take(SAMPLE_RATE as usize * 2).collect();
+        let expected = (10000.0 * dbfs(-6.0)) as i16;
+        assert!(played.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(played.windows(2).all(|pair| pair[0] - pair[1] <= 1));
+        assert!((played.last().unwrap() - expected).abs() <= 1);
+
+        // Switching normalization off ramps back to unity
+        normalizer.set_enabled(false);
+        assert_eq!(source.last(), Some(10000));
+
+        // A track measured up front starts at its gain right away
+        normalizer.set_enabled(true);
+        let samples = vec![10000i16; 16];
+        let mut source = normalizer.apply("a", SamplesBuffer::new(1, SAMPLE_RATE, samples));
+        assert!((source.next().unwrap() - expected).abs() <= 1);
+
+        let _ = fs::remove_file(path);
+    }
+}
@@ -2,16 +2,43 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod audio;
+mod byte_ring;
+mod cache;
+mod clock;
 mod config;
+mod crossfeed;
+mod decoder;
+mod equalizer;
+mod fade;
+mod hls;
+mod http_stream;
 mod hyprland;
+mod loudness;
+mod media_format;
+mod mixer;
 mod mpris;
+mod opus;
+mod playback_error;
+mod read_ahead;
 mod server;
+mod sleep_timer;
+mod spectrum;
 mod state;
+mod stream_stats;
+mod stream_token;
 mod theme;
 
-use audio::AudioManager;
+use audio::{AudioHandle, BufferMetrics, OutputDeviceInfo};
+use cache::{AudioCache, CacheUsage};
 use config::AppConfig;
+use crossfeed::CrossfeedLevel;
+use equalizer::{EqBand, Equalizer, EqualizerConfig};
+use fade::{FadeConfig, FadeSettings};
+use loudness::LoudnessNormalizer;
+use mixer::MixerConfig;
 use server::ServerClient;
+use sleep_timer::{SleepTimer, SleepTimerAction, SleepTimerStatus, SLEEP_TIMER_INTERVAL};
+use spectrum::{SpectrumAnalysis, SpectrumAnalyzer, SPECTRUM_INTERVAL};
 #[cfg(target_os = "linux")]
 use mpris::MprisManager;
 use state::{AppState, PlaybackStatus, PlayerSnapshot, Track};
@@ -28,6 +55,11 @@ use tokio::sync::Mutex;
 use notify::{Watcher, RecommendedWatcher, RecursiveMode, Event, EventKind};
 use std::sync::mpsc;
 use std::path::Path;
+use std::time::Duration;
+
+const OUTPUT_WATCHDOG_INTERVAL: Duration = Duration::from_secs(2);
+const BUFFER_METRICS_INTERVAL: Duration = Duration::from_secs(1);
+const SLEEP_FADE_RESET_MARGIN: Duration = Duration::from_millis(100);
 
 // Hold the tray icon handle so Linux tray implementations keep it alive.
 struct TrayHandle {
@@ -38,12 +70,13 @@ struct TrayHandle {
 async fn play_pause(
     app_handle: AppHandle,
     state: State<'_, Arc<Mutex<AppState>>>,
-    audio: State<'_, Arc<Mutex<AudioManager>>>,
+    audio: State<'_, AudioHandle>,
     server: State<'_, Arc<ServerClient>>,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
 ) -> Result<(), String> {
-    let audio_arc = audio.inner().clone();
+    let audio_handle = audio.inner().clone();
     let server_arc = server.inner().clone();
-    let pause_handle = app_handle.clone();
+    let soft_pause = config.lock().await.soft_pause_keep();
 
     let mut guard = state.lock().await;
 
@@ -56,74 +89,83 @@ async fn play_pause(
     );
 
     if should_pause {
-        println!("Taking PAUSE path - stopping playback");
-        let position = guard.computed_position();
-        guard.set_user_paused(true);
-        guard.update_player_status(PlaybackStatus::Paused);
-        let duration = guard.duration();
-        guard.update_sync(position, Some(duration));
-        let snapshot = guard.snapshot();
-        drop(guard);
-
-        // Emit state update IMMEDIATELY for instant UI feedback
-        println!(
-            "Emitting player_state_updated event with isPlaying={}",
-            snapshot.is_playing
-        );
-        pause_handle
-            .emit("player_state_updated", snapshot.clone())
-            .map_err(|e| e.to_string())?;
-        println!("Event emitted successfully");
-
-        // Update MPRIS status
-        #[cfg(target_os = "linux")]
-        if let Some(mpris) = pause_handle.try_state::<MprisManager>() {
-            if let Err(e) = mpris.update_playback_status(PlaybackStatus::Paused).await {
-                println!("Failed to update MPRIS playback status: {}", e);
-            }
-        }
-
-        // Audio stop can happen after UI update (pause = stop for streaming)
-        audio_arc
-            .lock()
-            .await
-            .stop()
-            .await
-            .map_err(|e| e.to_string())?;
+        pause_playback(&app_handle, guard, &audio_handle, &server_arc, soft_pause).await?;
     } else {
         println!("Taking RESUME path - calling server");
         guard.set_user_paused(false);
         drop(guard);
 
         server_arc
-            .resume_playback(state.inner().clone(), audio_arc, app_handle)
+            .resume_playback(state.inner().clone(), audio_handle, app_handle)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| format!("{:#}", e))?;
     }
 
     Ok(())
 }
 
+/// Enter the user-paused state; shared by `play_pause` and the sleep timer
+async fn pause_playback(
+    app_handle: &AppHandle,
+    mut guard: tokio::sync::MutexGuard<'_, AppState>,
+    audio: &AudioHandle,
+    server: &ServerClient,
+    soft_pause: Option<Duration>,
+) -> Result<(), String> {
+    println!("Taking PAUSE path - stopping playback");
+    let position = guard.computed_position();
+    guard.set_user_paused(true);
+    guard.update_player_status(PlaybackStatus::Paused);
+    // Starts still in flight must not override the pause once they finish
+    server.cancel_pending_playback();
+    let duration = guard.duration();
+    guard.update_sync(position, Some(duration));
+    let snapshot = guard.snapshot();
+    drop(guard);
+
+    // Emit state update IMMEDIATELY for instant UI feedback
+    println!(
+        "Emitting player_state_updated event with isPlaying={}",
+        snapshot.is_playing
+    );
+    app_handle
+        .emit("player_state_updated", snapshot.clone())
+        .map_err(|e| e.to_string())?;
+    println!("Event emitted successfully");
+
+    // Update MPRIS status
+    #[cfg(target_os = "linux")]
+    if let Some(mpris) = app_handle.try_state::<MprisManager>() {
+        if let Err(e) = mpris.update_playback_status(PlaybackStatus::Paused).await {
+            println!("Failed to update MPRIS playback status: {}", e);
+        }
+    }
+
+    // Audio stop can happen after UI update (pause = stop for streaming, unless the
+    // stream is kept open for a quick resume)
+    match soft_pause {
+        Some(keep) => audio.soft_pause(keep),
+        None => audio.stop(),
+    }
+    .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn set_volume(
     app_handle: AppHandle,
     state: State<'_, Arc<Mutex<AppState>>>,
-    audio: State<'_, Arc<Mutex<AudioManager>>>,
+    audio: State<'_, AudioHandle>,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
     volume: f32,
 ) -> Result<(), String> {
-    let audio_arc = audio.inner().clone();
-
     let snapshot = {
         let mut guard = state.lock().await;
         guard.update_volume(volume);
         guard.snapshot()
     };
 
-    audio_arc
-        .lock()
-        .await
-        .set_volume(volume)
-        .map_err(|e| e.to_string())?;
+    // Applied by the audio task right away, even while it is opening a stream
+    audio.set_volume(volume).map_err(|e| e.to_string())?;
 
     app_handle
         .emit("player_state_updated", snapshot.clone())
@@ -137,13 +179,10 @@ async fn set_volume(
         }
     }
 
-    // Save the new volume to config for persistence
-    // Note: We should update the config through the state management system
-    // For now, create a minimal config just for volume saving
-    // TODO: Properly integrate with config state management
-    let mut temp_config = AppConfig::default();
-    temp_config.volume = volume;
-    if let Err(e) = temp_config.save() {
+    // Save the new volume through the shared config so other settings are preserved
+    let mut config_guard = config.lock().await;
+    config_guard.volume = volume.clamp(0.0, 1.0);
+    if let Err(e) = config_guard.save() {
         println!("Failed to save volume to config: {}", e);
         // Don't fail the command just because config save failed
     }
@@ -311,6 +350,524 @@ async fn get_theme_overrides(
     Ok(theme::load_theme_overrides(&config_clone))
 }
 
+#[tauri::command]
+async fn get_cache_usage(cache: State<'_, Arc<AudioCache>>) -> Result<CacheUsage, String> {
+    Ok(cache.usage())
+}
+
+#[tauri::command]
+async fn clear_cache(cache: State<'_, Arc<AudioCache>>) -> Result<CacheUsage, String> {
+    cache.clear().map_err(|e| e.to_string())?;
+    Ok(cache.usage())
+}
+
+#[tauri::command]
+async fn get_buffer_metrics(audio: State<'_, AudioHandle>) -> Result<BufferMetrics, String> {
+    Ok(audio.buffer_metrics())
+}
+
+#[tauri::command]
+async fn list_output_devices() -> Result<Vec<OutputDeviceInfo>, String> {
+    tokio::task::spawn_blocking(audio::list_output_devices)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_output_device(audio: State<'_, AudioHandle>) -> Result<Option<String>, String> {
+    Ok(audio.output_device_name())
+}
+
+#[tauri::command]
+async fn set_output_device(
+    device: Option<String>,
+    state: State<'_, Arc<Mutex<AppState>>>,
+    audio: State<'_, AudioHandle>,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+) -> Result<(), String> {
+    let device = device.filter(|name| !name.trim().is_empty());
+
+    // Resume on the new device from the locally synced position
+    let resume_position = {
+        let guard = state.lock().await;
+        Some(guard.computed_position())
+    };
+
+    audio
+        .set_output_device(device.as_deref(), resume_position)
+        .await
+        .map_err(|e| format!("{:#}", e))?;
+
+    let mut config_guard = config.lock().await;
+    config_guard.output_device = device;
+    if let Err(e) = config_guard.save() {
+        println!("Failed to save output device to config: {}", e);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EqualizerState {
+    settings: EqualizerConfig,
+    presets: Vec<String>,
+}
+
+/// Apply `change` to the persisted EQ settings and push the result to the live filters
+async fn update_equalizer<F>(
+    config: &Mutex<AppConfig>,
+    equalizer: &Equalizer,
+    change: F,
+) -> Result<EqualizerState, String>
+where
+    F: FnOnce(&mut EqualizerConfig) -> anyhow::Result<()>,
+{
+    let mut config_guard = config.lock().await;
+    change(&mut config_guard.equalizer).map_err(|e| e.to_string())?;
+    equalizer.update(&config_guard.equalizer);
+
+    if let Err(e) = config_guard.save() {
+        println!("Failed to save equalizer to config: {}", e);
+    }
+
+    Ok(EqualizerState {
+        settings: config_guard.equalizer.clone(),
+        presets: config_guard.equalizer.preset_names(),
+    })
+}
+
+#[tauri::command]
+async fn get_equalizer(config: State<'_, Arc<Mutex<AppConfig>>>) -> Result<EqualizerState, String> {
+    let config_guard = config.lock().await;
+    Ok(EqualizerState {
+        settings: config_guard.equalizer.clone(),
+        presets: config_guard.equalizer.preset_names(),
+    })
+}
+
+#[tauri::command]
+async fn set_equalizer_enabled(
+    enabled: bool,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    equalizer: State<'_, Arc<Equalizer>>,
+) -> Result<EqualizerState, String> {
+    update_equalizer(&config, &equalizer, |eq| {
+        eq.enabled = enabled;
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+async fn set_equalizer_band(
+    index: usize,
+    gain_db: f32,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    equalizer: State<'_, Arc<Equalizer>>,
+) -> Result<EqualizerState, String> {
+    update_equalizer(&config, &equalizer, |eq| eq.set_band_gain(index, gain_db)).await
+}
+
+#[tauri::command]
+async fn set_equalizer_bands(
+    bands: Vec<EqBand>,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    equalizer: State<'_, Arc<Equalizer>>,
+) -> Result<EqualizerState, String> {
+    update_equalizer(&config, &equalizer, |eq| {
+        eq.set_bands(bands);
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+async fn apply_equalizer_preset(
+    name: String,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    equalizer: State<'_, Arc<Equalizer>>,
+) -> Result<EqualizerState, String> {
+    update_equalizer(&config, &equalizer, |eq| eq.apply_preset(&name)).await
+}
+
+#[tauri::command]
+async fn save_equalizer_preset(
+    name: String,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    equalizer: State<'_, Arc<Equalizer>>,
+) -> Result<EqualizerState, String> {
+    update_equalizer(&config, &equalizer, |eq| eq.save_preset(&name)).await
+}
+
+#[tauri::command]
+async fn delete_equalizer_preset(
+    name: String,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    equalizer: State<'_, Arc<Equalizer>>,
+) -> Result<EqualizerState, String> {
+    update_equalizer(&config, &equalizer, |eq| eq.delete_preset(&name)).await
+}
+
+#[tauri::command]
+async fn get_loudness_normalization(
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+) -> Result<bool, String> {
+    Ok(config.lock().await.normalize_loudness)
+}
+
+#[tauri::command]
+async fn set_loudness_normalization(
+    enabled: bool,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    loudness: State<'_, Arc<LoudnessNormalizer>>,
+) -> Result<(), String> {
+    loudness.set_enabled(enabled);
+
+    let mut config_guard = config.lock().await;
+    config_guard.normalize_loudness = enabled;
+    if let Err(e) = config_guard.save() {
+        println!("Failed to save loudness normalization to config: {}", e);
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_crossfeed(
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+) -> Result<CrossfeedLevel, String> {
+    Ok(config.lock().await.crossfeed)
+}
+
+#[tauri::command]
+async fn set_crossfeed(
+    level: CrossfeedLevel,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    audio: State<'_, AudioHandle>,
+) -> Result<(), String> {
+    audio.crossfeed().set_level(level);
+
+    let mut config_guard = config.lock().await;
+    config_guard.crossfeed = level;
+    if let Err(e) = config_guard.save() {
+        println!("Failed to save crossfeed to config: {}", e);
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_soft_pause(config: State<'_, Arc<Mutex<AppConfig>>>) -> Result<bool, String> {
+    Ok(config.lock().await.soft_pause)
+}
+
+#[tauri::command]
+async fn set_soft_pause(
+    enabled: bool,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+) -> Result<(), String> {
+    let mut config_guard = config.lock().await;
+    config_guard.soft_pause = enabled;
+    if let Err(e) = config_guard.save() {
+        println!("Failed to save soft pause to config: {}", e);
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_spectrum_visualizer(config: State<'_, Arc<Mutex<AppConfig>>>) -> Result<bool, String> {
+    Ok(config.lock().await.spectrum_visualizer)
+}
+
+#[tauri::command]
+async fn set_spectrum_visualizer(
+    enabled: bool,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    audio: State<'_, AudioHandle>,
+) -> Result<(), String> {
+    audio.spectrum().set_enabled(enabled);
+
+    let mut config_guard = config.lock().await;
+    config_guard.spectrum_visualizer = enabled;
+    if let Err(e) = config_guard.save() {
+        println!("Failed to save spectrum visualizer to config: {}", e);
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_mixer(config: State<'_, Arc<Mutex<AppConfig>>>) -> Result<MixerConfig, String> {
+    Ok(config.lock().await.mixer)
+}
+
+#[tauri::command]
+async fn set_mixer(
+    settings: MixerConfig,
+    state: State<'_, Arc<Mutex<AppState>>>,
+    audio: State<'_, AudioHandle>,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+) -> Result<MixerConfig, String> {
+    settings.validate().map_err(|e| e.to_string())?;
+    audio.mixer().update(&settings);
+
+    // Re-apply the volume so a new curve takes effect right away
+    let volume = state.lock().await.volume;
+    audio.set_volume(volume).map_err(|e| e.to_string())?;
+
+    let mut config_guard = config.lock().await;
+    config_guard.mixer = settings;
+    if let Err(e) = config_guard.save() {
+        println!("Failed to save mixer to config: {}", e);
+    }
+
+    Ok(settings)
+}
+
+#[tauri::command]
+async fn get_fades(config: State<'_, Arc<Mutex<AppConfig>>>) -> Result<FadeConfig, String> {
+    Ok(config.lock().await.fades)
+}
+
+#[tauri::command]
+async fn set_fades(
+    settings: FadeConfig,
+    config: State<'_, Arc<Mutex<AppConfig>>>,
+    fades: State<'_, Arc<FadeSettings>>,
+) -> Result<FadeConfig, String> {
+    settings.validate().map_err(|e| e.to_string())?;
+    fades.update(&settings);
+
+    let mut config_guard = config.lock().await;
+    config_guard.fades = settings;
+    if let Err(e) = config_guard.save() {
+        println!("Failed to save fades to config: {}", e);
+    }
+
+    Ok(settings)
+}
+
+/// Publish the sleep timer change to the UI and hand back its countdown
+fn publish_sleep_timer(
+    app_handle: &AppHandle,
+    state: &AppState,
+) -> Result<Option<SleepTimerStatus>, String> {
+    let snapshot = state.snapshot();
+    app_handle
+        .emit("player_state_updated", snapshot.clone())
+        .map_err(|e| e.to_string())?;
+    Ok(snapshot.sleep_timer)
+}
+
+/// Start a sleep timer that pauses after `minutes`, or at the end of the current track
+/// (`tracks` = 1) or the Nth track from now. Replaces any running timer.
+#[tauri::command]
+async fn set_sleep_timer(
+    app_handle: AppHandle,
+    minutes: Option<u32>,
+    tracks: Option<u32>,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<Option<SleepTimerStatus>, String> {
+    let mut guard = state.lock().await;
+    let (timer, length) = match (minutes, tracks) {
+        (Some(minutes), None) => (
+            SleepTimer::after_minutes(minutes),
+            format!("{} minute(s)", minutes),
+        ),
+        (None, Some(tracks)) => (
+            SleepTimer::after_tracks(tracks, guard.current_track.as_ref()),
+            format!("{} track(s)", tracks),
+        ),
+        _ => return Err("Set the sleep timer with either minutes or tracks".to_string()),
+    };
+    let timer = timer.map_err(|e| e.to_string())?;
+
+    println!("Sleep timer: Set for {}", length);
+    guard.sleep_timer = Some(timer);
+    publish_sleep_timer(&app_handle, &guard)
+}
+
+/// Add `amount` minutes, or `amount` tracks for end-of-track timers
+#[tauri::command]
+async fn extend_sleep_timer(
+    app_handle: AppHandle,
+    amount: u32,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<Option<SleepTimerStatus>, String> {
+    let mut guard = state.lock().await;
+    let timer = guard
+        .sleep_timer
+        .as_mut()
+        .ok_or_else(|| "No sleep timer is set".to_string())?;
+    timer.extend(amount).map_err(|e| e.to_string())?;
+
+    println!("Sleep timer: Extended by {}", amount);
+    publish_sleep_timer(&app_handle, &guard)
+}
+
+#[tauri::command]
+async fn cancel_sleep_timer(
+    app_handle: AppHandle,
+    state: State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let mut guard = state.lock().await;
+    if guard.sleep_timer.take().is_some() {
+        println!("Sleep timer: Cancelled");
+    }
+    publish_sleep_timer(&app_handle, &guard).map(|_| ())
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AudioOutputChanged {
+    device: Option<String>,
+    previous_device: Option<String>,
+}
+
+/// Watch for an output device that stopped consuming audio (unplugged DAC, disconnected
+/// headset) and move playback to the current default device.
+fn setup_output_watchdog(app_handle: AppHandle, state: Arc<Mutex<AppState>>, audio: AudioHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(OUTPUT_WATCHDOG_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if !audio.output_stalled().await {
+                continue;
+            }
+
+            // The local clock stopped along with the output, so restart where the server is
+            let resume_position = state.lock().await.server_position();
+
+            let previous_device = audio.output_device_name();
+            println!(
+                "Audio: Output {} stopped consuming audio, switching to default device",
+                previous_device.as_deref().unwrap_or("<unknown>")
+            );
+
+            if let Err(e) = audio.set_output_device(None, Some(resume_position)).await {
+                println!("Audio: Failed to recover audio output: {}", e);
+                continue;
+            }
+
+            let payload = AudioOutputChanged {
+                device: audio.output_device_name(),
+                previous_device,
+            };
+
+            if let Err(e) = app_handle.emit("audio_output_changed", &payload) {
+                println!("Failed to emit audio_output_changed event: {}", e);
+            }
+        }
+    });
+}
+
+/// Publish buffer metrics while a network stream feeds playback, plus one final update when
+/// it stops so the UI can clear its stall indicator.
+fn setup_buffer_metrics_emitter(app_handle: AppHandle, audio: AudioHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(BUFFER_METRICS_INTERVAL);
+        let mut was_streaming = false;
+
+        loop {
+            interval.tick().await;
+
+            let metrics = audio.buffer_metrics();
+            if !metrics.streaming && !was_streaming {
+                continue;
+            }
+            was_streaming = metrics.streaming;
+
+            if let Err(e) = app_handle.emit("buffer_metrics", &metrics) {
+                println!("Failed to emit buffer_metrics event: {}", e);
+            }
+        }
+    });
+}
+
+/// Analyze what is playing and publish `audio_spectrum` frames for the visualizer. Frames
+/// stop once the bands have settled after playback stops, and while the visualizer is off.
+fn setup_spectrum_emitter(app_handle: AppHandle, spectrum: Arc<SpectrumAnalyzer>) {
+    tauri::async_runtime::spawn(async move {
+        let mut analysis = SpectrumAnalysis::new(spectrum);
+        let mut interval = tokio::time::interval(SPECTRUM_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            interval.tick().await;
+
+            let Some(frame) = analysis.next_frame() else {
+                continue;
+            };
+            if let Err(e) = app_handle.emit("audio_spectrum", &frame) {
+                println!("Failed to emit audio_spectrum event: {}", e);
+            }
+        }
+    });
+}
+
+/// Drive the sleep timer: lower the volume over its last stretch, then pause exactly like
+/// `play_pause` does. The user's volume is restored once the timer is gone.
+fn setup_sleep_timer(
+    app_handle: AppHandle,
+    state: Arc<Mutex<AppState>>,
+    audio: AudioHandle,
+    server: Arc<ServerClient>,
+    config: Arc<Mutex<AppConfig>>,
+    fades: Arc<FadeSettings>,
+) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(SLEEP_TIMER_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut gain = 1.0f32;
+
+        loop {
+            interval.tick().await;
+
+            let mut guard = state.lock().await;
+            match guard.poll_sleep_timer() {
+                None if gain < 1.0 => {
+                    gain = 1.0;
+                    audio.mixer().set_fade_gain(1.0);
+                }
+                None => {}
+                Some(SleepTimerAction::Running { gain: next }) if next != gain => {
+                    gain = next;
+                    // Squared like the fader for a roughly even perceived ramp
+                    audio.mixer().set_fade_gain(gain * gain);
+                }
+                Some(SleepTimerAction::Running { .. }) => {}
+                Some(SleepTimerAction::Expired) => {
+                    println!("Sleep timer: Time is up, pausing playback");
+                    guard.sleep_timer = None;
+                    drop(guard);
+
+                    let soft_pause = config.lock().await.soft_pause_keep();
+                    let guard = state.lock().await;
+                    if !guard.user_paused && guard.player_status == PlaybackStatus::Playing {
+                        if let Err(e) =
+                            pause_playback(&app_handle, guard, &audio, &server, soft_pause).await
+                        {
+                            println!("Sleep timer: Failed to pause playback: {}", e);
+                        }
+                    } else if let Err(e) = publish_sleep_timer(&app_handle, &guard) {
+                        println!("Sleep timer: Failed to publish expiry: {}", e);
+                    }
+
+                    // Let the pause fade finish at the lowered gain before lifting it
+                    tokio::time::sleep(fades.fade_out() + SLEEP_FADE_RESET_MARGIN).await;
+                    gain = 1.0;
+                    audio.mixer().set_fade_gain(1.0);
+                }
+            }
+        }
+    });
+}
+
 // FFmpeg-related commands removed - using HTTP streaming instead
 
 fn main() {
@@ -320,13 +877,34 @@ fn main() {
         AppConfig::default()
     });
 
-    let app_state = Arc::new(Mutex::new(AppState::new_with_volume(config.volume)));
     let config_arc = Arc::new(Mutex::new(config.clone()));
-
-    // Initialize audio manager with HTTP streaming
-    let audio_manager = Arc::new(Mutex::new(
-        AudioManager::new().expect("Failed to initialize audio"),
+    let audio_cache = Arc::new(AudioCache::from_config(&config));
+    let equalizer = Arc::new(Equalizer::new(&config.equalizer));
+    let loudness = Arc::new(LoudnessNormalizer::new(
+        config.loudness_db_path(),
+        config.normalize_loudness,
     ));
+    let fades = Arc::new(FadeSettings::new(&config.fades));
+
+    // Start the audio task with HTTP streaming
+    let audio_handle = AudioHandle::spawn(
+        audio_cache.clone(),
+        equalizer.clone(),
+        loudness.clone(),
+        fades.clone(),
+        config.output_device.as_deref(),
+        config.stream_transport,
+    )
+    .expect("Failed to initialize audio");
+    audio_handle
+        .spectrum()
+        .set_enabled(config.spectrum_visualizer);
+    audio_handle.mixer().update(&config.mixer);
+    audio_handle.crossfeed().set_level(config.crossfeed);
+
+    let mut initial_state = AppState::new_with_volume(config.volume);
+    initial_state.attach_playback_clock(audio_handle.playback_clock());
+    let app_state = Arc::new(Mutex::new(initial_state));
 
     let server_client = Arc::new(ServerClient::new());
 
@@ -336,9 +914,13 @@ fn main() {
         .plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_dialog::init())
         .manage(app_state.clone())
-        .manage(audio_manager.clone())
+        .manage(audio_handle.clone())
         .manage(server_client.clone())
         .manage(config_arc.clone())
+        .manage(audio_cache.clone())
+        .manage(equalizer.clone())
+        .manage(loudness.clone())
+        .manage(fades.clone())
         .invoke_handler(tauri::generate_handler![
             play_pause,
             set_volume,
@@ -346,12 +928,40 @@ fn main() {
             get_player_state,
             connect_to_server,
             get_hyprland_theme,
-            get_theme_overrides
+            get_theme_overrides,
+            get_cache_usage,
+            clear_cache,
+            get_buffer_metrics,
+            list_output_devices,
+            get_output_device,
+            set_output_device,
+            get_equalizer,
+            set_equalizer_enabled,
+            set_equalizer_band,
+            set_equalizer_bands,
+            apply_equalizer_preset,
+            save_equalizer_preset,
+            delete_equalizer_preset,
+            get_loudness_normalization,
+            set_loudness_normalization,
+            get_crossfeed,
+            set_crossfeed,
+            get_fades,
+            set_fades,
+            get_mixer,
+            set_mixer,
+            get_soft_pause,
+            set_soft_pause,
+            get_spectrum_visualizer,
+            set_spectrum_visualizer,
+            set_sleep_timer,
+            extend_sleep_timer,
+            cancel_sleep_timer
         ])
         .setup(move |app| {
             let app_handle = app.handle().clone();
             let state_clone = app_state.clone();
-            let audio_clone = audio_manager.clone();
+            let audio_clone = audio_handle.clone();
             let server_clone = server_client.clone();
 
 
@@ -367,6 +977,18 @@ fn main() {
             // Setup file watcher for matugen color changes
             setup_matugen_watcher(app_handle.clone());
 
+            setup_output_watchdog(app_handle.clone(), state_clone.clone(), audio_clone.clone());
+            setup_buffer_metrics_emitter(app_handle.clone(), audio_clone.clone());
+            setup_spectrum_emitter(app_handle.clone(), audio_clone.spectrum());
+            setup_sleep_timer(
+                app_handle.clone(),
+                state_clone.clone(),
+                audio_clone.clone(),
+                server_clone.clone(),
+                config_arc.clone(),
+                fades.clone(),
+            );
+
             if let Some(theme_overrides) = theme::load_theme_overrides(&config) {
                 println!(
                     "Loaded CSS theme overrides from {}",
@@ -377,17 +999,13 @@ fn main() {
                 }
             }
 
-            // Initialize volume from config in the async context
+            // Initialize volume from config
             let volume = config.volume;
-            let audio_clone_for_init = audio_clone.clone();
-            tauri::async_runtime::spawn(async move {
-                let mut audio_guard = audio_clone_for_init.lock().await;
-                if let Err(e) = audio_guard.set_volume(volume) {
-                    println!("Failed to set initial volume: {}", e);
-                } else {
-                    println!("Set initial volume to: {}", volume);
-                }
-            });
+            if let Err(e) = audio_clone.set_volume(volume) {
+                println!("Failed to set initial volume: {}", e);
+            } else {
+                println!("Set initial volume to: {}", volume);
+            }
 
             // Set up MPRIS event listeners before spawning background task
             #[cfg(target_os = "linux")]
@@ -399,8 +1017,9 @@ fn main() {
 
                     tauri::async_runtime::spawn(async move {
                         let state_handle = handle.state::<Arc<Mutex<AppState>>>();
-                        let audio_handle = handle.state::<Arc<Mutex<AudioManager>>>();
+                        let audio_handle = handle.state::<AudioHandle>();
                         let server_handle = handle.state::<Arc<ServerClient>>();
+                        let config_handle = handle.state::<Arc<Mutex<AppConfig>>>();
                         let handle_for_call = handle.clone();
 
                         if let Err(e) = crate::play_pause(
@@ -408,6 +1027,7 @@ fn main() {
                             state_handle,
                             audio_handle,
                             server_handle,
+                            config_handle,
                         )
                         .await
                         {
@@ -424,13 +1044,15 @@ fn main() {
 
                         tauri::async_runtime::spawn(async move {
                             let state_handle = handle.state::<Arc<Mutex<AppState>>>();
-                            let audio_handle = handle.state::<Arc<Mutex<AudioManager>>>();
+                            let audio_handle = handle.state::<AudioHandle>();
+                            let config_handle = handle.state::<Arc<Mutex<AppConfig>>>();
                             let handle_for_call = handle.clone();
 
                             if let Err(e) = crate::set_volume(
                                 handle_for_call,
                                 state_handle,
                                 audio_handle,
+                                config_handle,
                                 volume,
                             )
                             .await
@@ -0,0 +1,238 @@
+use std::fmt;
+use symphonia::core::codecs::{
+    CodecType, CODEC_TYPE_AAC, CODEC_TYPE_ALAC, CODEC_TYPE_FLAC, CODEC_TYPE_MP3, CODEC_TYPE_NULL,
+    CODEC_TYPE_OPUS, CODEC_TYPE_VORBIS,
+};
+
+/// Bytes needed from the start of a stream to recognize its container
+pub const SNIFF_LEN: usize = 12;
+
+/// Containers the backend may serve, either as a stream or as a cache file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaFormat {
+    Mp4,
+    /// Raw AAC frames, as carried by the HLS segments
+    Adts,
+    Mp3,
+    Flac,
+    Ogg,
+    /// WebM and other Matroska files
+    Matroska,
+    Wav,
+}
+
+impl MediaFormat {
+    pub const ALL: [Self; 7] = [
+        Self::Mp4,
+        Self::Adts,
+        Self::Mp3,
+        Self::Flac,
+        Self::Ogg,
+        Self::Matroska,
+        Self::Wav,
+    ];
+
+    /// Recognize the container from the first `SNIFF_LEN` bytes of the stream
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        match header {
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(Self::Mp4),
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            [b'O', b'g', b'g', b'S', ..] => Some(Self::Ogg),
+            [0x1A, 0x45, 0xDF, 0xA3, ..] => Some(Self::Matroska),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Self::Wav),
+            // MPEG sync word; layer bits 00 mean ADTS, anything else is MPEG audio
+            [0xFF, second, ..] if second & 0xF6 == 0xF0 => Some(Self::Adts),
+            [0xFF, second, ..] if second & 0xE0 == 0xE0 && second & 0x06 != 0 => Some(Self::Mp3),
+            // An ID3 tag may front MP3 as well as ADTS, so it is left to the probe
+            _ => None,
+        }
+    }
+
+    /// Map a `Content-Type` header value. `None` for generic binary types and anything else
+    /// that does not name one of these containers, which is left to the probe.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        let format = match mime.as_str() {
+            "audio/mp4" | "audio/m4a" | "audio/x-m4a" | "video/mp4" => Self::Mp4,
+            "audio/aac" | "audio/aacp" | "audio/x-aac" => Self::Adts,
+            "audio/mpeg" | "audio/mp3" | "audio/x-mpeg" => Self::Mp3,
+            "audio/flac" | "audio/x-flac" => Self::Flac,
+            "audio/ogg" | "audio/opus" | "audio/vorbis" | "application/ogg" => Self::Ogg,
+            "audio/webm" | "video/webm" | "audio/x-matroska" | "video/x-matroska" => Self::Matroska,
+            "audio/wav" | "audio/x-wav" | "audio/wave" => Self::Wav,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Decide on the container: the magic bytes win, since a cache file or a misconfigured
+    /// server can be wrong about the type, and the declared `Content-Type` breaks the tie
+    pub fn detect(header: &[u8], content_type: Option<&str>) -> Option<Self> {
+        Self::sniff(header).or_else(|| content_type.and_then(Self::from_content_type))
+    }
+
+    /// Human-readable name of the container, for errors
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Mp4 => "MP4",
+            Self::Adts => "ADTS",
+            Self::Mp3 => "MP3",
+            Self::Flac => "FLAC",
+            Self::Ogg => "Ogg",
+            Self::Matroska => "WebM/Matroska",
+            Self::Wav => "WAV",
+        }
+    }
+
+    /// File extension for cache files
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "m4a",
+            Self::Adts => "aac",
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Ogg => "ogg",
+            Self::Matroska => "webm",
+            Self::Wav => "wav",
+        }
+    }
+}
+
+/// Human-readable name of a codec, for errors
+pub fn codec_name(codec: CodecType) -> String {
+    match codec {
+        CODEC_TYPE_AAC => "AAC".to_string(),
+        CODEC_TYPE_ALAC => "ALAC".to_string(),
+        CODEC_TYPE_FLAC => "FLAC".to_string(),
+        CODEC_TYPE_MP3 => "MP3".to_string(),
+        CODEC_TYPE_OPUS => "Opus".to_string(),
+        CODEC_TYPE_VORBIS => "Vorbis".to_string(),
+        CODEC_TYPE_NULL => "unknown codec".to_string(),
+        other => format!("codec {}", other),
+    }
+}
+
+/// Audio this build can not decode, named so the user can tell what the server sent
+#[derive(Debug, Clone)]
+pub struct UnsupportedFormat {
+    pub format: String,
+}
+
+impl UnsupportedFormat {
+    pub fn new(format: impl Into<String>) -> Self {
+        Self {
+            format: format.into(),
+        }
+    }
+}
+
+impl fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unsupported audio format: {}", self.format)
+    }
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: &[u8]) -> [u8; SNIFF_LEN] {
+        let mut header = [0u8; SNIFF_LEN];
+        header[..bytes.len()].copy_from_slice(bytes);
+        header
+    }
+
+    #[test]
+    fn sniff_recognizes_each_container() {
+        let cases: [(&[u8], MediaFormat); 8] = [
+            (b"\0\0\0\x20ftypM4A ", MediaFormat::Mp4),
+            (b"fLaC\0\0\0\x22", MediaFormat::Flac),
+            (b"OggS\0\x02", MediaFormat::Ogg),
+            (&[0x1A, 0x45, 0xDF, 0xA3, 0x9F], MediaFormat::Matroska),
+            (b"RIFF\x24\0\0\0WAVE", MediaFormat::Wav),
+            // MPEG-4 and MPEG-2 ADTS, with and without CRC
+            (&[0xFF, 0xF1, 0x50, 0x80], MediaFormat::Adts),
+            (&[0xFF, 0xF8, 0x50, 0x80], MediaFormat::Adts),
+            // MPEG-1 layer III
+            (&[0xFF, 0xFB, 0x90, 0x64], MediaFormat::Mp3),
+        ];
+
+        for (bytes, expected) in cases {
+            assert_eq!(
+                MediaFormat::sniff(&header(bytes)),
+                Some(expected),
+                "{:x?}",
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn sniff_leaves_tags_and_unknown_bytes_to_the_probe() {
+        assert_eq!(MediaFormat::sniff(&header(b"ID3\x04\0\0")), None);
+        assert_eq!(MediaFormat::sniff(&header(b"<html>")), None);
+        // Sync word with the reserved layer bits is neither ADTS nor MPEG audio
+        assert_eq!(MediaFormat::sniff(&header(&[0xFF, 0xE0])), None);
+        assert_eq!(MediaFormat::sniff(&[]), None);
+        assert_eq!(MediaFormat::sniff(b"fLa"), None);
+    }
+
+    #[test]
+    fn content_type_ignores_case_and_parameters() {
+        assert_eq!(
+            MediaFormat::from_content_type("audio/webm; codecs=\"opus\""),
+            Some(MediaFormat::Matroska)
+        );
+        assert_eq!(
+            MediaFormat::from_content_type(" Audio/MP4 "),
+            Some(MediaFormat::Mp4)
+        );
+        assert_eq!(
+            MediaFormat::from_content_type("audio/ogg;codecs=opus"),
+            Some(MediaFormat::Ogg)
+        );
+        assert_eq!(
+            MediaFormat::from_content_type("audio/mpeg"),
+            Some(MediaFormat::Mp3)
+        );
+        assert_eq!(
+            MediaFormat::from_content_type("application/octet-stream"),
+            None
+        );
+        assert_eq!(MediaFormat::from_content_type(""), None);
+    }
+
+    #[test]
+    fn detect_prefers_magic_bytes_over_the_content_type() {
+        let ogg = header(b"OggS");
+        assert_eq!(
+            MediaFormat::detect(&ogg, Some("audio/mp4")),
+            Some(MediaFormat::Ogg)
+        );
+        assert_eq!(
+            MediaFormat::detect(&header(b"ID3"), Some("audio/aac")),
+            Some(MediaFormat::Adts)
+        );
+        assert_eq!(
+            MediaFormat::detect(&header(b"ID3"), Some("application/octet-stream")),
+            None
+        );
+        assert_eq!(MediaFormat::detect(&ogg, None), Some(MediaFormat::Ogg));
+    }
+
+    #[test]
+    fn every_format_has_a_distinct_extension() {
+        let mut extensions: Vec<_> = MediaFormat::ALL.iter().map(|f| f.extension()).collect();
+        extensions.sort_unstable();
+        extensions.dedup();
+        assert_eq!(extensions.len(), MediaFormat::ALL.len());
+    }
+}
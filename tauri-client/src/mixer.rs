@@ -0,0 +1,315 @@
+use anyhow::{anyhow, Result};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const LOG_CURVE_RANGE_DB: f32 = 60.0; // Attenuation at the bottom of the slider, just above 0
+
+/// How the 0-1 volume slider maps to output amplitude
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeCurve {
+    /// Slider value used as amplitude; most of the audible range sits in the top half
+    #[default]
+    Linear,
+    /// Even steps in dB across the slider
+    Logarithmic,
+    /// Slider value cubed; close to the dB curve without its jump to silence at 0
+    Cubic,
+}
+
+impl VolumeCurve {
+    const ALL: [Self; 3] = [Self::Linear, Self::Logarithmic, Self::Cubic];
+
+    /// Amplitude for the slider value `volume`
+    pub fn gain(self, volume: f32) -> f32 {
+        let volume = volume.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => volume,
+            Self::Logarithmic if volume <= 0.0 => 0.0,
+            Self::Logarithmic => 10f32.powf((volume - 1.0) * LOG_CURVE_RANGE_DB / 20.0),
+            Self::Cubic => volume * volume * volume,
+        }
+    }
+}
+
+/// Persisted output mix settings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MixerConfig {
+    #[serde(default)]
+    pub volume_curve: VolumeCurve,
+    /// -1.0 plays only the left channel, 1.0 only the right
+    #[serde(default)]
+    pub balance: f32,
+    /// Fold all channels into each one, for listening on a single earbud
+    #[serde(default)]
+    pub mono: bool,
+}
+
+impl MixerConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(-1.0..=1.0).contains(&self.balance) {
+            return Err(anyhow!("Balance must be between -1.0 and 1.0"));
+        }
+        Ok(())
+    }
+}
+
+/// Live mix settings shared between the UI commands and the audio task.
+///
+/// The volume curve is applied to the sink volume by the audio task, so the rest of the app
+/// (the slider, MPRIS, the saved config) keeps working with the user-facing value. Balance,
+/// mono and the sleep-timer fade are applied per frame by `MixSource`.
+pub struct Mixer {
+    volume_curve: AtomicU8,
+    balance: AtomicU32,
+    mono: AtomicBool,
+    // Not persisted; the sleep timer lowers it towards the end and puts it back afterwards
+    fade_gain: AtomicU32,
+}
+
+impl Mixer {
+    pub fn new(config: &MixerConfig) -> Self {
+        let mixer = Self {
+            volume_curve: AtomicU8::new(0),
+            balance: AtomicU32::new(0f32.to_bits()),
+            mono: AtomicBool::new(false),
+            fade_gain: AtomicU32::new(1f32.to_bits()),
+        };
+        mixer.update(config);
+        mixer
+    }
+
+    pub fn update(&self, config: &MixerConfig) {
+        let curve = VolumeCurve::ALL
+            .iter()
+            .position(|curve| *curve == config.volume_curve)
+            .unwrap_or(0);
+        self.volume_curve.store(curve as u8, Ordering::Relaxed);
+        self.balance
+            .store(config.balance.clamp(-1.0, 1.0).to_bits(), Ordering::Relaxed);
+        self.mono.store(config.mono, Ordering::Relaxed);
+    }
+
+    /// Sink volume for the slider value `volume`
+    pub fn output_volume(&self, volume: f32) -> f32 {
+        let curve = VolumeCurve::ALL
+            .get(self.volume_curve.load(Ordering::Relaxed) as usize)
+            .copied()
+            .unwrap_or_default();
+        curve.gain(volume)
+    }
+
+    /// Extra gain on top of the volume, so a fade can run without touching the sink volume
+    pub fn set_fade_gain(&self, gain: f32) {
+        self.fade_gain
+            .store(gain.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    fn fade_gain(&self) -> f32 {
+        f32::from_bits(self.fade_gain.load(Ordering::Relaxed))
+    }
+
+    /// Left and right channel gains for the current balance
+    fn channel_gains(&self) -> (f32, f32) {
+        let balance = f32::from_bits(self.balance.load(Ordering::Relaxed));
+        ((1.0 - balance).min(1.0), (1.0 + balance).min(1.0))
+    }
+
+    /// Wrap `source` in a stage that applies the balance, mono and fade settings
+    pub fn apply<S>(self: &Arc<Self>, source: S) -> MixSource<S>
+    where
+        S: Source<Item = i16>,
+    {
+        MixSource {
+            inner: source,
+            mixer: self.clone(),
+            frame: Vec::with_capacity(2),
+            next: 0,
+        }
+    }
+}
+
+/// Mixes one frame at a time, since mono needs every channel of a frame before it can
+/// emit the first
+pub struct MixSource<S> {
+    inner: S,
+    mixer: Arc<Mixer>,
+    frame: Vec<i16>,
+    next: usize,
+}
+
+impl<S> MixSource<S>
+where
+    S: Source<Item = i16>,
+{
+    fn fill_frame(&mut self) -> bool {
+        let channels = self.inner.channels().max(1) as usize;
+        self.frame.clear();
+        self.next = 0;
+        self.frame.extend(self.inner.by_ref().take(channels));
+        if self.frame.is_empty() {
+            return false;
+        }
+
+        if self.frame.len() > 1 && self.mixer.mono.load(Ordering::Relaxed) {
+            let sum: i32 = self.frame.iter().map(|&sample| sample as i32).sum();
+            let mixed = (sum / self.frame.len() as i32) as i16;
+            self.frame.fill(mixed);
+        }
+
+        // Front left and right; any further channels are left as they are
+        if self.frame.len() >= 2 {
+            let (left, right) = self.mixer.channel_gains();
+            if left < 1.0 {
+                self.frame[0] = (self.frame[0] as f32 * left) as i16;
+            }
+            if right < 1.0 {
+                self.frame[1] = (self.frame[1] as f32 * right) as i16;
+            }
+        }
+
+        let fade = self.mixer.fade_gain();
+        if fade < 1.0 {
+            for sample in self.frame.iter_mut() {
+                *sample = (*sample as f32 * fade) as i16;
+            }
+        }
+        true
+    }
+}
+
+impl<S> Iterator for MixSource<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.next >= self.frame.len() && !self.fill_frame() {
+            return None;
+        }
+
+        let sample = self.frame[self.next];
+        self.next += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for MixSource<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        // The rest of the buffered frame still belongs to the inner source's current span
+        let buffered = self.frame.len() - self.next;
+        self.inner.current_frame_len().map(|len| len + buffered)
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn mixed(config: &MixerConfig, channels: u16, samples: Vec<i16>) -> Vec<i16> {
+        let mixer = Arc::new(Mixer::new(config));
+        mixer
+            .apply(SamplesBuffer::new(channels, 48000, samples))
+            .collect()
+    }
+
+    #[test]
+    fn volume_curves_map_the_slider_ends_and_middle() {
+        for curve in VolumeCurve::ALL {
+            assert_eq!(curve.gain(0.0), 0.0);
+            assert_eq!(curve.gain(1.0), 1.0);
+            assert_eq!(curve.gain(-0.5), 0.0);
+            assert_eq!(curve.gain(1.5), 1.0);
+        }
+
+        assert_eq!(VolumeCurve::Linear.gain(0.5), 0.5);
+        assert_eq!(VolumeCurve::Cubic.gain(0.5), 0.125);
+        // Half way down the slider is half of the 60 dB range
+        assert!((VolumeCurve::Logarithmic.gain(0.5) - 10f32.powf(-1.5)).abs() < 1e-6);
+        assert!((VolumeCurve::Logarithmic.gain(0.01) - 10f32.powf(-2.97)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn output_volume_follows_the_configured_curve() {
+        let mixer = Mixer::new(&MixerConfig::default());
+        assert_eq!(mixer.output_volume(0.5), 0.5);
+
+        mixer.update(&MixerConfig {
+            volume_curve: VolumeCurve::Cubic,
+            ..Default::default()
+        });
+        assert_eq!(mixer.output_volume(0.5), 0.125);
+    }
+
+    #[test]
+    fn balance_attenuates_the_opposite_channel() {
+        let samples = vec![1000, 1000, -1000, -1000];
+        assert_eq!(mixed(&MixerConfig::default(), 2, samples.clone()), samples);
+
+        let left = MixerConfig {
+            balance: -0.5,
+            ..Default::default()
+        };
+        assert_eq!(mixed(&left, 2, samples.clone()), [1000, 500, -1000, -500]);
+
+        let right = MixerConfig {
+            balance: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(mixed(&right, 2, samples), [0, 1000, 0, -1000]);
+
+        assert!(left.validate().is_ok());
+        assert!(MixerConfig {
+            balance: 1.5,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn mono_folds_channels_and_leaves_mono_sources_alone() {
+        let mono = MixerConfig {
+            mono: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            mixed(&mono, 2, vec![1000, 0, 300, 500]),
+            [500, 500, 400, 400]
+        );
+        assert_eq!(mixed(&mono, 1, vec![1000, 0]), [1000, 0]);
+    }
+
+    #[test]
+    fn fade_gain_scales_every_channel() {
+        let mixer = Arc::new(Mixer::new(&MixerConfig::default()));
+        mixer.set_fade_gain(0.5);
+        let faded: Vec<i16> = mixer
+            .apply(SamplesBuffer::new(2, 48000, vec![1000, -1000]))
+            .collect();
+        assert_eq!(faded, [500, -500]);
+
+        mixer.set_fade_gain(2.0);
+        assert_eq!(mixer.fade_gain(), 1.0);
+    }
+}
@@ -0,0 +1,164 @@
+use audiopus::coder::{Decoder as Libopus, GenericCtl};
+use audiopus::packet::Packet as OpusPacket;
+use audiopus::{Channels as OpusChannels, MutSignals, SampleRate};
+use std::sync::{Mutex, PoisonError};
+use symphonia::core::audio::{
+    AsAudioBufferRef, AudioBuffer, AudioBufferRef, Channels, Signal, SignalSpec,
+};
+use symphonia::core::codecs::{
+    CodecDescriptor, CodecParameters, Decoder, DecoderOptions, FinalizeResult, CODEC_TYPE_OPUS,
+};
+use symphonia::core::errors::{decode_error, unsupported_error, Result};
+use symphonia::core::formats::Packet;
+use symphonia::core::support_codec;
+use symphonia::core::units::TimeBase;
+
+// Opus always decodes at 48 kHz; the rate in the header only describes the original input
+const SAMPLE_RATE: u32 = 48_000;
+
+// Longest duration a single packet can hold: 120 ms at 48 kHz
+const MAX_FRAMES: usize = 5_760;
+
+/// libopus-backed symphonia decoder, for the Opus tracks the Ogg and Matroska readers hand
+/// out (YouTube audio is mostly WebM/Opus)
+///
+/// Only mono and stereo streams are handled; surround Opus needs the multistream API.
+pub struct OpusDecoder {
+    params: CodecParameters,
+    // Only ever used through `&mut self`; the mutex is there because symphonia decoders must
+    // be `Sync` and the libopus handle is not
+    coder: Mutex<Libopus>,
+    channels: usize,
+    // Frames of encoder priming at the start of the stream, to be dropped
+    pre_skip: u64,
+    time_base: Option<TimeBase>,
+    // Interleaved output of the last packet, as libopus writes it
+    pcm: Vec<f32>,
+    buffer: AudioBuffer<f32>,
+}
+
+impl OpusDecoder {
+    /// Position of a packet timestamp in 48 kHz frames
+    fn frames_at(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(ts);
+                time.seconds * u64::from(SAMPLE_RATE)
+                    + (time.frac * f64::from(SAMPLE_RATE)).round() as u64
+            }
+            None => ts,
+        }
+    }
+}
+
+impl Decoder for OpusDecoder {
+    fn try_new(params: &CodecParameters, _options: &DecoderOptions) -> Result<Self> {
+        if params.codec != CODEC_TYPE_OPUS {
+            return unsupported_error("opus: invalid codec type");
+        }
+
+        // The identification header, which Matroska stores as the codec private data and the
+        // Ogg reader passes on as extra data
+        let head = params
+            .extra_data
+            .as_deref()
+            .filter(|head| head.len() >= 19 && head.starts_with(b"OpusHead"));
+
+        let channels = head
+            .map(|head| head[9] as usize)
+            .or_else(|| params.channels.map(|channels| channels.count()))
+            .or_else(|| {
+                params
+                    .channel_layout
+                    .map(|layout| layout.into_channels().count())
+            })
+            .unwrap_or(2);
+        let pre_skip = head
+            .map(|head| u64::from(u16::from_le_bytes([head[10], head[11]])))
+            .or_else(|| params.delay.map(u64::from))
+            .unwrap_or(0);
+
+        let (opus_channels, layout) = match channels {
+            1 => (OpusChannels::Mono, Channels::FRONT_LEFT),
+            2 => (
+                OpusChannels::Stereo,
+                Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+            ),
+            _ => return unsupported_error("opus: only mono and stereo streams are supported"),
+        };
+
+        let coder = match Libopus::new(SampleRate::Hz48000, opus_channels) {
+            Ok(coder) => coder,
+            Err(_) => return unsupported_error("opus: failed to create decoder"),
+        };
+
+        Ok(Self {
+            params: params.clone(),
+            coder: Mutex::new(coder),
+            channels,
+            pre_skip,
+            time_base: params.time_base,
+            pcm: vec![0.0; MAX_FRAMES * channels],
+            buffer: AudioBuffer::new(MAX_FRAMES as u64, SignalSpec::new(SAMPLE_RATE, layout)),
+        })
+    }
+
+    fn supported_codecs() -> &'static [CodecDescriptor] {
+        &[support_codec!(CODEC_TYPE_OPUS, "opus", "Opus (libopus)")]
+    }
+
+    fn reset(&mut self) {
+        // Drops the prediction state so a seek doesn't blend in audio from the old position
+        let coder = self.coder.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let _ = coder.reset_state();
+    }
+
+    fn codec_params(&self) -> &CodecParameters {
+        &self.params
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Result<AudioBufferRef<'_>> {
+        self.buffer.clear();
+
+        let coder = self.coder.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let input = match OpusPacket::try_from(packet.buf()) {
+            Ok(input) => input,
+            Err(_) => return decode_error("opus: invalid packet"),
+        };
+        let output = match MutSignals::try_from(&mut self.pcm) {
+            Ok(output) => output,
+            Err(_) => return decode_error("opus: output buffer too large"),
+        };
+        let frames = match coder.decode_float(Some(input), output, false) {
+            Ok(frames) => frames,
+            Err(_) => return decode_error("opus: corrupt packet"),
+        };
+
+        self.buffer.render_reserved(Some(frames));
+        for (channel, plane) in self.buffer.planes_mut().planes().iter_mut().enumerate() {
+            for (frame, sample) in plane.iter_mut().enumerate() {
+                *sample = self.pcm[frame * self.channels + channel];
+            }
+        }
+
+        // The Ogg reader only trims a start its granule positions mark as delay, and the
+        // Matroska reader ignores CodecDelay, so the pre-skip is applied here as well. Both
+        // count from the start of the packet, so the larger one covers the other and no
+        // frame is dropped twice.
+        let pre_skip = self.pre_skip.saturating_sub(self.frames_at(packet.ts())) as usize;
+        self.buffer.trim(
+            pre_skip.max(packet.trim_start() as usize),
+            packet.trim_end() as usize,
+        );
+
+        Ok(self.buffer.as_audio_buffer_ref())
+    }
+
+    fn finalize(&mut self) -> FinalizeResult {
+        Default::default()
+    }
+
+    fn last_decoded(&self) -> AudioBufferRef<'_> {
+        self.buffer.as_audio_buffer_ref()
+    }
+}
@@ -0,0 +1,188 @@
+use crate::http_stream::HttpStatusError;
+use crate::media_format::UnsupportedFormat;
+use rodio::{PlayError, StreamError};
+use serde::Serialize;
+use std::error::Error as StdError;
+use std::io::{Error as IoError, ErrorKind};
+use symphonia::core::errors::Error as SymphoniaError;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_notification::NotificationExt;
+
+/// Rough cause of a failed playback start
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackErrorKind {
+    /// The stream could not be fetched from the server
+    Network,
+    /// The stream arrived but could not be decoded
+    Decode,
+    /// The audio output device could not be used
+    Output,
+    Other,
+}
+
+impl PlaybackErrorKind {
+    pub fn classify(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(find_cause)
+            .map_or(Self::Other, |cause| cause.kind)
+    }
+
+    fn summary(self) -> &'static str {
+        match self {
+            Self::Network => "Lost connection to the music server",
+            Self::Decode => "This track could not be decoded",
+            Self::Output => "The audio output device is unavailable",
+            Self::Other => "Playback could not start",
+        }
+    }
+}
+
+/// Worth trying the same start again after a short wait: the connection could not be made
+/// or timed out, or the server failed with a 5xx
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .find_map(find_cause)
+        .is_some_and(|cause| cause.transient)
+}
+
+/// What a failure comes down to, read from the first typed error in its chain
+struct Cause {
+    kind: PlaybackErrorKind,
+    transient: bool,
+}
+
+impl Cause {
+    fn new(kind: PlaybackErrorKind, transient: bool) -> Self {
+        Self { kind, transient }
+    }
+}
+
+fn find_cause(error: &(dyn StdError + 'static)) -> Option<Cause> {
+    if error.is::<UnsupportedFormat>() {
+        return Some(Cause::new(PlaybackErrorKind::Decode, false));
+    }
+    if error.is::<PlayError>() || error.is::<StreamError>() {
+        return Some(Cause::new(PlaybackErrorKind::Output, false));
+    }
+    if let Some(error) = error.downcast_ref::<HttpStatusError>() {
+        return Some(Cause::new(
+            PlaybackErrorKind::Network,
+            error.status.is_server_error(),
+        ));
+    }
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        let transient = error.is_connect()
+            || error.is_timeout()
+            || error
+                .status()
+                .is_some_and(|status| status.is_server_error());
+        return Some(Cause::new(PlaybackErrorKind::Network, transient));
+    }
+    if let Some(error) = error.downcast_ref::<SymphoniaError>() {
+        // Symphonia only exposes the I/O error behind a failed read through `cause`
+        return match error {
+            SymphoniaError::IoError(error) => find_io_cause(error),
+            _ => Some(Cause::new(PlaybackErrorKind::Decode, false)),
+        };
+    }
+    if let Some(error) = error.downcast_ref::<IoError>() {
+        return find_io_cause(error);
+    }
+    None
+}
+
+/// `io::Error::source` skips the error it wraps, so look at that one directly
+fn find_io_cause(error: &IoError) -> Option<Cause> {
+    match error.kind() {
+        ErrorKind::TimedOut | ErrorKind::ConnectionRefused => {
+            return Some(Cause::new(PlaybackErrorKind::Network, true));
+        }
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+            return Some(Cause::new(PlaybackErrorKind::Network, false));
+        }
+        _ => {}
+    }
+
+    let mut next = error
+        .get_ref()
+        .map(|inner| inner as &(dyn StdError + 'static));
+    while let Some(error) = next {
+        if let Some(cause) = find_cause(error) {
+            return Some(cause);
+        }
+        next = error.source();
+    }
+    None
+}
+
+/// Payload of the `playback_error` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaybackError {
+    pub kind: PlaybackErrorKind,
+    pub message: String,
+    pub track_id: Option<String>,
+    pub attempt: u32,
+    /// Delay before the next attempt; `None` once playback has given up
+    pub retrying_in_ms: Option<u64>,
+}
+
+impl PlaybackError {
+    pub fn new(
+        error: &anyhow::Error,
+        track_id: Option<String>,
+        attempt: u32,
+        retrying_in_ms: Option<u64>,
+    ) -> Self {
+        Self {
+            kind: PlaybackErrorKind::classify(error),
+            message: format!("{:#}", error),
+            track_id,
+            attempt,
+            retrying_in_ms,
+        }
+    }
+}
+
+/// Tell the frontend about a failed start. Once retries are exhausted and the window is
+/// hidden to the tray, a desktop notification is shown as well.
+pub fn report(app_handle: &AppHandle, error: &PlaybackError) {
+    match error.retrying_in_ms {
+        Some(delay) => println!(
+            "Audio: Playback start failed (attempt {}, retrying in {}ms): {}",
+            error.attempt, delay, error.message
+        ),
+        None => println!(
+            "Audio: Playback start failed after {} attempt(s): {}",
+            error.attempt, error.message
+        ),
+    }
+
+    if let Err(e) = app_handle.emit("playback_error", error) {
+        println!("Failed to emit playback_error event: {}", e);
+    }
+
+    if error.retrying_in_ms.is_some() || main_window_visible(app_handle) {
+        return;
+    }
+
+    if let Err(e) = app_handle
+        .notification()
+        .builder()
+        .title(error.kind.summary())
+        .body(&error.message)
+        .show()
+    {
+        println!("Failed to show playback error notification: {}", e);
+    }
+}
+
+fn main_window_visible(app_handle: &AppHandle) -> bool {
+    app_handle
+        .get_webview_window("main")
+        .and_then(|window| window.is_visible().ok())
+        .unwrap_or(false)
+}
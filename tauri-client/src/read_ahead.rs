@@ -0,0 +1,134 @@
+use crate::stream_stats::StreamStats;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// Bounds for how much audio the download may run ahead of the decoder
+const MIN_READ_AHEAD_SECS: f64 = 2.0;
+const MAX_READ_AHEAD_SECS: f64 = 60.0;
+// Each second of chunk arrival jitter asks for this many seconds of extra read-ahead
+const JITTER_WEIGHT: f64 = 8.0;
+// A stall or reconnect deepens the window by this much; the boost fades as chunks arrive
+const STALL_BOOST_SECS: f64 = 10.0;
+const STALL_BOOST_DECAY: f64 = 0.995;
+// Below this ratio of download rate to stream bitrate the connection barely keeps up
+const MIN_THROUGHPUT_MARGIN: f64 = 1.5;
+// Weight of each new chunk in the running estimates
+const EWMA_WEIGHT: f64 = 0.05;
+// Used until the stream's size and length are known; the backend serves ~192 kbps AAC
+const DEFAULT_BYTE_RATE: f64 = 24_000.0;
+// Fallback wake-up for the fetcher in case it misses a consumption notification
+const FLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+struct NetworkEstimate {
+    samples: u32,
+    // Mean gap between chunks and its mean deviation, in seconds
+    gap: f64,
+    jitter: f64,
+    // Mean chunk size in bytes
+    chunk_bytes: f64,
+    stall_boost: f64,
+}
+
+/// Read-ahead window of the HTTP stream, sized from measured network conditions.
+///
+/// Chunk arrival gaps are tracked like TCP's RTT estimator: a running mean plus mean
+/// deviation. Steady connections settle at a shallow window, so little is downloaded that a
+/// seek would throw away; jittery or slow ones, or any stall, push the window deeper. The
+/// estimate outlives individual streams so the next track starts with what was learned.
+pub struct ReadAhead {
+    // f64 seconds
+    target_secs: AtomicU64,
+    estimate: Mutex<NetworkEstimate>,
+    consumed: Notify,
+}
+
+impl ReadAhead {
+    pub fn new() -> Self {
+        Self {
+            target_secs: AtomicU64::new(MIN_READ_AHEAD_SECS.to_bits()),
+            estimate: Mutex::new(NetworkEstimate {
+                samples: 0,
+                gap: 0.0,
+                jitter: 0.0,
+                chunk_bytes: 0.0,
+                stall_boost: 0.0,
+            }),
+            consumed: Notify::new(),
+        }
+    }
+
+    pub fn target_secs(&self) -> f64 {
+        f64::from_bits(self.target_secs.load(Ordering::Relaxed))
+    }
+
+    /// Window in bytes at the stream's bitrate
+    pub fn target_bytes(&self, stats: &StreamStats) -> u64 {
+        let byte_rate = stats.byte_rate().unwrap_or(DEFAULT_BYTE_RATE);
+        (self.target_secs() * byte_rate) as u64
+    }
+
+    /// A chunk of `bytes` arrived `gap` after the previous one (time spent paused by flow
+    /// control excluded)
+    pub fn record_chunk(&self, bytes: usize, gap: Duration, byte_rate: Option<f64>) {
+        let Ok(mut estimate) = self.estimate.lock() else {
+            return;
+        };
+
+        let gap = gap.as_secs_f64();
+        if estimate.samples == 0 {
+            estimate.gap = gap;
+            estimate.jitter = gap / 2.0;
+            estimate.chunk_bytes = bytes as f64;
+        } else {
+            let deviation = (gap - estimate.gap).abs();
+            estimate.jitter += EWMA_WEIGHT * (deviation - estimate.jitter);
+            estimate.gap += EWMA_WEIGHT * (gap - estimate.gap);
+            estimate.chunk_bytes += EWMA_WEIGHT * (bytes as f64 - estimate.chunk_bytes);
+        }
+        estimate.samples = estimate.samples.saturating_add(1);
+        estimate.stall_boost *= STALL_BOOST_DECAY;
+
+        let throughput = estimate.chunk_bytes / estimate.gap.max(1e-3);
+        let byte_rate = byte_rate.unwrap_or(DEFAULT_BYTE_RATE);
+
+        let target = if throughput < byte_rate * MIN_THROUGHPUT_MARGIN {
+            MAX_READ_AHEAD_SECS
+        } else {
+            MIN_READ_AHEAD_SECS + JITTER_WEIGHT * estimate.jitter + estimate.stall_boost
+        };
+        self.set_target(target);
+    }
+
+    /// Playback waited on the network or the connection had to be reopened
+    pub fn record_stall(&self) {
+        if let Ok(mut estimate) = self.estimate.lock() {
+            estimate.stall_boost =
+                (estimate.stall_boost + STALL_BOOST_SECS).min(MAX_READ_AHEAD_SECS);
+        }
+
+        self.set_target(self.target_secs() + STALL_BOOST_SECS);
+        println!(
+            "Audio: Stream stalled, read-ahead raised to {:.0}s",
+            self.target_secs()
+        );
+    }
+
+    fn set_target(&self, secs: f64) {
+        let secs = secs.clamp(MIN_READ_AHEAD_SECS, MAX_READ_AHEAD_SECS);
+        self.target_secs.store(secs.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Wait until the buffered data drops below the window
+    pub async fn wait_for_room(&self, stats: &StreamStats) {
+        while stats.buffered_bytes() >= self.target_bytes(stats) {
+            let _ = tokio::time::timeout(FLOW_POLL_INTERVAL, self.consumed.notified()).await;
+        }
+    }
+
+    /// The reader took data off the buffer
+    pub fn notify_consumed(&self) {
+        self.consumed.notify_one();
+    }
+}
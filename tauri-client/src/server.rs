@@ -1,11 +1,14 @@
-use crate::audio::AudioManager;
+use crate::audio::AudioHandle;
 #[cfg(target_os = "linux")]
 use crate::mpris::MprisManager;
+use crate::playback_error::{self, PlaybackError};
 use crate::state::{AppState, PlaybackStatus, Track};
+use crate::stream_token::request_secure_stream_url;
 use anyhow::{anyhow, Result};
 use futures_util::StreamExt;
 use serde::Deserialize;
 // Removed serde_json::Value - SSE provides complete data
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tauri::{AppHandle, Emitter};
@@ -13,9 +16,16 @@ use tauri::{AppHandle, Emitter};
 use tauri::Manager;
 use tokio::sync::Mutex;
 
+// Retry policy for playback starts that failed on a transient (network) error
+const START_RETRY_ATTEMPTS: u32 = 4;
+const START_RETRY_BASE_DELAY_MS: u64 = 1000; // Doubled after every failed attempt
+const START_RETRY_MAX_DELAY_MS: u64 = 8000;
+
 #[derive(Clone)]
 pub struct ServerClient {
     client: reqwest::Client,
+    /// Generation of the latest playback start; starts from older generations are stale
+    play_session: Arc<AtomicU64>,
 }
 
 impl ServerClient {
@@ -26,7 +36,27 @@ impl ServerClient {
                 .http1_only() // Force HTTP/1.1 for better SSE compatibility
                 .build()
                 .expect("Failed to create reqwest client"),
+            play_session: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Supersede every pending or in-progress playback start and return the new session
+    fn begin_play_session(&self) -> u64 {
+        self.play_session.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Drop pending and in-progress playback starts, e.g. when the user pauses
+    pub fn cancel_pending_playback(&self) {
+        self.begin_play_session();
+    }
+
+    /// True once a newer start (or a cancel) has replaced `session`
+    fn is_superseded(&self, session: u64) -> bool {
+        let superseded = self.play_session.load(Ordering::SeqCst) != session;
+        if superseded {
+            println!("Audio: Discarding superseded playback start #{}", session);
         }
+        superseded
     }
 
     // Removed get_status - SSE provides complete data
@@ -34,7 +64,7 @@ impl ServerClient {
     pub fn spawn_background(
         self: Arc<Self>,
         state: Arc<Mutex<AppState>>,
-        audio: Arc<Mutex<AudioManager>>,
+        audio: AudioHandle,
         app_handle: AppHandle,
     ) {
         tauri::async_runtime::spawn(async move {
@@ -45,7 +75,7 @@ impl ServerClient {
     async fn run_event_loop(
         self: Arc<Self>,
         state: Arc<Mutex<AppState>>,
-        audio: Arc<Mutex<AudioManager>>,
+        audio: AudioHandle,
         app_handle: AppHandle,
     ) {
         let mut reconnect_attempts = 0;
@@ -101,7 +131,7 @@ impl ServerClient {
         self: Arc<Self>,
         backend_url: String,
         state: Arc<Mutex<AppState>>,
-        audio: Arc<Mutex<AudioManager>>,
+        audio: AudioHandle,
         app_handle: AppHandle,
     ) -> Result<()> {
         let sse_url = format!("{}/api/music/state/live", backend_url);
@@ -174,7 +204,7 @@ impl ServerClient {
         self: Arc<Self>,
         block: &str,
         state: Arc<Mutex<AppState>>,
-        audio: Arc<Mutex<AudioManager>>,
+        audio: AudioHandle,
         app_handle: AppHandle,
         backend_url: &str,
         received_time_ms: f64,
@@ -227,7 +257,7 @@ impl ServerClient {
         event: String,
         payload: &str,
         state: Arc<Mutex<AppState>>,
-        audio: Arc<Mutex<AudioManager>>,
+        audio: AudioHandle,
         app_handle: AppHandle,
         backend_url: &str,
         received_time_ms: f64,
@@ -262,7 +292,7 @@ impl ServerClient {
         &self,
         data: StateEventPayload,
         state: Arc<Mutex<AppState>>,
-        audio: Arc<Mutex<AudioManager>>,
+        audio: AudioHandle,
         app_handle: AppHandle,
     ) -> Result<()> {
         let mut guard = state.lock().await;
@@ -298,6 +328,9 @@ impl ServerClient {
 
         // Store queue before moving for later use
         let queue_for_transition = data.queue.clone();
+        let previous_next_id = guard.queue.first().map(|t| t.youtube_id.clone());
+        let next_changed =
+            queue_for_transition.first().map(|t| t.youtube_id.clone()) != previous_next_id;
         guard.update_queue(data.queue);
 
         if let Some(position) = data.position {
@@ -305,14 +338,9 @@ impl ServerClient {
             guard.update_sync(position, duration);
 
             // Update audio buffer with SSE position information
-            let audio_clone = audio.clone();
-            let position_for_audio = position;
-            tokio::spawn(async move {
-                let audio_manager = audio_clone.lock().await;
-                if let Err(e) = audio_manager.update_from_sse(position_for_audio, None).await {
-                    println!("SSE: Failed to update audio buffer with position: {}", e);
-                }
-            });
+            if let Err(e) = audio.sync_position(position, None) {
+                println!("SSE: Failed to update audio buffer with position: {}", e);
+            }
         }
 
         let snapshot = guard.snapshot();
@@ -320,21 +348,33 @@ impl ServerClient {
 
         let _ = app_handle.emit("player_state_updated", snapshot);
 
-        // If track changed, immediately start playback like the frontend does
-        if track_changed {
-            // Prepare buffer for track transition if we have queue information
-            if let Some(next_track) = queue_for_transition.first() {
+        // Prefetch the next queued track so the switch to it can be gapless
+        if let Some(next_track) = queue_for_transition.first() {
+            if track_changed || next_changed {
+                let client = self.clone();
                 let audio_clone = audio.clone();
                 let backend_url_for_prep = state.lock().await.backend_url().unwrap_or_default();
                 let next_track_id = next_track.youtube_id.clone();
                 tokio::spawn(async move {
-                    let audio_manager = audio_clone.lock().await;
-                    let next_stream_url = format!("{}/api/music/stream?v={}", backend_url_for_prep, next_track_id);
-                    if let Err(e) = audio_manager.prepare_track_transition(&next_stream_url, 0.0).await {
+                    let next_stream_url = match client
+                        .track_stream_url(&backend_url_for_prep, &next_track_id)
+                        .await
+                    {
+                        Ok(url) => url,
+                        Err(e) => {
+                            println!("SSE: Failed to resolve stream for next track: {}", e);
+                            return;
+                        }
+                    };
+                    if let Err(e) = audio_clone.prefetch(&next_track_id, &next_stream_url) {
                         println!("SSE: Failed to prepare track transition: {}", e);
                     }
                 });
             }
+        }
+
+        // If track changed, immediately start playback like the frontend does
+        if track_changed {
             // Get the backend URL and check if we should start playing
             let should_start_playback = {
                 let guard = state.lock().await;
@@ -347,24 +387,15 @@ impl ServerClient {
 
             if should_start_playback {
                 // Start playback immediately like the frontend does
-                let self_clone = self.clone();
-                let state_clone = state.clone();
-                let app_handle_clone = app_handle.clone();
-
-                tokio::spawn(async move {
-                    match Arc::new(self_clone)
-                        .sync_play_now(
-                            state_clone.clone(),
-                            audio.clone(),
-                            app_handle_clone,
-                            "".to_string(), // We'll get backend_url from state
-                        )
-                        .await
-                    {
-                        Ok(()) => {}
-                        Err(_e) => {}
-                    }
-                });
+                let session = self.begin_play_session();
+                Arc::new(self.clone()).spawn_playback_start(
+                    session,
+                    state.clone(),
+                    audio,
+                    app_handle.clone(),
+                    "".to_string(), // We'll get backend_url from state
+                    Duration::ZERO,
+                );
             } else {
             }
         }
@@ -376,7 +407,7 @@ impl ServerClient {
         self: Arc<Self>,
         data: SyncPlayEventPayload,
         state: Arc<Mutex<AppState>>,
-        audio: Arc<Mutex<AudioManager>>,
+        audio: AudioHandle,
         app_handle: AppHandle,
         backend_url: String,
         received_time_ms: f64,
@@ -464,63 +495,119 @@ impl ServerClient {
         let mut time_until_play = server_buffer - elapsed_since_received - estimated_latency;
 
         // Update audio buffer with sync timing information for optimized buffering
-        let audio_clone = audio.clone();
-        let sync_position = position;
-        let latency_estimate = estimated_latency;
-        tokio::spawn(async move {
-            let audio_manager = audio_clone.lock().await;
-            if let Err(e) = audio_manager.update_from_sse(sync_position, Some(latency_estimate)).await {
-                println!("SSE: Failed to update audio buffer for sync_play: {}", e);
-            }
-        });
+        if let Err(e) = audio.sync_position(position, Some(estimated_latency)) {
+            println!("SSE: Failed to update audio buffer for sync_play: {}", e);
+        }
 
         if !time_until_play.is_finite() {
             time_until_play = 0.0;
         }
 
-        if time_until_play <= 0.0 {
-            self.clone()
-                .sync_play_now(state, audio, app_handle, backend_url.clone())
-                .await
-        } else {
-            let backend_for_spawn = backend_url.clone();
-            tokio::spawn({
-                let client = self.clone();
-                let state_clone = state.clone();
-                let audio_clone = audio.clone();
-                let app_handle_clone = app_handle.clone();
-                async move {
-                    tokio::time::sleep(Duration::from_millis(time_until_play as u64)).await;
-                    if let Err(_err) = client
-                        .sync_play_now(
-                            state_clone,
-                            audio_clone,
-                            app_handle_clone,
-                            backend_for_spawn,
-                        )
-                        .await
-                    {}
+        // Scheduling this start supersedes any earlier one, even while it waits for its delay
+        let session = self.begin_play_session();
+
+        // Runs in the background either way, so retries never hold up the event stream
+        self.spawn_playback_start(
+            session,
+            state,
+            audio,
+            app_handle,
+            backend_url,
+            Duration::from_millis(time_until_play.max(0.0) as u64),
+        );
+        Ok(())
+    }
+
+    /// Run `sync_play_now` in the background after `delay`, retrying transient failures with
+    /// backoff. Every failure is reported to the frontend as a `playback_error` event.
+    fn spawn_playback_start(
+        self: Arc<Self>,
+        session: u64,
+        state: Arc<Mutex<AppState>>,
+        audio: AudioHandle,
+        app_handle: AppHandle,
+        backend_url: String,
+        delay: Duration,
+    ) {
+        tokio::spawn(async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+
+            let mut attempt = 1;
+            loop {
+                let track_id = current_track_id(&state).await;
+                let error = match self
+                    .clone()
+                    .sync_play_now(
+                        session,
+                        state.clone(),
+                        audio.clone(),
+                        app_handle.clone(),
+                        backend_url.clone(),
+                    )
+                    .await
+                {
+                    Ok(()) => return,
+                    Err(e) => e,
+                };
+                if self.is_superseded(session) {
+                    return;
+                }
+
+                let retry_delay = (playback_error::is_transient(&error)
+                    && attempt < START_RETRY_ATTEMPTS)
+                    .then(|| start_retry_delay(attempt));
+                playback_error::report(
+                    &app_handle,
+                    &PlaybackError::new(
+                        &error,
+                        track_id,
+                        attempt,
+                        retry_delay.map(|delay| delay.as_millis() as u64),
+                    ),
+                );
+
+                let Some(retry_delay) = retry_delay else {
+                    self.abort_start(session, &state, &app_handle).await;
+                    return;
+                };
+
+                tokio::time::sleep(retry_delay).await;
+                if self.is_superseded(session) {
+                    return;
                 }
-            });
-            Ok(())
+                attempt += 1;
+            }
+        });
+    }
+
+    /// Show a failed start as stopped, unless a newer start already took over
+    async fn abort_start(&self, session: u64, state: &Mutex<AppState>, app_handle: &AppHandle) {
+        let mut guard = state.lock().await;
+        if self.is_superseded(session) {
+            return;
         }
+        guard.update_player_status(PlaybackStatus::Stopped);
+        guard.clear_sync();
+        let snapshot = guard.snapshot();
+        drop(guard);
+        let _ = app_handle.emit("player_state_updated", snapshot);
     }
 
     async fn sync_play_now(
         self: Arc<Self>,
+        session: u64,
         state: Arc<Mutex<AppState>>,
-        audio: Arc<Mutex<AudioManager>>,
+        audio: AudioHandle,
         app_handle: AppHandle,
         _backend_url: String,
     ) -> Result<()> {
-        // Always stop current playback first to ensure clean restart
-        {
-            let audio_manager = audio.lock().await;
-            audio_manager.stop().await.ok(); // Ignore errors from stopping
-        }
-
-        let (stream_url, playback_position, duration_opt, _track_info) = {
+        let (backend_url, track_id, playback_position, duration_opt, _track_info) = {
             let mut guard = state.lock().await;
+            if self.is_superseded(session) {
+                return Ok(());
+            }
 
             // Verify we have track metadata (should be from SSE state event)
             let current_track = guard.current_track.as_ref().ok_or_else(|| {
@@ -529,10 +616,11 @@ impl ServerClient {
 
             // Extract track info before mutable operations to avoid borrowing conflicts
             let track_info = format!("{} - {}", current_track.youtube_id, current_track.title);
+            let track_id = current_track.youtube_id.clone();
 
-            let stream_url = guard
-                .stream_url()
-                .ok_or_else(|| anyhow!("Server stream URL not configured"))?;
+            let backend_url = guard
+                .backend_url()
+                .ok_or_else(|| anyhow!("Server URL not configured"))?;
             let position = guard.computed_position();
             let duration = guard.duration();
 
@@ -544,58 +632,53 @@ impl ServerClient {
             } else {
                 None
             };
-            (stream_url, position, duration_opt, track_info)
+            (backend_url, track_id, position, duration_opt, track_info)
         };
 
-        // Generate fresh stream URL with timestamp to bypass caching
-        let ts_suffix = current_time_millis() as u128;
-        let full_stream_url = format!("{}?ts={}", stream_url, ts_suffix);
+        // Every play gets its own token, so no cache-busting suffix is needed
+        let stream_url = self.track_stream_url(&backend_url, &track_id).await?;
 
         {
             let mut guard = state.lock().await;
+            if self.is_superseded(session) {
+                return Ok(());
+            }
             guard.prepare_sync_preview(playback_position, duration_opt);
             let snapshot = guard.snapshot();
             drop(guard);
             let _ = app_handle.emit("player_state_updated", snapshot);
         }
 
-        let play_result = {
-            let mut audio_manager = audio.lock().await;
-
-            // Removed track end callback - track advancement is handled entirely by SSE events
-            // The backend manages track timing and automatically broadcasts state changes
-            println!("Audio: Track end handling delegated to SSE events from backend");
+        // Removed track end callback - track advancement is handled entirely by SSE events
+        // The backend manages track timing and automatically broadcasts state changes
+        println!("Audio: Track end handling delegated to SSE events from backend");
 
-            let play_result = audio_manager
-                .play_from(&full_stream_url, playback_position, duration_opt)
-                .await;
+        // The audio task continues straight into the prefetched track when it has it;
+        // otherwise it stops the current sink and opens a fresh stream
+        let play_result = audio.play(&track_id, &stream_url, playback_position).await;
 
-            // Update buffer with current playback position for better sync after play starts
-            if play_result.is_ok() {
-                let audio_clone = audio.clone();
-                let position_for_buffer = playback_position;
-                tokio::spawn(async move {
-                    let audio_manager = audio_clone.lock().await;
-                    if let Err(e) = audio_manager.update_from_sse(position_for_buffer, None).await {
-                        println!("Audio: Failed to update buffer on playback start: {}", e);
-                    }
-                });
+        {
+            // A newer start or a pause owns the player state now. Its audio command may have
+            // reached the audio task ahead of ours, so make sure a pause still wins.
+            let guard = state.lock().await;
+            if self.is_superseded(session) {
+                if play_result.is_ok() && guard.user_paused {
+                    let _ = audio.stop();
+                }
+                return Ok(());
             }
+        }
 
-            play_result
-        };
-
-        if let Err(play_err) = play_result {
-            let mut guard = state.lock().await;
-            guard.update_player_status(PlaybackStatus::Stopped);
-            guard.clear_sync();
-            let snapshot = guard.snapshot();
-            drop(guard);
-            let _ = app_handle.emit("player_state_updated", snapshot);
-
-            return Err(play_err);
+        // Update buffer with current playback position for better sync after play starts
+        if play_result.is_ok() {
+            if let Err(e) = audio.sync_position(playback_position, None) {
+                println!("Audio: Failed to update buffer on playback start: {}", e);
+            }
         }
 
+        // The caller decides between retrying and giving up
+        play_result?;
+
         {
             let mut guard = state.lock().await;
             guard.update_player_status(PlaybackStatus::Playing);
@@ -643,7 +726,7 @@ impl ServerClient {
     pub async fn resume_playback(
         self: Arc<Self>,
         state: Arc<Mutex<AppState>>,
-        audio: Arc<Mutex<AudioManager>>,
+        audio: AudioHandle,
         app_handle: AppHandle,
     ) -> Result<()> {
         let backend_url = {
@@ -653,9 +736,87 @@ impl ServerClient {
                 .ok_or_else(|| anyhow!("Server URL not configured"))?
         };
 
-        self.clone()
-            .sync_play_now(state, audio, app_handle, backend_url)
+        let session = self.begin_play_session();
+        if self
+            .resume_in_place(session, &state, &audio, &app_handle)
             .await
+        {
+            return Ok(());
+        }
+
+        let track_id = current_track_id(&state).await;
+        let result = self
+            .clone()
+            .sync_play_now(
+                session,
+                state.clone(),
+                audio,
+                app_handle.clone(),
+                backend_url,
+            )
+            .await;
+
+        // The user asked for this one, so it is not retried; the command reports the failure
+        if let Err(e) = &result {
+            if !self.is_superseded(session) {
+                playback_error::report(&app_handle, &PlaybackError::new(e, track_id, 1, None));
+                self.abort_start(session, &state, &app_handle).await;
+            }
+        }
+        result
+    }
+
+    /// Continue a soft-paused stream without reloading it. Returns false when there was
+    /// nothing warm to continue and playback has to start over.
+    async fn resume_in_place(
+        &self,
+        session: u64,
+        state: &Mutex<AppState>,
+        audio: &AudioHandle,
+        app_handle: &AppHandle,
+    ) -> bool {
+        let (track_id, position) = {
+            let guard = state.lock().await;
+            let Some(track_id) = guard.current_track.as_ref().map(|t| t.youtube_id.clone()) else {
+                return false;
+            };
+            // The server kept playing during the pause, so pick up where it is now
+            (track_id, guard.server_position())
+        };
+
+        if !audio.resume(&track_id, position).await {
+            return false;
+        }
+
+        let mut guard = state.lock().await;
+        if self.is_superseded(session) {
+            return true;
+        }
+        guard.update_player_status(PlaybackStatus::Playing);
+        guard.set_user_paused(false);
+        let duration = guard.duration();
+        guard.update_sync(position, Some(duration));
+        let snapshot = guard.snapshot();
+        drop(guard);
+        let _ = app_handle.emit("player_state_updated", snapshot);
+
+        #[cfg(target_os = "linux")]
+        if let Some(mpris) = app_handle.try_state::<MprisManager>() {
+            if let Err(e) = mpris.update_playback_status(PlaybackStatus::Playing).await {
+                println!("Failed to update MPRIS playing status: {}", e);
+            }
+            if let Err(e) = mpris.update_position(position).await {
+                println!("Failed to update MPRIS position: {}", e);
+            }
+        }
+
+        true
+    }
+
+    /// Resolve a stream URL for a specific track. `/api/music/stream` is unauthenticated and
+    /// only ever serves the current track, so every track goes through a secure stream token.
+    pub async fn track_stream_url(&self, backend_url: &str, youtube_id: &str) -> Result<String> {
+        request_secure_stream_url(&self.client, backend_url, youtube_id).await
     }
 
     // Removed fetch_and_apply_status - SSE provides complete initial state and real-time updates
@@ -693,4 +854,18 @@ fn current_time_millis() -> f64 {
         .unwrap_or(0.0)
 }
 
+fn start_retry_delay(attempt: u32) -> Duration {
+    let delay_ms = START_RETRY_BASE_DELAY_MS.saturating_mul(1 << (attempt - 1).min(16));
+    Duration::from_millis(delay_ms.min(START_RETRY_MAX_DELAY_MS))
+}
+
+async fn current_track_id(state: &Mutex<AppState>) -> Option<String> {
+    state
+        .lock()
+        .await
+        .current_track
+        .as_ref()
+        .map(|track| track.youtube_id.clone())
+}
+
 // Removed tests for MinimalStatus - SSE provides complete data
@@ -0,0 +1,300 @@
+use crate::state::Track;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+pub const SLEEP_TIMER_INTERVAL: Duration = Duration::from_millis(250);
+
+// Volume ramps down over the last stretch before pausing
+const SLEEP_FADE_SECS: f64 = 30.0;
+// How close to its end the last counted track counts as finished; one poll interval would
+// do, with some slack for position updates that arrive late
+const END_OF_TRACK_MARGIN_SECS: f64 = 0.5;
+const MAX_MINUTES: u32 = 24 * 60;
+const MAX_TRACKS: u32 = 100;
+
+enum Deadline {
+    At(Instant),
+    /// Pause once `tracks_left` tracks, counting the one playing, have ended
+    EndOfTrack {
+        track_id: Option<String>,
+        tracks_left: u32,
+    },
+}
+
+/// Countdown shown in `PlayerSnapshot`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SleepTimerStatus {
+    /// Seconds until playback pauses; `None` when a track along the way has no known duration
+    pub remaining_secs: Option<f64>,
+    /// Tracks left to play, counting the current one, for end-of-track timers
+    pub tracks_left: Option<u32>,
+}
+
+pub enum SleepTimerAction {
+    /// Keep playing at this share of the user's volume
+    Running { gain: f32 },
+    /// Time is up: pause playback
+    Expired,
+}
+
+pub struct SleepTimer {
+    deadline: Deadline,
+}
+
+impl SleepTimer {
+    pub fn after_minutes(minutes: u32) -> Result<Self> {
+        if minutes == 0 || minutes > MAX_MINUTES {
+            return Err(anyhow!(
+                "Sleep timer must be between 1 and {} minutes",
+                MAX_MINUTES
+            ));
+        }
+
+        Ok(Self {
+            deadline: Deadline::At(Instant::now() + minutes_to_duration(minutes)),
+        })
+    }
+
+    /// Pause at the end of the current track (`tracks` = 1) or of the Nth track from now
+    pub fn after_tracks(tracks: u32, current_track: Option<&Track>) -> Result<Self> {
+        if tracks == 0 || tracks > MAX_TRACKS {
+            return Err(anyhow!(
+                "Sleep timer must be between 1 and {} tracks",
+                MAX_TRACKS
+            ));
+        }
+
+        Ok(Self {
+            deadline: Deadline::EndOfTrack {
+                track_id: current_track.map(|track| track.youtube_id.clone()),
+                tracks_left: tracks,
+            },
+        })
+    }
+
+    /// Push the deadline back by `amount` minutes, or `amount` tracks for end-of-track timers
+    pub fn extend(&mut self, amount: u32) -> Result<()> {
+        match &mut self.deadline {
+            Deadline::At(at) => {
+                let remaining = at.saturating_duration_since(Instant::now());
+                let extended = remaining + minutes_to_duration(amount);
+                if extended > minutes_to_duration(MAX_MINUTES) {
+                    return Err(anyhow!("Sleep timer is limited to {} minutes", MAX_MINUTES));
+                }
+                *at += minutes_to_duration(amount);
+            }
+            Deadline::EndOfTrack { tracks_left, .. } => {
+                let extended = tracks_left.saturating_add(amount);
+                if extended > MAX_TRACKS {
+                    return Err(anyhow!("Sleep timer is limited to {} tracks", MAX_TRACKS));
+                }
+                *tracks_left = extended;
+            }
+        }
+        Ok(())
+    }
+
+    /// Advance the timer against the current playback state
+    pub fn poll(
+        &mut self,
+        current_track: Option<&Track>,
+        queue: &[Track],
+        position: f64,
+        duration: f64,
+    ) -> SleepTimerAction {
+        if let Deadline::EndOfTrack {
+            track_id,
+            tracks_left,
+        } = &mut self.deadline
+        {
+            let current_id = current_track.map(|track| track.youtube_id.as_str());
+            if track_id.is_none() {
+                // Set while nothing was playing; start counting from the first track
+                *track_id = current_id.map(str::to_string);
+            } else if track_id.as_deref() != current_id {
+                if *tracks_left <= 1 {
+                    return SleepTimerAction::Expired;
+                }
+                *tracks_left -= 1;
+                *track_id = current_id.map(str::to_string);
+            }
+
+            // Stop as the last track ends rather than once the next one has started
+            let last_track_ending = *tracks_left <= 1
+                && track_id.is_some()
+                && duration > 0.0
+                && position >= duration - END_OF_TRACK_MARGIN_SECS;
+            if last_track_ending {
+                return SleepTimerAction::Expired;
+            }
+        }
+
+        match self.remaining(queue, position, duration) {
+            Some(remaining) if remaining <= 0.0 && matches!(self.deadline, Deadline::At(_)) => {
+                SleepTimerAction::Expired
+            }
+            Some(remaining) => SleepTimerAction::Running {
+                gain: (remaining / SLEEP_FADE_SECS).clamp(0.0, 1.0) as f32,
+            },
+            None => SleepTimerAction::Running { gain: 1.0 },
+        }
+    }
+
+    pub fn status(&self, queue: &[Track], position: f64, duration: f64) -> SleepTimerStatus {
+        SleepTimerStatus {
+            remaining_secs: self.remaining(queue, position, duration),
+            tracks_left: match self.deadline {
+                Deadline::At(_) => None,
+                Deadline::EndOfTrack { tracks_left, .. } => Some(tracks_left),
+            },
+        }
+    }
+
+    /// Seconds left; end-of-track timers estimate it from the track durations
+    fn remaining(&self, queue: &[Track], position: f64, duration: f64) -> Option<f64> {
+        match &self.deadline {
+            Deadline::At(at) => Some(at.saturating_duration_since(Instant::now()).as_secs_f64()),
+            Deadline::EndOfTrack {
+                track_id,
+                tracks_left,
+            } => {
+                if track_id.is_none() || duration <= 0.0 {
+                    return None;
+                }
+
+                let upcoming = (*tracks_left as usize).saturating_sub(1);
+                if queue.len() < upcoming {
+                    return None;
+                }
+                queue[..upcoming]
+                    .iter()
+                    .map(|track| (track.duration > 0.0).then_some(track.duration))
+                    .sum::<Option<f64>>()
+                    .map(|rest| (duration - position).max(0.0) + rest)
+            }
+        }
+    }
+}
+
+fn minutes_to_duration(minutes: u32) -> Duration {
+    Duration::from_secs(minutes as u64 * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: &str, duration: f64) -> Track {
+        Track {
+            youtube_id: id.to_string(),
+            title: id.to_string(),
+            duration,
+            thumbnail: None,
+            requested_by: None,
+            channel_title: None,
+            requested_at: None,
+            is_autoplay: None,
+        }
+    }
+
+    fn gain(action: SleepTimerAction) -> Option<f32> {
+        match action {
+            SleepTimerAction::Running { gain } => Some(gain),
+            SleepTimerAction::Expired => None,
+        }
+    }
+
+    #[test]
+    fn minute_timer_fades_over_the_last_stretch() {
+        let mut timer = SleepTimer::after_minutes(10).unwrap();
+        assert_eq!(gain(timer.poll(None, &[], 0.0, 0.0)), Some(1.0));
+
+        timer.deadline = Deadline::At(Instant::now() + Duration::from_secs(15));
+        let faded = gain(timer.poll(None, &[], 0.0, 0.0)).unwrap();
+        assert!(faded > 0.45 && faded <= 0.5, "gain {}", faded);
+
+        timer.deadline = Deadline::At(Instant::now());
+        assert!(gain(timer.poll(None, &[], 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn track_timer_expires_as_the_current_track_ends() {
+        let current = track("a", 200.0);
+        let queue = [track("b", 180.0)];
+        let mut timer = SleepTimer::after_tracks(1, Some(&current)).unwrap();
+
+        assert_eq!(
+            gain(timer.poll(Some(&current), &queue, 100.0, 200.0)),
+            Some(1.0)
+        );
+        let faded = gain(timer.poll(Some(&current), &queue, 185.0, 200.0)).unwrap();
+        assert!((faded - 0.5).abs() < 1e-6, "gain {}", faded);
+        assert!(gain(timer.poll(Some(&current), &queue, 199.8, 200.0)).is_none());
+    }
+
+    #[test]
+    fn track_timer_counts_tracks_and_expires_when_the_last_one_is_left() {
+        let tracks = [track("a", 200.0), track("b", 180.0), track("c", 240.0)];
+        let mut timer = SleepTimer::after_tracks(2, Some(&tracks[0])).unwrap();
+
+        // The end of the first track only moves the count on
+        assert!(gain(timer.poll(Some(&tracks[0]), &tracks[1..], 199.9, 200.0)).is_some());
+        assert!(gain(timer.poll(Some(&tracks[1]), &tracks[2..], 0.0, 180.0)).is_some());
+        assert_eq!(timer.status(&tracks[2..], 0.0, 180.0).tracks_left, Some(1));
+
+        // Skipping past the last counted track stops right away
+        assert!(gain(timer.poll(Some(&tracks[2]), &[], 0.0, 240.0)).is_none());
+    }
+
+    #[test]
+    fn track_timer_set_while_idle_starts_with_the_first_track() {
+        let a = track("a", 200.0);
+        let mut timer = SleepTimer::after_tracks(1, None).unwrap();
+        assert_eq!(timer.status(&[], 0.0, 0.0).remaining_secs, None);
+
+        assert!(gain(timer.poll(Some(&a), &[], 0.0, 200.0)).is_some());
+        assert!(gain(timer.poll(Some(&a), &[], 199.6, 200.0)).is_none());
+    }
+
+    #[test]
+    fn remaining_adds_up_the_counted_tracks() {
+        let a = track("a", 200.0);
+        let queue = [track("b", 180.0), track("c", 240.0)];
+        let timer = SleepTimer::after_tracks(3, Some(&a)).unwrap();
+        assert_eq!(
+            timer.status(&queue, 50.0, 200.0).remaining_secs,
+            Some(570.0)
+        );
+
+        // Unknown as soon as one of them has no duration, or the queue runs short
+        let unknown = [track("b", 0.0), track("c", 240.0)];
+        assert_eq!(timer.status(&unknown, 50.0, 200.0).remaining_secs, None);
+        assert_eq!(timer.status(&queue[..1], 50.0, 200.0).remaining_secs, None);
+    }
+
+    #[test]
+    fn extend_adds_time_or_tracks_within_the_limits() {
+        let mut timer = SleepTimer::after_minutes(10).unwrap();
+        timer.extend(5).unwrap();
+        let remaining = timer.status(&[], 0.0, 0.0).remaining_secs.unwrap();
+        assert!(remaining > 14.0 * 60.0 && remaining <= 15.0 * 60.0);
+        assert!(timer.extend(MAX_MINUTES).is_err());
+
+        let a = track("a", 200.0);
+        let mut timer = SleepTimer::after_tracks(1, Some(&a)).unwrap();
+        timer.extend(2).unwrap();
+        assert_eq!(timer.status(&[], 0.0, 200.0).tracks_left, Some(3));
+        assert!(timer.extend(MAX_TRACKS).is_err());
+        assert_eq!(timer.status(&[], 0.0, 200.0).tracks_left, Some(3));
+    }
+
+    #[test]
+    fn limits_are_checked_up_front() {
+        assert!(SleepTimer::after_minutes(0).is_err());
+        assert!(SleepTimer::after_minutes(MAX_MINUTES + 1).is_err());
+        assert!(SleepTimer::after_tracks(0, None).is_err());
+        assert!(SleepTimer::after_tracks(MAX_TRACKS + 1, None).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use rodio::Source;
+use serde::Serialize;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const SPECTRUM_INTERVAL: Duration = Duration::from_millis(33); // ~30 frames per second
+
+const FFT_SIZE: usize = 2048;
+const BAND_COUNT: usize = 64;
+const MIN_FREQUENCY: f32 = 30.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+const FLOOR_DB: f32 = -70.0; // Anything quieter shows as an empty band
+const DECAY: f32 = 0.85; // Share of its level a falling band keeps per frame
+const SILENT_LEVEL: f32 = 0.001; // Below this on every band, frames stop being emitted
+const TAP_BLOCK_FRAMES: usize = 256; // Collected on the audio thread before handing over
+
+/// Payload of the `audio_spectrum` event
+#[derive(Debug, Clone, Serialize)]
+pub struct SpectrumFrame {
+    /// Log-spaced bands from low to high, each 0.0 (silent) to 1.0 (full scale)
+    pub bands: Vec<f32>,
+}
+
+/// Most recent mono samples, oldest overwritten first
+struct SampleHistory {
+    samples: Vec<f32>,
+    next: usize,
+}
+
+/// Hand-over point between the taps in the audio chain and the analysis.
+///
+/// Taps only mix down to mono and copy blocks in, skipping a block rather than waiting when
+/// the analysis holds the lock; the FFT itself runs in `SpectrumAnalysis`, off the audio
+/// thread.
+pub struct SpectrumAnalyzer {
+    enabled: AtomicBool,
+    sample_rate: AtomicU32,
+    // Bumped for every block handed over, so the analysis can tell when audio stopped
+    generation: AtomicU64,
+    history: Mutex<SampleHistory>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            sample_rate: AtomicU32::new(0),
+            generation: AtomicU64::new(0),
+            history: Mutex::new(SampleHistory {
+                samples: vec![0.0; FFT_SIZE],
+                next: 0,
+            }),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Wrap `source` in a tap that feeds this analyzer while it is enabled
+    pub fn tap<S>(self: &Arc<Self>, source: S) -> SpectrumTap<S>
+    where
+        S: Source<Item = i16>,
+    {
+        SpectrumTap {
+            inner: source,
+            analyzer: self.clone(),
+            block: Vec::with_capacity(TAP_BLOCK_FRAMES),
+            frame_sum: 0.0,
+            channel: 0,
+            active: false,
+        }
+    }
+
+    fn push_block(&self, block: &[f32], sample_rate: u32) {
+        let Ok(mut history) = self.history.try_lock() else {
+            return;
+        };
+
+        for &sample in block {
+            let next = history.next;
+            history.samples[next] = sample;
+            history.next = (next + 1) % FFT_SIZE;
+        }
+        drop(history);
+
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+pub struct SpectrumTap<S> {
+    inner: S,
+    analyzer: Arc<SpectrumAnalyzer>,
+    block: Vec<f32>,
+    frame_sum: f32,
+    channel: usize,
+    // Whether the current frame is being collected; checked once per frame
+    active: bool,
+}
+
+impl<S> Iterator for SpectrumTap<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.inner.next()?;
+        let channels = self.inner.channels().max(1) as usize;
+
+        if self.channel == 0 {
+            self.active = self.analyzer.is_enabled();
+        }
+        if self.active {
+            self.frame_sum += sample as f32;
+        }
+
+        self.channel += 1;
+        if self.channel >= channels {
+            self.channel = 0;
+            if self.active {
+                self.block
+                    .push(self.frame_sum / (channels as f32 * i16::MAX as f32));
+                if self.block.len() >= TAP_BLOCK_FRAMES {
+                    self.analyzer
+                        .push_block(&self.block, self.inner.sample_rate());
+                    self.block.clear();
+                }
+            }
+            self.frame_sum = 0.0;
+        }
+
+        Some(sample)
+    }
+}
+
+impl<S> Source for SpectrumTap<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// FFT and band state for turning the analyzer's samples into spectrum frames
+pub struct SpectrumAnalysis {
+    analyzer: Arc<SpectrumAnalyzer>,
+    window: Vec<f32>,
+    twiddles: Vec<(f32, f32)>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    levels: Vec<f32>,
+    last_generation: u64,
+}
+
+impl SpectrumAnalysis {
+    pub fn new(analyzer: Arc<SpectrumAnalyzer>) -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        let twiddles = (0..FFT_SIZE / 2)
+            .map(|k| {
+                let angle = -2.0 * PI * k as f32 / FFT_SIZE as f32;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+
+        Self {
+            analyzer,
+            window,
+            twiddles,
+            re: vec![0.0; FFT_SIZE],
+            im: vec![0.0; FFT_SIZE],
+            levels: vec![0.0; BAND_COUNT],
+            last_generation: 0,
+        }
+    }
+
+    /// The frame to show next, or `None` while disabled or once the bands have settled
+    /// after audio stopped
+    pub fn next_frame(&mut self) -> Option<SpectrumFrame> {
+        if !self.analyzer.is_enabled() {
+            self.levels.fill(0.0);
+            return None;
+        }
+
+        let generation = self.analyzer.generation.load(Ordering::Acquire);
+        if generation == self.last_generation {
+            // Nothing new reached the output; let the bands fall back to silence
+            if self.levels.iter().all(|&level| level < SILENT_LEVEL) {
+                return None;
+            }
+            self.levels.iter_mut().for_each(|level| *level *= DECAY);
+        } else {
+            self.last_generation = generation;
+            self.analyze();
+        }
+
+        Some(SpectrumFrame {
+            bands: self.levels.clone(),
+        })
+    }
+
+    fn analyze(&mut self) {
+        {
+            let history = self
+                .analyzer
+                .history
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            for i in 0..FFT_SIZE {
+                let sample = history.samples[(history.next + i) % FFT_SIZE];
+                self.re[i] = sample * self.window[i];
+            }
+        }
+        self.im.fill(0.0);
+        self.fft();
+
+        let sample_rate = self.analyzer.sample_rate.load(Ordering::Relaxed).max(1) as f32;
+        let bin_width = sample_rate / FFT_SIZE as f32;
+        let max_frequency = MAX_FREQUENCY.min(sample_rate / 2.0);
+        let ratio = max_frequency / MIN_FREQUENCY;
+        // Full-scale sine after the Hann window's 0.5 coherent gain
+        let full_scale = FFT_SIZE as f32 / 4.0;
+
+        for band in 0..BAND_COUNT {
+            let low = MIN_FREQUENCY * ratio.powf(band as f32 / BAND_COUNT as f32);
+            let high = MIN_FREQUENCY * ratio.powf((band + 1) as f32 / BAND_COUNT as f32);
+            let first = ((low / bin_width) as usize).max(1);
+            // Low bands are narrower than a bin; they still get the bin they fall into
+            let last = ((high / bin_width) as usize).clamp(first, FFT_SIZE / 2 - 1);
+
+            let magnitude = (first..=last)
+                .map(|bin| (self.re[bin] * self.re[bin] + self.im[bin] * self.im[bin]).sqrt())
+                .fold(0.0, f32::max);
+            let db = 20.0 * (magnitude / full_scale).max(1e-9).log10();
+            let target = ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0);
+
+            let level = &mut self.levels[band];
+            *level = target.max(*level * DECAY);
+        }
+    }
+
+    /// In-place iterative radix-2 FFT over `re`/`im`
+    fn fft(&mut self) {
+        let bits = FFT_SIZE.trailing_zeros();
+        for i in 0..FFT_SIZE {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if j > i {
+                self.re.swap(i, j);
+                self.im.swap(i, j);
+            }
+        }
+
+        let mut size = 2;
+        while size <= FFT_SIZE {
+            let half = size / 2;
+            let stride = FFT_SIZE / size;
+            for start in (0..FFT_SIZE).step_by(size) {
+                for k in 0..half {
+                    let (cos, sin) = self.twiddles[k * stride];
+                    let (a, b) = (start + k, start + k + half);
+                    let re = self.re[b] * cos - self.im[b] * sin;
+                    let im = self.re[b] * sin + self.im[b] * cos;
+                    self.re[b] = self.re[a] - re;
+                    self.im[b] = self.im[a] - im;
+                    self.re[a] += re;
+                    self.im[a] += im;
+                }
+            }
+            size *= 2;
+        }
+    }
+}
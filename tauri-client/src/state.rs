@@ -1,4 +1,7 @@
+use crate::clock::PlaybackClock;
+use crate::sleep_timer::{SleepTimer, SleepTimerAction, SleepTimerStatus};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -112,7 +115,6 @@ impl PlaybackStatus {
 pub struct PlayerSnapshot {
     pub connected: bool,
     pub server_url: Option<String>,
-    pub stream_url: Option<String>,
     pub player_status: PlaybackStatus,
     pub server_status: PlaybackStatus,
     pub is_playing: bool,
@@ -123,23 +125,25 @@ pub struct PlayerSnapshot {
     pub current_track: Option<TrackView>,
     pub queue: Vec<TrackView>,
     pub last_sync_timestamp: Option<u128>,
+    pub sleep_timer: Option<SleepTimerStatus>,
 }
 
 pub struct AppState {
     server_url: Option<String>,
     backend_url: Option<String>,
-    stream_url: Option<String>,
     pub current_track: Option<Track>,
     pub queue: Vec<Track>,
     pub server_status: PlaybackStatus,
     pub player_status: PlaybackStatus,
     pub volume: f32,
     pub user_paused: bool,
+    pub sleep_timer: Option<SleepTimer>,
     synced_position: f64,
     track_duration: f64,
     last_sync_instant: Option<Instant>,
     last_sync_wallclock: Option<SystemTime>,
     last_track_id: Option<String>,
+    playback_clock: Option<Arc<PlaybackClock>>,
 }
 
 impl AppState {
@@ -150,28 +154,30 @@ impl AppState {
     pub fn new_with_volume(volume: f32) -> Self {
         Self {
             server_url: None,
-            stream_url: None,
             current_track: None,
             queue: Vec::new(),
             server_status: PlaybackStatus::Stopped,
             player_status: PlaybackStatus::Stopped,
             volume: volume.clamp(0.0, 1.0),
             user_paused: false,
+            sleep_timer: None,
             synced_position: 0.0,
             track_duration: 0.0,
             last_sync_instant: None,
             last_sync_wallclock: None,
             last_track_id: None,
             backend_url: None,
+            playback_clock: None,
         }
     }
 
-    pub fn backend_url(&self) -> Option<String> {
-        self.backend_url.clone()
+    /// Use the audio pipeline's own clock for the position while local audio is playing
+    pub fn attach_playback_clock(&mut self, clock: Arc<PlaybackClock>) {
+        self.playback_clock = Some(clock);
     }
 
-    pub fn stream_url(&self) -> Option<String> {
-        self.stream_url.clone()
+    pub fn backend_url(&self) -> Option<String> {
+        self.backend_url.clone()
     }
 
     pub fn set_server_url(&mut self, url: String) {
@@ -195,7 +201,6 @@ impl AppState {
         root = root.trim_end_matches('/').to_string();
         let backend_clean = backend_base.trim_end_matches('/').to_string();
 
-        self.stream_url = Some(format!("{}/api/music/stream", backend_clean));
         self.server_url = Some(root);
         self.backend_url = Some(backend_clean);
     }
@@ -239,6 +244,18 @@ impl AppState {
         self.queue = queue;
     }
 
+    /// Advance the sleep timer, if one is set
+    pub fn poll_sleep_timer(&mut self) -> Option<SleepTimerAction> {
+        let position = self.computed_position();
+        let timer = self.sleep_timer.as_mut()?;
+        Some(timer.poll(
+            self.current_track.as_ref(),
+            &self.queue,
+            position,
+            self.track_duration,
+        ))
+    }
+
     pub fn update_sync(&mut self, position: f64, duration: Option<f64>) {
         self.synced_position = position.max(0.0);
         if let Some(dur) = duration {
@@ -269,6 +286,10 @@ impl AppState {
             return self.synced_position.min(self.track_duration);
         }
 
+        if let Some(position) = self.local_playback_position() {
+            return position.min(self.track_duration);
+        }
+
         if let Some(last_sync) = self.last_sync_instant {
             let elapsed = last_sync.elapsed().as_secs_f64();
             let position = self.synced_position + elapsed;
@@ -278,6 +299,22 @@ impl AppState {
         self.synced_position.min(self.track_duration)
     }
 
+    /// Position on the server's timeline: the last synced position moved on by the time
+    /// since it arrived, regardless of what the local player is doing
+    pub fn server_position(&self) -> f64 {
+        let elapsed = self
+            .last_sync_instant
+            .map_or(0.0, |last_sync| last_sync.elapsed().as_secs_f64());
+        (self.synced_position + elapsed).min(self.track_duration)
+    }
+
+    fn local_playback_position(&self) -> Option<f64> {
+        let track = self.current_track.as_ref()?;
+        self.playback_clock
+            .as_ref()?
+            .played_position(&track.youtube_id)
+    }
+
     pub fn duration(&self) -> f64 {
         self.track_duration
     }
@@ -304,7 +341,6 @@ impl AppState {
         PlayerSnapshot {
             connected: self.server_url.is_some(),
             server_url: server_url.clone(),
-            stream_url: self.stream_url.clone(),
             player_status: self.player_status,
             server_status: self.server_status,
             is_playing: self.player_status == PlaybackStatus::Playing,
@@ -315,6 +351,9 @@ impl AppState {
             current_track,
             queue,
             last_sync_timestamp: self.last_sync_timestamp(),
+            sleep_timer: self.sleep_timer.as_ref().map(|timer| {
+                timer.status(&self.queue, self.computed_position(), self.track_duration)
+            }),
         }
     }
 }
@@ -0,0 +1,210 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+// Waits shorter than this are absorbed by the output buffer and not worth reporting
+const UNDERRUN_WAIT: Duration = Duration::from_millis(20);
+// Audio buffered ahead at which the stream counts as fully healthy
+const HEALTHY_BUFFER_SECS: f64 = 10.0;
+
+/// Download-side counters of the network stream currently feeding playback, shared between
+/// the HTTP reader, its fetch task and whoever reports buffer metrics.
+pub struct StreamStats {
+    streaming: AtomicBool,
+    // Byte offset the current HTTP range started at
+    range_start: AtomicU64,
+    // Byte offset just past the last byte received over the network
+    downloaded_end: AtomicU64,
+    read_position: AtomicU64,
+    // 0 while unknown
+    total_bytes: AtomicU64,
+    duration_ms: AtomicU64,
+    complete: AtomicBool,
+    waiting: AtomicBool,
+    underruns: AtomicU32,
+    reconnects: AtomicU32,
+    throughput: Mutex<ThroughputWindow>,
+}
+
+struct ThroughputWindow {
+    started: Instant,
+    bytes: u64,
+    bytes_per_sec: f64,
+}
+
+impl StreamStats {
+    pub fn new() -> Self {
+        Self {
+            streaming: AtomicBool::new(false),
+            range_start: AtomicU64::new(0),
+            downloaded_end: AtomicU64::new(0),
+            read_position: AtomicU64::new(0),
+            total_bytes: AtomicU64::new(0),
+            duration_ms: AtomicU64::new(0),
+            complete: AtomicBool::new(false),
+            waiting: AtomicBool::new(false),
+            underruns: AtomicU32::new(0),
+            reconnects: AtomicU32::new(0),
+            throughput: Mutex::new(ThroughputWindow {
+                started: Instant::now(),
+                bytes: 0,
+                bytes_per_sec: 0.0,
+            }),
+        }
+    }
+
+    /// Start counting for a new track. `streaming` is false when playback comes from local data.
+    pub fn reset(&self, streaming: bool) {
+        self.streaming.store(streaming, Ordering::Relaxed);
+        self.range_start.store(0, Ordering::Relaxed);
+        self.downloaded_end.store(0, Ordering::Relaxed);
+        self.read_position.store(0, Ordering::Relaxed);
+        self.total_bytes.store(0, Ordering::Relaxed);
+        self.duration_ms.store(0, Ordering::Relaxed);
+        self.complete.store(false, Ordering::Relaxed);
+        self.waiting.store(false, Ordering::Relaxed);
+        self.underruns.store(0, Ordering::Relaxed);
+        self.reconnects.store(0, Ordering::Relaxed);
+        if let Ok(mut window) = self.throughput.lock() {
+            *window = ThroughputWindow {
+                started: Instant::now(),
+                bytes: 0,
+                bytes_per_sec: 0.0,
+            };
+        }
+    }
+
+    /// A new HTTP range was opened at `offset`; everything buffered before it is gone
+    pub fn begin_range(&self, offset: u64, total_bytes: Option<u64>) {
+        self.range_start.store(offset, Ordering::Relaxed);
+        self.downloaded_end.store(offset, Ordering::Relaxed);
+        self.read_position.store(offset, Ordering::Relaxed);
+        self.total_bytes
+            .store(total_bytes.unwrap_or(0), Ordering::Relaxed);
+        self.complete.store(false, Ordering::Relaxed);
+    }
+
+    pub fn set_duration(&self, duration: Option<Duration>) {
+        let millis = duration.map(|d| d.as_millis() as u64).unwrap_or(0);
+        self.duration_ms.store(millis, Ordering::Relaxed);
+    }
+
+    pub fn record_download(&self, bytes: usize) {
+        self.downloaded_end
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+
+        if let Ok(mut window) = self.throughput.lock() {
+            window.bytes += bytes as u64;
+            let elapsed = window.started.elapsed();
+            if elapsed >= THROUGHPUT_WINDOW {
+                window.bytes_per_sec = window.bytes as f64 / elapsed.as_secs_f64();
+                window.bytes = 0;
+                window.started = Instant::now();
+            }
+        }
+    }
+
+    pub fn mark_complete(&self) {
+        self.complete.store(true, Ordering::Relaxed);
+    }
+
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_read_position(&self, position: u64) {
+        self.read_position.store(position, Ordering::Relaxed);
+    }
+
+    pub fn set_waiting(&self, waiting: bool) {
+        self.waiting.store(waiting, Ordering::Relaxed);
+    }
+
+    /// The reader blocked for `waited` on the network. Only counts once playback of the range
+    /// has begun; waiting for the first bytes of a fresh range is not an underrun. Returns
+    /// whether the wait was counted as one.
+    pub fn record_wait(&self, waited: Duration) -> bool {
+        let started =
+            self.read_position.load(Ordering::Relaxed) > self.range_start.load(Ordering::Relaxed);
+        if started && waited >= UNDERRUN_WAIT {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.streaming.load(Ordering::Relaxed)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete.load(Ordering::Relaxed)
+    }
+
+    /// True while playback is blocked waiting for the network
+    pub fn is_stalled(&self) -> bool {
+        self.is_streaming() && self.waiting.load(Ordering::Relaxed)
+    }
+
+    pub fn buffered_bytes(&self) -> u64 {
+        self.downloaded_end
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.read_position.load(Ordering::Relaxed))
+    }
+
+    /// Average bytes per second of audio, once the stream's size and length are known
+    pub fn byte_rate(&self) -> Option<f64> {
+        let total_bytes = self.total_bytes.load(Ordering::Relaxed);
+        let duration_ms = self.duration_ms.load(Ordering::Relaxed);
+        if total_bytes == 0 || duration_ms == 0 {
+            return None;
+        }
+
+        Some(total_bytes as f64 / (duration_ms as f64 / 1000.0))
+    }
+
+    /// Buffered bytes converted at the stream's average bitrate
+    pub fn buffered_seconds(&self) -> Option<f64> {
+        self.byte_rate()
+            .map(|byte_rate| self.buffered_bytes() as f64 / byte_rate)
+    }
+
+    pub fn underruns(&self) -> u32 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Download rate over the last full window; decays while no data arrives
+    pub fn throughput(&self) -> f64 {
+        let Ok(window) = self.throughput.lock() else {
+            return 0.0;
+        };
+
+        let elapsed = window.started.elapsed();
+        if elapsed > THROUGHPUT_WINDOW * 2 {
+            window.bytes as f64 / elapsed.as_secs_f64()
+        } else {
+            window.bytes_per_sec
+        }
+    }
+
+    /// 0.0 (playback waiting on the network) to 1.0 (comfortably buffered or fully downloaded)
+    pub fn health(&self) -> f64 {
+        if !self.is_streaming() || self.is_complete() {
+            return 1.0;
+        }
+        if self.is_stalled() {
+            return 0.0;
+        }
+
+        match self.buffered_seconds() {
+            Some(seconds) => (seconds / HEALTHY_BUFFER_SECS).min(1.0),
+            None if self.buffered_bytes() > 0 => 1.0,
+            None => 0.0,
+        }
+    }
+}
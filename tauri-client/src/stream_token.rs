@@ -0,0 +1,91 @@
+use crate::http_stream::HttpStatusError;
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SECURE_STREAM_PATH: &str = "/api/music/secure-stream/";
+// The backend drops stream sessions after an hour
+const TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+// A token this close to the end of its lifetime is renewed before the next request
+const REFRESH_MARGIN: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Deserialize)]
+struct SecureTokenResponse {
+    token: String,
+}
+
+/// Ask the backend for a stream token and return the `/secure-stream/:token` URL for it
+pub async fn request_secure_stream_url(
+    client: &Client,
+    backend_url: &str,
+    youtube_id: &str,
+) -> Result<String> {
+    let token_url = format!("{}/api/music/secure-token/{}", backend_url, youtube_id);
+
+    let response = client
+        .get(&token_url)
+        .send()
+        .await
+        .context("Failed to request stream token")?;
+
+    if !response.status().is_success() {
+        return Err(HttpStatusError::new("Stream token request", response.status()).into());
+    }
+
+    let body: SecureTokenResponse = response
+        .json()
+        .await
+        .context("Failed to parse stream token response")?;
+
+    Ok(format!(
+        "{}{}{}",
+        backend_url, SECURE_STREAM_PATH, body.token
+    ))
+}
+
+/// Secure stream URL of one track. Every request reuses the same token, so the backend keeps
+/// a single stream session; a new one is only requested as the old one nears its end or
+/// after the backend rejected it.
+pub struct StreamToken {
+    client: Client,
+    backend_url: String,
+    youtube_id: String,
+    issued_at: Mutex<Instant>,
+}
+
+impl StreamToken {
+    /// Token handling for `stream_url` if it points at the secure endpoint. The issue time of
+    /// a URL handed in from elsewhere is unknown, so it counts as fresh until the backend
+    /// rejects it.
+    pub fn from_url(client: Client, stream_url: &str, youtube_id: &str) -> Option<Self> {
+        let (backend_url, _) = stream_url.split_once(SECURE_STREAM_PATH)?;
+
+        Some(Self {
+            client,
+            backend_url: backend_url.to_string(),
+            youtube_id: youtube_id.to_string(),
+            issued_at: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Whether the token is within `REFRESH_MARGIN` of running out
+    pub fn needs_refresh(&self) -> bool {
+        self.issued_at
+            .lock()
+            .map(|issued_at| issued_at.elapsed() >= TOKEN_LIFETIME - REFRESH_MARGIN)
+            .unwrap_or(true)
+    }
+
+    /// Request a new token and return its stream URL
+    pub async fn refresh(&self) -> Result<String> {
+        let url =
+            request_secure_stream_url(&self.client, &self.backend_url, &self.youtube_id).await?;
+        if let Ok(mut issued_at) = self.issued_at.lock() {
+            *issued_at = Instant::now();
+        }
+        println!("Audio: Renewed stream token for {}", self.youtube_id);
+        Ok(url)
+    }
+}